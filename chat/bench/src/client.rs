@@ -0,0 +1,255 @@
+use chat::authentication_service_client::AuthenticationServiceClient;
+use chat::chat_service_client::ChatServiceClient;
+use proto::chat;
+use server_common::{keepalive, uds};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Status, Streaming};
+
+/// Prefix of every message sent by the benchmark, followed by the send time.
+const MESSAGE_PREFIX: &str = "bench:";
+
+/// Number of times a login is retried while the server's authentication rate limit is exhausted.
+const LOGIN_ATTEMPTS: usize = 60;
+
+/// Address of the chat server.
+pub struct Target {
+    pub server: String,
+    pub unix_socket: Option<PathBuf>,
+}
+
+impl Target {
+    async fn connect(&self) -> Result<Channel, Box<dyn std::error::Error>> {
+        let channel = match &self.unix_socket {
+            Some(path) => uds::connect(path).await?,
+            None => {
+                keepalive::configure(Endpoint::from_shared(self.server.clone())?)
+                    .connect()
+                    .await?
+            }
+        };
+
+        Ok(channel)
+    }
+}
+
+/// Messages sent by a single user.
+#[derive(Clone)]
+pub struct Load {
+    pub interval: Duration,
+    pub message_size: usize,
+    /// Start of the benchmark, send times are measured relative to it.
+    pub clock: Instant,
+    pub send_until: Instant,
+    pub receive_until: Instant,
+}
+
+impl Load {
+    /// Returns a message of `message_size` bytes carrying the current time.
+    fn message(&self) -> String {
+        let mut message = format!("{}{}:", MESSAGE_PREFIX, self.clock.elapsed().as_micros());
+        while message.len() < self.message_size {
+            message.push('x');
+        }
+
+        message
+    }
+
+    /// Returns the time since a message has been sent, `None` if it wasn't sent by the benchmark.
+    fn latency(&self, message: &str) -> Option<Duration> {
+        let sent = message.strip_prefix(MESSAGE_PREFIX)?.split(':').next()?;
+        let sent = Duration::from_micros(sent.parse().ok()?);
+
+        Some(self.clock.elapsed().checked_sub(sent).unwrap_or_default())
+    }
+}
+
+/// Statistics of a single user.
+#[derive(Default)]
+pub struct ClientReport {
+    /// Messages accepted by the server.
+    pub sent: u64,
+    pub received: u64,
+    pub latencies: Vec<Duration>,
+    /// Number of failed requests by status code.
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl ClientReport {
+    fn add_error(&mut self, request: &str, status: &Status) {
+        *self
+            .errors
+            .entry(format!("{} {:?}", request, status.code()))
+            .or_default() += 1;
+    }
+}
+
+/// A logged in user with an open receive stream.
+pub struct Client {
+    pub user: chat::User,
+    chat_client: ChatServiceClient<Channel>,
+    // the user is logged out as soon as the session ends
+    _session: Streaming<chat::AuthenticateResponse>,
+    notifications: Streaming<chat::ReceiveResponse>,
+}
+
+impl Client {
+    pub async fn login(
+        target: &Target,
+        name: String,
+    ) -> Result<Client, Box<dyn std::error::Error>> {
+        let channel = target.connect().await?;
+        let mut authentication_client = AuthenticationServiceClient::new(channel.clone());
+
+        let mut attempt = 1;
+        let mut session = loop {
+            let request = Request::new(chat::AuthenticateRequest { name: name.clone() });
+
+            match authentication_client.authenticate(request).await {
+                Ok(response) => break response.into_inner(),
+                Err(status)
+                    if status.code() == Code::ResourceExhausted && attempt < LOGIN_ATTEMPTS =>
+                {
+                    attempt += 1;
+                    tokio::time::delay_for(Duration::from_secs(1)).await;
+                }
+                Err(status) => return Err(status.into()),
+            }
+        };
+
+        let response = match session.message().await? {
+            Some(response) => response,
+            None => return Err(format!("session of {} ended before login", name).into()),
+        };
+
+        let user_id = AsciiMetadataValue::from_str(&response.id)?;
+        let user_token = AsciiMetadataValue::from_str(&response.token)?;
+
+        let mut chat_client =
+            ChatServiceClient::with_interceptor(channel, move |mut req: Request<()>| {
+                req.metadata_mut().insert("user_id", user_id.clone());
+                req.metadata_mut().insert("user_token", user_token.clone());
+
+                Ok(req)
+            });
+
+        let notifications = chat_client
+            .receive(Request::new(chat::ReceiveRequest {}))
+            .await?
+            .into_inner();
+
+        Ok(Client {
+            user: chat::User {
+                id: response.id,
+                name,
+            },
+            chat_client,
+            _session: session,
+            notifications,
+        })
+    }
+
+    /// Sends messages to the other users in turn and measures the latency of received messages.
+    pub async fn run(self, index: usize, users: Arc<Vec<chat::User>>, load: Load) -> ClientReport {
+        let receiver = tokio::spawn(Client::receive(
+            self.notifications,
+            self.chat_client.clone(),
+            load.clone(),
+        ));
+
+        let mut chat_client = self.chat_client;
+        let mut report = ClientReport::default();
+        let mut interval = tokio::time::interval(load.interval);
+
+        for sequence in 0.. {
+            interval.tick().await;
+            if Instant::now() >= load.send_until {
+                break;
+            }
+
+            // every other user, never the sender itself
+            let offset = 1 + sequence % (users.len() - 1);
+            let recipient = &users[(index + offset) % users.len()];
+
+            let request = Request::new(chat::SendRequest {
+                notification: Some(chat::OutgoingNotification {
+                    to: Some(recipient.clone()),
+                    types: Some(chat::outgoing_notification::Types::Message(
+                        chat::MessageContent {
+                            time_sent: None,
+                            content: load.message(),
+                        },
+                    )),
+                }),
+                idempotency_key: String::new(),
+            });
+
+            match chat_client.send(request).await {
+                Ok(_) => report.sent += 1,
+                Err(status) => report.add_error("send", &status),
+            }
+        }
+
+        match receiver.await {
+            Ok(received) => {
+                report.received = received.received;
+                report.latencies = received.latencies;
+                report.errors.extend(received.errors);
+            }
+            Err(err) => eprintln!("receiver of {} failed: {}", self.user.name, err),
+        }
+
+        report
+    }
+
+    async fn receive(
+        mut notifications: Streaming<chat::ReceiveResponse>,
+        mut chat_client: ChatServiceClient<Channel>,
+        load: Load,
+    ) -> ClientReport {
+        let mut report = ClientReport::default();
+
+        loop {
+            let response =
+                match tokio::time::timeout_at(load.receive_until, notifications.message()).await {
+                    Ok(Ok(Some(response))) => response,
+                    Ok(Ok(None)) | Err(_) => break,
+                    Ok(Err(status)) => {
+                        report.add_error("receive", &status);
+                        break;
+                    }
+                };
+
+            let types = response
+                .notification
+                .and_then(|notification| notification.types);
+
+            match types {
+                Some(chat::incoming_notification::Types::Message(message)) => {
+                    let content = message.message_content.unwrap_or_default().content;
+
+                    if let Some(latency) = load.latency(&content) {
+                        report.received += 1;
+                        report.latencies.push(latency);
+                    }
+                }
+                Some(chat::incoming_notification::Types::Heartbeat(_)) => {
+                    let request = Request::new(chat::HeartbeatRequest {});
+                    if let Err(status) = chat_client.heartbeat(request).await {
+                        report.add_error("heartbeat", &status);
+                    }
+                }
+                Some(chat::incoming_notification::Types::ServerShutdown(_))
+                | Some(chat::incoming_notification::Types::Disconnected(_)) => break,
+                _ => {}
+            }
+        }
+
+        report
+    }
+}
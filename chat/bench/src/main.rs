@@ -0,0 +1,123 @@
+// tonic::Status is large by design and dictated by the generated interceptor signature
+#![allow(clippy::result_large_err)]
+
+mod client;
+mod report;
+
+use client::{Client, Load};
+use report::Report;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use structopt::StructOpt;
+use tokio::time::Instant;
+
+#[derive(StructOpt)]
+#[structopt(
+    about = "A load generator for the gRPC chat server",
+    after_help = "Users are logged in one after another. The server limits authentications per peer \
+                  address, raise its --auth-rate and --auth-burst to quickly log in many users."
+)]
+struct Cli {
+    #[structopt(
+        long,
+        default_value = "http://localhost:50001",
+        help = "URI of the chat server"
+    )]
+    server: String,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Unix domain socket of the chat server, used instead of --server"
+    )]
+    unix_socket: Option<PathBuf>,
+
+    #[structopt(long, default_value = "10", help = "Number of simulated users")]
+    users: usize,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Number of messages per second each user sends"
+    )]
+    rate: f64,
+
+    #[structopt(
+        long,
+        default_value = "10",
+        help = "Number of seconds during which messages are sent"
+    )]
+    duration: u64,
+
+    #[structopt(long, default_value = "64", help = "Size of a single message in bytes")]
+    message_size: usize,
+
+    #[structopt(
+        long,
+        default_value = "2",
+        help = "Number of seconds to wait for outstanding messages after sending stopped"
+    )]
+    drain_timeout: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Cli::from_args();
+
+    if args.users < 2 {
+        return Err("at least 2 users are needed to exchange messages".into());
+    }
+    if !args.rate.is_finite() || args.rate <= 0.0 {
+        return Err("the message rate has to be positive".into());
+    }
+
+    let target = client::Target {
+        server: args.server.clone(),
+        unix_socket: args.unix_socket.clone(),
+    };
+
+    // names have to be unique on the server, several benchmarks may run at the same time
+    let run_id = std::process::id();
+
+    println!("Logging in {} users", args.users);
+
+    let mut clients = Vec::with_capacity(args.users);
+    for index in 0..args.users {
+        let name = format!("bench-{}-{}", run_id, index);
+        clients.push(Client::login(&target, name).await?);
+    }
+
+    let users: Arc<Vec<_>> = Arc::new(clients.iter().map(|client| client.user.clone()).collect());
+
+    let duration = Duration::from_secs(args.duration);
+    let clock = Instant::now();
+    let load = Load {
+        interval: Duration::from_secs_f64(1.0 / args.rate),
+        message_size: args.message_size,
+        clock,
+        send_until: clock + duration,
+        receive_until: clock + duration + Duration::from_secs(args.drain_timeout),
+    };
+
+    println!(
+        "Sending {} messages per second for {} seconds",
+        args.rate * args.users as f64,
+        args.duration
+    );
+
+    let tasks: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(index, client)| tokio::spawn(client.run(index, users.clone(), load.clone())))
+        .collect();
+
+    let mut report = Report::new(args.users, duration);
+    for task in tasks {
+        report.add(task.await?);
+    }
+
+    report.print();
+
+    Ok(())
+}
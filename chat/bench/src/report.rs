@@ -0,0 +1,89 @@
+use crate::client::ClientReport;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Statistics of all users.
+pub struct Report {
+    users: usize,
+    duration: Duration,
+    sent: u64,
+    received: u64,
+    latencies: Vec<Duration>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl Report {
+    pub fn new(users: usize, duration: Duration) -> Report {
+        Report {
+            users,
+            duration,
+            sent: 0,
+            received: 0,
+            latencies: vec![],
+            errors: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, client: ClientReport) {
+        self.sent += client.sent;
+        self.received += client.received;
+        self.latencies.extend(client.latencies);
+
+        for (error, count) in client.errors {
+            *self.errors.entry(error).or_default() += count;
+        }
+    }
+
+    /// Returns the latency below which `percent` of the messages were delivered.
+    fn percentile(latencies: &[Duration], percent: f64) -> Duration {
+        if latencies.is_empty() {
+            return Duration::default();
+        }
+
+        let rank = (percent / 100.0 * latencies.len() as f64).ceil() as usize;
+        latencies[rank.clamp(1, latencies.len()) - 1]
+    }
+
+    fn per_second(&self, count: u64) -> f64 {
+        count as f64 / self.duration.as_secs_f64()
+    }
+
+    pub fn print(mut self) {
+        self.latencies.sort();
+
+        // messages accepted by the server which never arrived
+        let lost = self.sent.saturating_sub(self.received);
+        let loss = match self.sent {
+            0 => 0.0,
+            sent => lost as f64 * 100.0 / sent as f64,
+        };
+
+        println!();
+        println!("Users:     {}", self.users);
+        println!("Duration:  {:.1} s", self.duration.as_secs_f64());
+        println!(
+            "Sent:      {} messages ({:.1}/s)",
+            self.sent,
+            self.per_second(self.sent)
+        );
+        println!(
+            "Received:  {} messages ({:.1}/s)",
+            self.received,
+            self.per_second(self.received)
+        );
+        println!("Lost:      {} messages ({:.2} %)", lost, loss);
+
+        println!("Latency:");
+        for &(label, percent) in &[("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("max", 100.0)] {
+            let latency = Report::percentile(&self.latencies, percent);
+            println!("  {}  {:.3} ms", label, latency.as_secs_f64() * 1000.0);
+        }
+
+        if !self.errors.is_empty() {
+            println!("Errors:");
+            for (error, count) in &self.errors {
+                println!("  {:<24}  {}", error, count);
+            }
+        }
+    }
+}
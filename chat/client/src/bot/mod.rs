@@ -0,0 +1,333 @@
+//! A small framework for bots: plugins react to presence changes, messages and slash commands
+//! like `/echo hello`, and answer with messages the client sends on their behalf.
+
+mod plugins;
+
+pub use plugins::builtin;
+
+use proto::chat::{self, incoming_notification};
+
+/// Prefix of the messages which are commands.
+const COMMAND_PREFIX: char = '/';
+
+/// Prepended to the messages of bots which would otherwise be read as a command, so two bots
+/// never answer each other's replies with commands of their own.
+const QUOTE_PREFIX: &str = "> ";
+
+/// A command a plugin handles, listed by `/help`.
+pub struct Command {
+    /// Name without the prefix, e.g. `echo`.
+    pub name: &'static str,
+    /// Arguments the command takes, e.g. `<text>`, empty if it takes none.
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+/// A message to be sent by the client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outgoing {
+    pub to: chat::User,
+    pub content: String,
+}
+
+impl Outgoing {
+    /// Returns the request sending the message, retries of it have to carry the same key.
+    pub fn into_request(self, idempotency_key: String) -> chat::SendRequest {
+        chat::SendRequest {
+            notification: Some(chat::OutgoingNotification {
+                to: Some(self.to),
+                types: Some(chat::outgoing_notification::Types::Message(
+                    chat::MessageContent {
+                        content: self.content,
+                        time_sent: None,
+                    },
+                )),
+            }),
+            idempotency_key,
+        }
+    }
+}
+
+/// The user an event came from and the messages plugins want to send in response.
+pub struct Context {
+    from: chat::User,
+    outgoing: Vec<Outgoing>,
+}
+
+impl Context {
+    fn new(from: chat::User) -> Context {
+        Context {
+            from,
+            outgoing: vec![],
+        }
+    }
+
+    /// The user who sent the message or whose presence changed.
+    pub fn from(&self) -> &chat::User {
+        &self.from
+    }
+
+    /// Sends a message to the user the event came from.
+    pub fn reply(&mut self, content: impl Into<String>) {
+        let to = self.from.clone();
+        self.send(to, content);
+    }
+
+    pub fn send(&mut self, to: chat::User, content: impl Into<String>) {
+        let mut content = content.into();
+        if parse_command(&content).is_some() {
+            content.insert_str(0, QUOTE_PREFIX);
+        }
+
+        self.outgoing.push(Outgoing { to, content });
+    }
+}
+
+/// Reacts to the events of a bot, all handlers do nothing by default.
+#[tonic::async_trait]
+pub trait Plugin: Send + Sync {
+    /// Name the plugin is enabled with on the command line.
+    fn name(&self) -> &'static str;
+
+    /// Returns the commands handled by `on_command`.
+    fn commands(&self) -> Vec<Command> {
+        vec![]
+    }
+
+    /// Handles one of the plugin's commands, `args` is the trimmed text after the command name.
+    async fn on_command(&self, _context: &mut Context, _command: &str, _args: &str) {}
+
+    /// Handles a user coming online or going offline.
+    async fn on_presence(&self, _context: &mut Context, _is_online: bool) {}
+
+    /// Handles a message which is not a command.
+    async fn on_message(&self, _context: &mut Context, _content: &str) {}
+}
+
+/// Dispatches the notifications of the client to its plugins. Commands go to the plugin which
+/// declares them, presence changes and other messages go to every plugin. `/help` lists all
+/// commands.
+#[derive(Default)]
+pub struct Bot {
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl Bot {
+    pub fn new() -> Bot {
+        Bot::default()
+    }
+
+    pub fn plugin(mut self, plugin: Box<dyn Plugin>) -> Bot {
+        self.plugins.push(plugin);
+        self
+    }
+
+    /// Returns the messages the plugins send in response to a notification. Notifications of the
+    /// server itself are ignored.
+    pub async fn handle(&self, notification: &chat::IncomingNotification) -> Vec<Outgoing> {
+        let from = match &notification.from {
+            Some(from) => from.clone(),
+            None => return vec![],
+        };
+
+        let mut context = Context::new(from);
+
+        match &notification.types {
+            Some(incoming_notification::Types::Online(online)) => {
+                for plugin in &self.plugins {
+                    plugin.on_presence(&mut context, online.is_online).await;
+                }
+            }
+            Some(incoming_notification::Types::Message(message)) => {
+                let content = match &message.message_content {
+                    Some(content) => content.content.as_str(),
+                    None => "",
+                };
+
+                match parse_command(content) {
+                    Some((command, args)) => self.run_command(&mut context, command, args).await,
+                    None => {
+                        for plugin in &self.plugins {
+                            plugin.on_message(&mut context, content).await;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        context.outgoing
+    }
+
+    async fn run_command(&self, context: &mut Context, command: &str, args: &str) {
+        if command == "help" {
+            context.reply(self.help());
+            return;
+        }
+
+        let plugin = self
+            .plugins
+            .iter()
+            .find(|plugin| plugin.commands().iter().any(|v| v.name == command));
+
+        match plugin {
+            Some(plugin) => plugin.on_command(context, command, args).await,
+            None => context.reply(format!(
+                "Unknown command {}{}, try {}help",
+                COMMAND_PREFIX, command, COMMAND_PREFIX
+            )),
+        }
+    }
+
+    fn help(&self) -> String {
+        let mut lines = vec![
+            String::from("Commands:"),
+            format!("{}help - lists the commands", COMMAND_PREFIX),
+        ];
+
+        for command in self.plugins.iter().flat_map(|plugin| plugin.commands()) {
+            let usage = match command.usage {
+                "" => String::new(),
+                usage => format!(" {}", usage),
+            };
+
+            lines.push(format!(
+                "{}{}{} - {}",
+                COMMAND_PREFIX, command.name, usage, command.description
+            ));
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Splits a command message like `/echo hello` into the command name and its arguments.
+fn parse_command(content: &str) -> Option<(&str, &str)> {
+    let content = content.trim().strip_prefix(COMMAND_PREFIX)?;
+
+    let (command, args) = match content.find(char::is_whitespace) {
+        Some(index) => (&content[..index], content[index..].trim()),
+        None => (content, ""),
+    };
+
+    match command.is_empty() {
+        true => None,
+        false => Some((command, args)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::plugins::{Echo, Greeter};
+    use super::*;
+
+    fn user(name: &str) -> chat::User {
+        chat::User {
+            id: format!("{}-id", name),
+            name: String::from(name),
+        }
+    }
+
+    fn message(from: &str, content: &str) -> chat::IncomingNotification {
+        chat::IncomingNotification {
+            from: Some(user(from)),
+            types: Some(incoming_notification::Types::Message(
+                incoming_notification::Message {
+                    message_id: None,
+                    message_content: Some(chat::MessageContent {
+                        time_sent: None,
+                        content: String::from(content),
+                    }),
+                    sequence_number: 1,
+                },
+            )),
+        }
+    }
+
+    fn replies(outgoing: Vec<Outgoing>) -> Vec<String> {
+        outgoing.into_iter().map(|v| v.content).collect()
+    }
+
+    #[test]
+    fn commands_are_split_from_their_arguments() {
+        assert_eq!(
+            parse_command("/echo  hello world "),
+            Some(("echo", "hello world"))
+        );
+        assert_eq!(parse_command("/time"), Some(("time", "")));
+        assert_eq!(parse_command("hello /echo"), None);
+        assert_eq!(parse_command("/ echo"), None);
+    }
+
+    #[tokio::test]
+    async fn commands_are_dispatched_to_their_plugin() {
+        let bot = Bot::new().plugin(Box::new(Greeter)).plugin(Box::new(Echo));
+
+        let outgoing = bot.handle(&message("alice", "/echo hello")).await;
+        assert_eq!(
+            outgoing,
+            vec![Outgoing {
+                to: user("alice"),
+                content: String::from("hello"),
+            }]
+        );
+
+        let help = replies(bot.handle(&message("alice", "/help")).await);
+        assert!(help[0].starts_with("Commands:"));
+        assert!(help[0].contains("/echo <text> - "));
+
+        let unknown = replies(bot.handle(&message("alice", "/dance")).await);
+        assert_eq!(unknown, vec!["Unknown command /dance, try /help"]);
+
+        assert!(bot.handle(&message("alice", "hello")).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn bots_do_not_answer_each_others_replies() {
+        let alice = Bot::new().plugin(Box::new(Echo));
+        let bob = Bot::new().plugin(Box::new(Echo));
+
+        for content in &["/help", "/echo /help", "/echo /echo /help", "/dance"] {
+            let mut outgoing = alice.handle(&message("bob", content)).await;
+            let mut is_alice = false;
+
+            // the reply of one bot is received by the other one and so on
+            for _ in 0..2 {
+                let bot = match is_alice {
+                    true => &alice,
+                    false => &bob,
+                };
+
+                outgoing = match outgoing.pop() {
+                    Some(reply) => bot.handle(&message("other", &reply.content)).await,
+                    None => break,
+                };
+                is_alice = !is_alice;
+            }
+
+            assert!(
+                outgoing.is_empty(),
+                "{} is answered back and forth",
+                content
+            );
+        }
+
+        let echo = replies(alice.handle(&message("bob", "/echo /help")).await);
+        assert_eq!(echo, vec!["> /help"]);
+    }
+
+    #[tokio::test]
+    async fn users_coming_online_are_greeted() {
+        let bot = Bot::new().plugin(Box::new(Greeter));
+
+        let online = |is_online| chat::IncomingNotification {
+            from: Some(user("bob")),
+            types: Some(incoming_notification::Types::Online(
+                incoming_notification::Online { is_online },
+            )),
+        };
+
+        assert_eq!(replies(bot.handle(&online(true)).await), vec!["Hello bob!"]);
+        assert!(bot.handle(&online(false)).await.is_empty());
+    }
+}
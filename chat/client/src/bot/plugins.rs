@@ -0,0 +1,75 @@
+//! The plugins which come with the client.
+
+use super::{Command, Context, Plugin};
+use std::time::SystemTime;
+
+/// Returns the plugin which comes with the client with the given name.
+pub fn builtin(name: &str) -> Option<Box<dyn Plugin>> {
+    let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(Greeter), Box::new(Echo), Box::new(Clock)];
+    plugins.into_iter().find(|plugin| plugin.name() == name)
+}
+
+/// Greets every user who comes online.
+pub struct Greeter;
+
+#[tonic::async_trait]
+impl Plugin for Greeter {
+    fn name(&self) -> &'static str {
+        "greeter"
+    }
+
+    async fn on_presence(&self, context: &mut Context, is_online: bool) {
+        if is_online {
+            let greeting = format!("Hello {}!", context.from().name);
+            context.reply(greeting);
+        }
+    }
+}
+
+/// Answers `/echo <text>` with the text.
+pub struct Echo;
+
+#[tonic::async_trait]
+impl Plugin for Echo {
+    fn name(&self) -> &'static str {
+        "echo"
+    }
+
+    fn commands(&self) -> Vec<Command> {
+        vec![Command {
+            name: "echo",
+            usage: "<text>",
+            description: "repeats the text",
+        }]
+    }
+
+    async fn on_command(&self, context: &mut Context, _command: &str, args: &str) {
+        match args {
+            "" => context.reply("Usage: /echo <text>"),
+            text => context.reply(text),
+        }
+    }
+}
+
+/// Answers `/time` with the current time of the client in UTC.
+pub struct Clock;
+
+#[tonic::async_trait]
+impl Plugin for Clock {
+    fn name(&self) -> &'static str {
+        "time"
+    }
+
+    fn commands(&self) -> Vec<Command> {
+        vec![Command {
+            name: "time",
+            usage: "",
+            description: "tells the current time in UTC",
+        }]
+    }
+
+    async fn on_command(&self, context: &mut Context, _command: &str, _args: &str) {
+        let now = proto::json::timestamp::format(&SystemTime::now().into());
+        context.reply(format!("It is {}", now));
+    }
+}
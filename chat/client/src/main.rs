@@ -1,18 +1,77 @@
+// tonic::Status is large by design and dictated by the generated interceptor signature
+#![allow(clippy::result_large_err)]
+
+mod bot;
+mod sequence;
+
+use bot::Bot;
 use chat::authentication_service_client::AuthenticationServiceClient;
 use chat::chat_service_client::ChatServiceClient;
 use chat::AuthenticateRequest;
 use chat::ReceiveRequest;
 use proto::chat;
+use sequence::{Received, Sequences};
+use server_common::{keepalive, uds};
 use std::io::Write;
+use std::path::PathBuf;
 use std::sync::mpsc;
-use tonic::{transport::Endpoint, Request};
+use std::time::Duration;
+use structopt::StructOpt;
+use tonic::{transport::Endpoint, Code, Request};
+
+/// Number of attempts to send a message.
+const SEND_ATTEMPTS: u32 = 3;
+/// Time to wait for the server to accept a message.
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time to wait before the first retry, increased with every attempt.
+const SEND_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Longest time to wait for the rate limit of the server before a message is given up.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(StructOpt)]
+#[structopt(about = "A gRPC chat client")]
+struct Cli {
+    #[structopt(
+        long,
+        default_value = "http://localhost:50001",
+        help = "URI of the chat server"
+    )]
+    server: String,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Unix domain socket of the chat server, used instead of --server"
+    )]
+    unix_socket: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        default_value = "greeter,echo,time",
+        help = "Bot plugins to enable: greeter, echo, time"
+    )]
+    plugins: Vec<String>,
+}
+
+fn build_bot(plugins: &[String]) -> Result<Bot, String> {
+    let mut bot = Bot::new();
+
+    for plugin in plugins {
+        bot = match bot::builtin(plugin) {
+            Some(plugin) => bot.plugin(plugin),
+            None => return Err(format!("Unknown plugin {}", plugin)),
+        };
+    }
+
+    Ok(bot)
+}
 
 async fn connect(
     clients: mpsc::Sender<Option<ChatServiceClient<tonic::transport::Channel>>>,
     user_name: String,
+    args: Cli,
 ) {
-    let endpoint = Endpoint::from_static("http://localhost:50001");
-
     // let channel: tonic::transport::Channel;
     // loop {
     //     channel = match endpoint.connect().await {
@@ -23,14 +82,25 @@ async fn connect(
     //     break;
     // }
 
-    let channel = match endpoint.connect().await {
+    let channel = match &args.unix_socket {
+        Some(path) => uds::connect(path).await,
+        None => match Endpoint::from_shared(args.server.clone()) {
+            Ok(endpoint) => keepalive::configure(endpoint).connect().await,
+            Err(_error) => panic!("Invalid server URI {}", args.server),
+        },
+    };
+
+    let channel = match channel {
         Ok(channel) => channel,
         Err(_error) => {
             panic!("Error");
         }
     };
 
-    println!("Connected to {}", endpoint.uri());
+    match &args.unix_socket {
+        Some(path) => println!("Connected to {}", path.display()),
+        None => println!("Connected to {}", args.server),
+    }
 
     let mut authentication_client = AuthenticationServiceClient::new(channel.clone());
 
@@ -69,6 +139,121 @@ async fn connect(
     }
 }
 
+/// Sends a request, retrying it while the server is unavailable, doesn't answer in time or rate
+/// limits the user. The idempotency key of the request keeps retries from sending the message
+/// twice.
+async fn send_with_retries(
+    client: &mut ChatServiceClient<tonic::transport::Channel>,
+    request: chat::SendRequest,
+) -> Result<chat::SendResponse, tonic::Status> {
+    let mut attempt = 1;
+
+    loop {
+        let result =
+            tokio::time::timeout(SEND_TIMEOUT, client.send(Request::new(request.clone()))).await;
+
+        let status = match result {
+            Ok(Ok(response)) => return Ok(response.into_inner()),
+            Ok(Err(status)) => status,
+            Err(_) => tonic::Status::deadline_exceeded("send timed out"),
+        };
+
+        let delay = match status.code() {
+            Code::Unavailable | Code::DeadlineExceeded | Code::Aborted => {
+                Some(SEND_RETRY_DELAY * attempt)
+            }
+            // the server tells when the next message is admitted
+            Code::ResourceExhausted => match retry_after(&status) {
+                Some(retry_after) if retry_after <= MAX_RETRY_AFTER => Some(retry_after),
+                Some(_) => None,
+                None => Some(SEND_RETRY_DELAY * attempt),
+            },
+            _ => None,
+        };
+
+        let delay = match delay {
+            Some(delay) if attempt < SEND_ATTEMPTS => delay,
+            _ => return Err(status),
+        };
+
+        println!("Sending failed, retrying: {}", status.message());
+        tokio::time::delay_for(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Returns the time the server asks the client to wait before retrying a rate limited request.
+fn retry_after(status: &tonic::Status) -> Option<Duration> {
+    let seconds = status.metadata().get("retry-after")?.to_str().ok()?;
+
+    seconds.parse().ok().map(Duration::from_secs)
+}
+
+/// Prints the messages a user sent after `last` and before `next` which have not been received.
+async fn print_missed_messages(
+    client: &mut ChatServiceClient<tonic::transport::Channel>,
+    sequences: &mut Sequences,
+    from: &chat::User,
+    last: u64,
+    next: u64,
+) -> Result<(), tonic::Status> {
+    let mut after = last;
+
+    while after + 1 < next {
+        let messages = client
+            .list_messages(Request::new(chat::ListMessagesRequest {
+                from_user_id: from.id.clone(),
+                after_sequence_number: after,
+                page_size: 0,
+            }))
+            .await?
+            .into_inner()
+            .messages;
+
+        let mut fetched = 0;
+        for notification in messages {
+            let message = match notification.types {
+                Some(chat::incoming_notification::Types::Message(message)) => message,
+                _ => continue,
+            };
+
+            if message.sequence_number >= next {
+                break;
+            }
+
+            after = message.sequence_number;
+            fetched += 1;
+
+            // messages which arrived late have been printed already
+            if !sequences.recover(&from.id, message.sequence_number) {
+                continue;
+            }
+
+            println!(
+                "Missed message {} from user {} ({}): {}",
+                message.message_id.unwrap_or_default().id,
+                from.name,
+                from.id,
+                message.message_content.unwrap_or_default().content
+            );
+        }
+
+        // the server no longer keeps the remaining messages
+        if fetched == 0 {
+            break;
+        }
+    }
+
+    if !sequences.is_complete_before(&from.id, next) {
+        println!(
+            "Some messages from user {} ({}) could not be fetched",
+            from.name, from.id
+        );
+    }
+
+    Ok(())
+}
+
 fn get_user_name() -> String {
     print!("Username: ");
     std::io::stdout().flush().unwrap();
@@ -82,102 +267,163 @@ fn get_user_name() -> String {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Cli::from_args();
+    let bot = build_bot(&args.plugins)?;
     let user_name = get_user_name();
 
     let (sender, receiver) = mpsc::channel();
 
     tokio::spawn(async {
-        connect(sender, user_name).await;
+        connect(sender, user_name, args).await;
     });
 
-    loop {
-        let client = receiver.recv().unwrap();
-
-        if let Some(mut client) = client {
-            let mut receive_stream = client
-                .receive(Request::new(ReceiveRequest {}))
-                .await?
-                .into_inner();
-
-            while let Some(response) = receive_stream.message().await? {
-                let notification = response.notification.unwrap();
-                let user = notification.from.unwrap();
-                let from_user_id = user.id;
-                let from_user_name = user.name;
-                let from_user = format!("{} ({})", from_user_name, from_user_id);
-
-                let notification_type = notification.types.unwrap();
-                match notification_type {
-                    chat::incoming_notification::Types::Delivered(delivered) => {
-                        println!(
-                            "Message {} was delivered to user {}",
-                            delivered.message_id.unwrap().id,
-                            from_user
-                        );
-                    }
-                    chat::incoming_notification::Types::Read(read) => {
-                        println!(
-                            "User {} read message {}",
-                            from_user,
-                            read.message_id.unwrap().id
-                        );
-                    }
-                    chat::incoming_notification::Types::Typing(typing) => {
-                        let typing_nottyping = match typing.is_typing {
-                            true => "typing",
-                            false => "not typing",
-                        };
-
-                        println!("User {} is {}", from_user, typing_nottyping);
-                    }
-                    chat::incoming_notification::Types::Online(online) => {
-                        let online_offline = match online.is_online {
-                            true => "online",
-                            false => "offline",
-                        };
-
-                        println!("User {} is {}", from_user, online_offline);
-
-                        let welcome_message = format!("Hello {}!", from_user_name);
-                        if online.is_online {
-                            let message_id = client
-                                .send(Request::new(chat::SendRequest {
-                                    notification: Some(chat::OutgoingNotification {
-                                        to: Some(chat::User {
-                                            id: from_user_id,
-                                            name: from_user_name,
-                                        }),
-                                        types: Some(chat::outgoing_notification::Types::Message(
-                                            chat::MessageContent {
-                                                content: welcome_message,
-                                                time_sent: None,
-                                            },
-                                        )),
-                                    }),
-                                }))
-                                .await
-                                .unwrap()
-                                .into_inner()
-                                .message_id
-                                .unwrap();
-
-                            println!("Message {} was sent", message_id.id);
-                        }
-                    }
-                    chat::incoming_notification::Types::Message(message) => {
-                        println!(
-                            "Message {} from user {}: {}",
-                            message.message_id.unwrap().id,
-                            from_user,
-                            message.message_content.unwrap().content
-                        );
-                    }
+    let mut client = match receiver.recv().unwrap() {
+        Some(client) => client,
+        None => return Ok(()),
+    };
+
+    let mut sequences = Sequences::new();
+    // numbers the idempotency keys, which only have to be unique per user
+    let mut sent_messages: u64 = 0;
+
+    let mut receive_stream = client
+        .receive(Request::new(ReceiveRequest {}))
+        .await?
+        .into_inner();
+
+    while let Some(response) = receive_stream.message().await? {
+        let notification = response.notification.unwrap();
+
+        // messages received twice are dropped, missed messages are fetched before the
+        // next one
+        if let (Some(from), Some(chat::incoming_notification::Types::Message(message))) =
+            (&notification.from, &notification.types)
+        {
+            match sequences.receive(&from.id, message.sequence_number) {
+                Received::InOrder => {}
+                Received::AfterGap(last) => {
+                    print_missed_messages(
+                        &mut client,
+                        &mut sequences,
+                        from,
+                        last,
+                        message.sequence_number,
+                    )
+                    .await?
+                }
+                Received::Duplicate => {
+                    println!(
+                        "Dropped duplicate message {} from user {} ({})",
+                        message.sequence_number, from.name, from.id
+                    );
+                    continue;
                 }
             }
         }
 
-        break;
+        let outgoing = bot.handle(&notification).await;
+
+        // notifications sent by the server itself have no sender
+        let user = notification.from.unwrap_or_default();
+        let from_user_id = user.id;
+        let from_user_name = user.name;
+        let from_user = format!("{} ({})", from_user_name, from_user_id);
+
+        let notification_type = notification.types.unwrap();
+        match notification_type {
+            chat::incoming_notification::Types::Delivered(delivered) => {
+                println!(
+                    "Message {} was delivered to user {}",
+                    delivered.message_id.unwrap().id,
+                    from_user
+                );
+            }
+            chat::incoming_notification::Types::Read(read) => {
+                println!(
+                    "User {} read message {}",
+                    from_user,
+                    read.message_id.unwrap().id
+                );
+            }
+            chat::incoming_notification::Types::Typing(typing) => {
+                let typing_nottyping = match typing.is_typing {
+                    true => "typing",
+                    false => "not typing",
+                };
+
+                println!("User {} is {}", from_user, typing_nottyping);
+            }
+            chat::incoming_notification::Types::Online(online) => {
+                let online_offline = match online.is_online {
+                    true => "online",
+                    false => "offline",
+                };
+
+                println!("User {} is {}", from_user, online_offline);
+            }
+            chat::incoming_notification::Types::Message(message) => {
+                println!(
+                    "Message {} from user {}: {}",
+                    message.message_id.unwrap().id,
+                    from_user,
+                    message.message_content.unwrap().content
+                );
+            }
+            chat::incoming_notification::Types::SystemMessage(system_message) => {
+                println!(
+                    "*** Announcement: {} ***",
+                    system_message.message_content.unwrap().content
+                );
+            }
+            chat::incoming_notification::Types::Heartbeat(_) => {
+                // keeps the user online, whether a failed answer ended the session is settled by
+                // the next heartbeat or the timeout of the server
+                if let Err(status) = client
+                    .heartbeat(Request::new(chat::HeartbeatRequest {}))
+                    .await
+                {
+                    println!("Answering the heartbeat failed: {}", status.message());
+                }
+            }
+            chat::incoming_notification::Types::ServerShutdown(server_shutdown) => {
+                println!("Server is shutting down: {}", server_shutdown.reason);
+            }
+            chat::incoming_notification::Types::Disconnected(disconnected) => {
+                println!("Disconnected by the server: {}", disconnected.reason);
+            }
+        }
+
+        for message in outgoing {
+            sent_messages += 1;
+            let request = message.into_request(format!("bot-{}", sent_messages));
+
+            // a reply which can't be sent doesn't end the bot
+            match send_with_retries(&mut client, request).await {
+                Ok(response) => println!("Message {} was sent", response.message_id.unwrap().id),
+                Err(status) => println!("Sending failed: {}", status.message()),
+            }
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+
+    fn exhausted(retry_after: &'static str) -> tonic::Status {
+        let mut metadata = MetadataMap::new();
+        metadata.insert("retry-after", AsciiMetadataValue::from_static(retry_after));
+
+        tonic::Status::with_metadata(Code::ResourceExhausted, "rate limit exceeded", metadata)
+    }
+
+    #[test]
+    fn retry_after_is_read_from_the_metadata() {
+        assert_eq!(retry_after(&exhausted("2")), Some(Duration::from_secs(2)));
+        assert_eq!(retry_after(&exhausted("soon")), None);
+        assert_eq!(retry_after(&tonic::Status::resource_exhausted("")), None);
+    }
+}
@@ -0,0 +1,123 @@
+//! Detects missed and duplicated messages with the sequence numbers the server assigns to the
+//! messages of every sender.
+
+use std::collections::{BTreeSet, HashMap};
+
+/// What became of a received message.
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    /// All messages before this one have been received.
+    InOrder,
+    /// Some messages after the given sequence number and before this message are missing.
+    AfterGap(u64),
+    /// The message has been received before.
+    Duplicate,
+}
+
+/// The sequence numbers received from a single sender.
+#[derive(Default)]
+struct Sender {
+    /// All numbers up to this one have been received.
+    complete: u64,
+    /// The numbers received after the first missing one.
+    received: BTreeSet<u64>,
+}
+
+impl Sender {
+    /// Records a number, returns whether it is new.
+    fn record(&mut self, sequence_number: u64) -> bool {
+        if sequence_number <= self.complete || !self.received.insert(sequence_number) {
+            return false;
+        }
+
+        while self.received.remove(&(self.complete + 1)) {
+            self.complete += 1;
+        }
+
+        true
+    }
+}
+
+/// The sequence numbers received from every sender. Numbers are tracked one by one, so a
+/// message arriving after a later one is not mistaken for a duplicate.
+#[derive(Default)]
+pub struct Sequences {
+    senders: HashMap<String, Sender>,
+}
+
+impl Sequences {
+    pub fn new() -> Sequences {
+        Sequences::default()
+    }
+
+    /// Records a message received from a user. Messages without a sequence number always count
+    /// as in order.
+    pub fn receive(&mut self, from_user_id: &str, sequence_number: u64) -> Received {
+        if sequence_number == 0 {
+            return Received::InOrder;
+        }
+
+        let sender = self.senders.entry(String::from(from_user_id)).or_default();
+
+        if !sender.record(sequence_number) {
+            return Received::Duplicate;
+        }
+
+        match sequence_number <= sender.complete {
+            true => Received::InOrder,
+            false => Received::AfterGap(sender.complete),
+        }
+    }
+
+    /// Records a missed message which has been fetched, returns whether it had not been received
+    /// yet.
+    pub fn recover(&mut self, from_user_id: &str, sequence_number: u64) -> bool {
+        let sender = self.senders.entry(String::from(from_user_id)).or_default();
+        sender.record(sequence_number)
+    }
+
+    /// Returns whether all messages of the user before the given sequence number have been
+    /// received.
+    pub fn is_complete_before(&self, from_user_id: &str, sequence_number: u64) -> bool {
+        match self.senders.get(from_user_id) {
+            Some(sender) => sender.complete + 1 >= sequence_number,
+            None => sequence_number <= 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_and_duplicates_are_detected_per_sender() {
+        let mut sequences = Sequences::new();
+
+        assert_eq!(sequences.receive("alice", 1), Received::InOrder);
+        assert_eq!(sequences.receive("alice", 2), Received::InOrder);
+        assert_eq!(sequences.receive("bob", 1), Received::InOrder);
+        assert_eq!(sequences.receive("alice", 5), Received::AfterGap(2));
+        assert_eq!(sequences.receive("alice", 5), Received::Duplicate);
+        assert_eq!(sequences.receive("alice", 2), Received::Duplicate);
+        assert_eq!(sequences.receive("bob", 0), Received::InOrder);
+    }
+
+    #[test]
+    fn late_messages_are_not_duplicates() {
+        let mut sequences = Sequences::new();
+
+        assert_eq!(sequences.receive("alice", 2), Received::AfterGap(0));
+        assert!(!sequences.is_complete_before("alice", 3));
+
+        assert_eq!(sequences.receive("alice", 1), Received::InOrder);
+        assert!(sequences.is_complete_before("alice", 3));
+        assert_eq!(sequences.receive("alice", 3), Received::InOrder);
+
+        // fetched messages are only shown once
+        assert_eq!(sequences.receive("alice", 6), Received::AfterGap(3));
+        assert!(sequences.recover("alice", 4));
+        assert!(!sequences.recover("alice", 4));
+        assert_eq!(sequences.receive("alice", 5), Received::InOrder);
+    }
+}
@@ -1,13 +1,107 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+const PROTOS: &[&str] = &[
+    "proto/chat/admin_service.proto",
+    "proto/chat/authentication_service.proto",
+    "proto/chat/backplane.proto",
+    "proto/chat/message.proto",
+    "proto/chat/search.proto",
+    "proto/chat/service.proto",
+    "proto/chat/state.proto",
+    "proto/chat/user.proto",
+    "proto/google/rpc/error_details.proto",
+    "proto/google/rpc/status.proto",
+];
+
+/// Messages of the REST gateway, mapped to JSON following the proto3 JSON mapping: fields are
+/// lowerCamelCase, oneofs are flattened into their message and timestamps are RFC 3339 strings.
+const JSON_MESSAGES: &[&str] = &[
+    ".chat.User",
+    ".chat.MessageId",
+    ".chat.MessageContent",
+    ".chat.OutgoingNotification",
+    ".chat.IncomingNotification",
+    ".chat.AuthenticateRequest",
+    ".chat.AuthenticateResponse",
+    ".chat.SendRequest",
+    ".chat.SendResponse",
+    ".chat.SearchHit",
+    ".chat.SearchResponse",
+    ".chat.ListUsersResponse",
+];
+
+/// JSON messages without oneofs, missing fields take their default value. Serde can't default
+/// the enums generated for oneofs, whose messages only have optional fields anyway.
+const JSON_DEFAULTED_MESSAGES: &[&str] = &[
+    ".chat.User",
+    ".chat.MessageId",
+    ".chat.MessageContent",
+    ".chat.AuthenticateRequest",
+    ".chat.AuthenticateResponse",
+    ".chat.SendRequest",
+    ".chat.SendResponse",
+    ".chat.SearchHit",
+    ".chat.SearchResponse",
+    ".chat.ListUsersResponse",
+];
+
+/// Oneof fields of the JSON messages, matched by suffix so the oneof's variants don't match.
+const JSON_ONEOFS: &[&str] = &["OutgoingNotification.types", "IncomingNotification.types"];
+
+/// Timestamp fields of the JSON messages, matched by suffix.
+const JSON_TIMESTAMPS: &[&str] = &[
+    "MessageContent.time_sent",
+    "Typing.expiration",
+    "Read.time_read",
+    "Delivered.time_delivered",
+    "Heartbeat.time_sent",
+    "SearchHit.time_sent",
+];
+
 fn main() {
-    tonic_build::configure()
-        .compile(
-            &[
-                "proto/chat/authentication_service.proto",
-                "proto/chat/message.proto",
-                "proto/chat/service.proto",
-                "proto/chat/user.proto",
-            ],
-            &["proto"],
-        )
-        .expect("gRPC protobuf compilation failed")
+    let mut builder = tonic_build::configure();
+
+    for message in JSON_MESSAGES {
+        builder = builder
+            .type_attribute(message, "#[derive(serde::Serialize, serde::Deserialize)]")
+            .type_attribute(message, "#[serde(rename_all = \"camelCase\")]");
+    }
+    for message in JSON_DEFAULTED_MESSAGES {
+        builder = builder.type_attribute(message, "#[serde(default)]");
+    }
+    for oneof in JSON_ONEOFS {
+        builder = builder.field_attribute(oneof, "#[serde(flatten)]");
+    }
+    for timestamp in JSON_TIMESTAMPS {
+        builder = builder.field_attribute(
+            timestamp,
+            "#[serde(default, with = \"crate::json::timestamp\")]",
+        );
+    }
+
+    builder
+        .compile(PROTOS, &["proto"])
+        .expect("gRPC protobuf compilation failed");
+
+    // the descriptor set is served by the reflection service
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let descriptor_set = out_dir.join("chat_descriptor.bin");
+
+    let status = Command::new(prost_build::protoc())
+        .arg("--include_imports")
+        .arg("-I")
+        .arg("proto")
+        .arg("-I")
+        .arg(prost_build::protoc_include())
+        .arg(format!("--descriptor_set_out={}", descriptor_set.display()))
+        .args(PROTOS)
+        .status()
+        .expect("failed to run protoc");
+
+    assert!(
+        status.success(),
+        "protobuf descriptor set generation failed"
+    );
 }
@@ -0,0 +1,263 @@
+//! JSON mapping of the messages used by the REST gateway, see `build.rs`.
+
+/// Maps `google.protobuf.Timestamp` fields to RFC 3339 strings in UTC, e.g.
+/// `2020-05-01T12:30:00.250Z`, as the proto3 JSON mapping does.
+pub mod timestamp {
+    use prost_types::Timestamp;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    const SECONDS_PER_DAY: i64 = 86_400;
+    const NANOS_PER_SECOND: i32 = 1_000_000_000;
+
+    pub fn serialize<S: Serializer>(
+        timestamp: &Option<Timestamp>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match timestamp {
+            Some(timestamp) => serializer.serialize_str(&format(timestamp)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Timestamp>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(value) => match parse(&value) {
+                Some(timestamp) => Ok(Some(timestamp)),
+                None => Err(de::Error::custom(format!(
+                    "invalid RFC 3339 timestamp {:?}",
+                    value
+                ))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Formats a timestamp with as many fractional digits as needed, 0, 3, 6 or 9.
+    pub fn format(timestamp: &Timestamp) -> String {
+        let days = timestamp.seconds.div_euclid(SECONDS_PER_DAY);
+        let seconds_of_day = timestamp.seconds.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+
+        let mut formatted = format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            seconds_of_day / 3600,
+            seconds_of_day % 3600 / 60,
+            seconds_of_day % 60
+        );
+
+        let nanos = timestamp.nanos.clamp(0, NANOS_PER_SECOND - 1);
+        if nanos % 1_000_000 == 0 {
+            if nanos != 0 {
+                formatted.push_str(&format!(".{:03}", nanos / 1_000_000));
+            }
+        } else if nanos % 1_000 == 0 {
+            formatted.push_str(&format!(".{:06}", nanos / 1_000));
+        } else {
+            formatted.push_str(&format!(".{:09}", nanos));
+        }
+
+        formatted.push('Z');
+        formatted
+    }
+
+    /// Parses an RFC 3339 timestamp, e.g. `2020-05-01T12:30:00Z` or
+    /// `2020-05-01T14:30:00.25+02:00`.
+    pub fn parse(value: &str) -> Option<Timestamp> {
+        let value = value.as_bytes();
+        if value.len() < 20 || value[4] != b'-' || value[7] != b'-' || value[13] != b':' {
+            return None;
+        }
+        if !matches!(value[10], b'T' | b't' | b' ') || value[16] != b':' {
+            return None;
+        }
+
+        let year = number(&value[0..4])?;
+        let month = number(&value[5..7])?;
+        let day = number(&value[8..10])?;
+        let hour = number(&value[11..13])?;
+        let minute = number(&value[14..16])?;
+        let second = number(&value[17..19])?;
+
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+
+        let mut rest = &value[19..];
+
+        let mut nanos = 0;
+        if rest[0] == b'.' {
+            let digits = rest[1..].iter().take_while(|v| v.is_ascii_digit()).count();
+            if digits == 0 || digits > 9 {
+                return None;
+            }
+
+            nanos = number(&rest[1..=digits])? * 10_i64.pow(9 - digits as u32);
+            rest = &rest[1 + digits..];
+        }
+
+        let offset = match rest {
+            [b'Z'] | [b'z'] => 0,
+            [sign @ (b'+' | b'-'), hours @ .., b':', m1, m2] if hours.len() == 2 => {
+                let offset = number(hours)? * 3600 + number(&[*m1, *m2])? * 60;
+                match sign {
+                    b'+' => offset,
+                    _ => -offset,
+                }
+            }
+            _ => return None,
+        };
+
+        let seconds = days_from_civil(year, month, day) * SECONDS_PER_DAY
+            + hour * 3600
+            + minute * 60
+            + second
+            - offset;
+
+        Some(Timestamp {
+            seconds,
+            nanos: nanos as i32,
+        })
+    }
+
+    fn number(digits: &[u8]) -> Option<i64> {
+        if digits.is_empty() || !digits.iter().all(|v| v.is_ascii_digit()) {
+            return None;
+        }
+
+        std::str::from_utf8(digits).ok()?.parse().ok()
+    }
+
+    fn days_in_month(year: i64, month: i64) -> i64 {
+        match month {
+            2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// Returns the number of days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+    fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// Returns the year, month and day of a number of days since 1970-01-01.
+    fn civil_from_days(days: i64) -> (i64, i64, i64) {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400;
+
+        (if month <= 2 { year + 1 } else { year }, month, day)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn timestamp(seconds: i64, nanos: i32) -> Timestamp {
+            Timestamp { seconds, nanos }
+        }
+
+        #[test]
+        fn formats_utc_with_needed_precision() {
+            assert_eq!(format(&timestamp(0, 0)), "1970-01-01T00:00:00Z");
+            assert_eq!(
+                format(&timestamp(1_588_336_200, 250_000_000)),
+                "2020-05-01T12:30:00.250Z"
+            );
+            assert_eq!(
+                format(&timestamp(951_782_400, 1_000)),
+                "2000-02-29T00:00:00.000001Z"
+            );
+            assert_eq!(format(&timestamp(-1, 5)), "1969-12-31T23:59:59.000000005Z");
+        }
+
+        #[test]
+        fn parses_offsets_and_fractions() {
+            assert_eq!(
+                parse("2020-05-01T12:30:00.25Z"),
+                Some(timestamp(1_588_336_200, 250_000_000))
+            );
+            assert_eq!(
+                parse("2020-05-01T14:30:00+02:00"),
+                Some(timestamp(1_588_336_200, 0))
+            );
+            assert_eq!(
+                parse("2000-02-29T00:00:00Z"),
+                Some(timestamp(951_782_400, 0))
+            );
+
+            assert_eq!(parse("2001-02-29T00:00:00Z"), None);
+            assert_eq!(parse("2020-05-01T12:30:00"), None);
+            assert_eq!(parse("2020-05-01 12:30"), None);
+        }
+
+        #[test]
+        fn round_trips() {
+            for seconds in &[-86_401, 0, 1_600_000_000, 4_102_444_800] {
+                let value = timestamp(*seconds, 123_000_000);
+                assert_eq!(parse(&format(&value)), Some(value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat;
+
+    #[test]
+    fn oneofs_are_flattened_into_their_message() {
+        let notification: chat::OutgoingNotification = serde_json::from_str(
+            r#"{"to": {"id": "bob-id"}, "message": {"content": "hi", "timeSent": "2020-05-01T12:30:00Z"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(notification.to.unwrap().id, "bob-id");
+        match notification.types {
+            Some(chat::outgoing_notification::Types::Message(message)) => {
+                assert_eq!(message.content, "hi");
+                assert_eq!(message.time_sent.unwrap().seconds, 1_588_336_200);
+            }
+            types => panic!("unexpected types {:?}", types),
+        }
+
+        let notification = chat::IncomingNotification {
+            from: None,
+            types: Some(chat::incoming_notification::Types::ServerShutdown(
+                chat::incoming_notification::ServerShutdown {
+                    reason: String::from("maintenance"),
+                },
+            )),
+        };
+        assert_eq!(
+            serde_json::to_string(&notification).unwrap(),
+            r#"{"from":null,"serverShutdown":{"reason":"maintenance"}}"#
+        );
+    }
+}
@@ -1,3 +1,15 @@
+pub mod json;
+
 pub mod chat {
     tonic::include_proto!("chat");
 }
+
+pub mod google {
+    pub mod rpc {
+        tonic::include_proto!("google.rpc");
+    }
+}
+
+/// Encoded `FileDescriptorSet` of all chat protos, including their imports.
+pub const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/chat_descriptor.bin"));
@@ -0,0 +1,52 @@
+use super::{Backplane, BackplaneError, SUBSCRIPTION_CAPACITY};
+use proto::chat;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+/// Exchanges events between nodes running in the same process. Clones share their subscribers,
+/// so every node has to be given a clone of the same backplane.
+#[derive(Clone, Default)]
+pub struct MemoryBackplane {
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<chat::BackplaneEvent>>>>,
+}
+
+impl MemoryBackplane {
+    pub fn new() -> MemoryBackplane {
+        MemoryBackplane::default()
+    }
+}
+
+#[tonic::async_trait]
+impl Backplane for MemoryBackplane {
+    async fn publish(&self, event: &chat::BackplaneEvent) -> Result<(), BackplaneError> {
+        let mut subscribers = match self.subscribers.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(BackplaneError::Closed),
+        };
+
+        subscribers.retain(
+            |subscriber| match subscriber.clone().try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!("backplane subscriber is lagging, dropping event");
+                    true
+                }
+                Err(TrySendError::Closed(_)) => false,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<mpsc::Receiver<chat::BackplaneEvent>, BackplaneError> {
+        let (events_tx, events_rx) = mpsc::channel(SUBSCRIPTION_CAPACITY);
+
+        match self.subscribers.lock() {
+            Ok(mut subscribers) => subscribers.push(events_tx),
+            Err(_) => return Err(BackplaneError::Closed),
+        }
+
+        Ok(events_rx)
+    }
+}
@@ -0,0 +1,379 @@
+mod memory;
+mod redis;
+mod resp;
+#[cfg(test)]
+mod stand_in;
+
+pub use memory::MemoryBackplane;
+pub use redis::RedisBackplane;
+
+use crate::error::ChatError;
+use crate::metrics;
+use crate::search::{IndexedMessage, MessageIndex};
+use crate::UserList;
+use chat::backplane_event;
+use proto::chat;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Number of received events buffered for a subscriber.
+const SUBSCRIPTION_CAPACITY: usize = 1024;
+
+/// Publish/subscribe transport connecting the nodes of a chat server cluster.
+///
+/// Every published event is delivered to all subscribers, including the publishing node itself.
+#[tonic::async_trait]
+pub trait Backplane: Send + Sync {
+    async fn publish(&self, event: &chat::BackplaneEvent) -> Result<(), BackplaneError>;
+    async fn subscribe(&self) -> Result<mpsc::Receiver<chat::BackplaneEvent>, BackplaneError>;
+}
+
+#[derive(Debug)]
+pub enum BackplaneError {
+    /// The connection to the backplane failed.
+    Io(io::Error),
+    /// The backplane sent an unexpected reply.
+    Protocol(String),
+    /// The backplane URL is not supported.
+    InvalidUrl(String),
+    /// The backplane or the subscription was closed.
+    Closed,
+}
+
+impl fmt::Display for BackplaneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackplaneError::Io(err) => write!(f, "{}", err),
+            BackplaneError::Protocol(reason) => write!(f, "protocol error: {}", reason),
+            BackplaneError::InvalidUrl(url) => write!(f, "invalid backplane url {}", url),
+            BackplaneError::Closed => write!(f, "backplane closed"),
+        }
+    }
+}
+
+impl std::error::Error for BackplaneError {}
+
+impl From<io::Error> for BackplaneError {
+    fn from(err: io::Error) -> BackplaneError {
+        BackplaneError::Io(err)
+    }
+}
+
+/// This server's membership in the cluster. Routes notifications to users connected to other
+/// nodes and keeps the presence of remote users in the `UserList` up to date.
+pub struct Node {
+    id: String,
+    backplane: Box<dyn Backplane>,
+    users: Arc<Mutex<UserList>>,
+    index: Arc<Mutex<MessageIndex>>,
+}
+
+impl Node {
+    pub fn new(
+        backplane: Box<dyn Backplane>,
+        users: Arc<Mutex<UserList>>,
+        index: Arc<Mutex<MessageIndex>>,
+    ) -> Node {
+        Node {
+            id: Uuid::new_v4().to_hyphenated().to_string(),
+            backplane,
+            users,
+            index,
+        }
+    }
+
+    /// Starts handling the events of the other nodes and asks them for their users.
+    pub async fn join(node: &Arc<Node>) -> Result<(), BackplaneError> {
+        let mut events_rx = node.backplane.subscribe().await?;
+
+        let event_node = node.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events_rx.recv().await {
+                event_node.handle_event(event).await;
+            }
+
+            tracing::warn!("backplane subscription closed");
+        });
+
+        tracing::info!(node_id = %node.id, "joined backplane");
+
+        node.publish(backplane_event::Types::PresenceRequest(
+            backplane_event::PresenceRequest {},
+        ))
+        .await
+    }
+
+    /// Tells the other nodes that all users of this node are offline.
+    pub async fn leave(&self) {
+        let result = self
+            .publish(backplane_event::Types::NodeLeft(
+                backplane_event::NodeLeft {},
+            ))
+            .await;
+
+        if let Err(err) = result {
+            tracing::warn!("could not leave backplane: {}", err);
+        }
+    }
+
+    /// Tells the other nodes that a user of this node came online or went offline.
+    pub async fn publish_presence(&self, user: chat::User, is_online: bool) {
+        let user_id = user.id.clone();
+
+        let result = self
+            .publish(backplane_event::Types::Presence(
+                backplane_event::Presence {
+                    user: Some(user),
+                    is_online,
+                },
+            ))
+            .await;
+
+        if let Err(err) = result {
+            tracing::warn!(%user_id, is_online, "could not publish presence: {}", err);
+        }
+    }
+
+    /// Sends a notification to a user connected to another node.
+    pub async fn deliver(
+        &self,
+        to_user_id: String,
+        notification: chat::IncomingNotification,
+    ) -> Result<(), ChatError> {
+        let result = self
+            .publish(backplane_event::Types::Deliver(backplane_event::Deliver {
+                to_user_id,
+                notification: Some(notification),
+            }))
+            .await;
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(err) => Err(ChatError::BackplaneUnavailable(err.to_string())),
+        }
+    }
+
+    async fn publish(&self, types: backplane_event::Types) -> Result<(), BackplaneError> {
+        self.backplane
+            .publish(&chat::BackplaneEvent {
+                node_id: self.id.clone(),
+                types: Some(types),
+            })
+            .await
+    }
+
+    async fn handle_event(&self, event: chat::BackplaneEvent) {
+        // every node receives its own events as well
+        if event.node_id == self.id {
+            return;
+        }
+
+        let types = match event.types {
+            Some(types) => types,
+            None => return,
+        };
+
+        // users to announce, the lock must not be held while publishing
+        let mut local_users = vec![];
+
+        {
+            let mut users = match self.users.lock() {
+                Ok(guard) => guard,
+                Err(_) => {
+                    tracing::error!("unable to acquire lock, dropping backplane event");
+                    return;
+                }
+            };
+
+            match types {
+                backplane_event::Types::Deliver(deliver) => {
+                    if let Some(notification) = deliver.notification {
+                        self.deliver_local(&users, &deliver.to_user_id, notification);
+                    }
+                }
+                backplane_event::Types::Presence(presence) => {
+                    if let Some(user) = presence.user {
+                        users.set_remote_user_online(&event.node_id, user, presence.is_online);
+                    }
+                }
+                backplane_event::Types::PresenceRequest(_) => local_users = users.local_users(),
+                backplane_event::Types::NodeLeft(_) => users.remove_node(&event.node_id),
+            }
+        }
+
+        for user in local_users {
+            self.publish_presence(user, true).await;
+        }
+    }
+
+    fn deliver_local(
+        &self,
+        users: &UserList,
+        user_id: &str,
+        notification: chat::IncomingNotification,
+    ) {
+        // the user may have been connected to another node
+        let user = match users.get_user(user_id) {
+            Ok(user) => user,
+            Err(_) => return,
+        };
+
+        // messages are indexed on the nodes of both users, so both can search them
+        let indexed_message = match (&notification.from, &notification.types) {
+            (Some(from), Some(chat::incoming_notification::Types::Message(message))) => {
+                Some(IndexedMessage {
+                    message_id: message
+                        .message_id
+                        .as_ref()
+                        .map_or_else(String::new, |id| id.id.clone()),
+                    from: from.clone(),
+                    to: user.user_data.user(),
+                    time_sent: SystemTime::now(),
+                    content: message
+                        .message_content
+                        .as_ref()
+                        .map_or_else(String::new, |content| content.content.clone()),
+                    sequence_number: message.sequence_number,
+                })
+            }
+            _ => None,
+        };
+
+        if user.user_data.sender().try_send(notification).is_err() {
+            metrics::NOTIFICATIONS_DROPPED
+                .with_label_values(&["message"])
+                .inc();
+            tracing::warn!(%user_id, "could not deliver notification from backplane");
+            return;
+        }
+
+        if let Some(message) = indexed_message {
+            match self.index.lock() {
+                Ok(mut index) => index.add(message),
+                Err(_) => tracing::error!("unable to acquire lock, message not indexed"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user_list::NotificationReceiver;
+    use std::time::Duration;
+
+    async fn next(rx: &mut NotificationReceiver) -> chat::incoming_notification::Types {
+        let notification = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("no notification received")
+            .expect("notification channel closed");
+
+        notification.types.expect("notification without type")
+    }
+
+    async fn start_node(backplane: &MemoryBackplane) -> (Arc<Node>, Arc<Mutex<UserList>>) {
+        let users = Arc::new(Mutex::new(UserList::new()));
+        let node = Arc::new(Node::new(
+            Box::new(backplane.clone()),
+            users.clone(),
+            Arc::new(Mutex::new(MessageIndex::new(0))),
+        ));
+        Node::join(&node).await.unwrap();
+
+        (node, users)
+    }
+
+    fn login(users: &Arc<Mutex<UserList>>, name: &str) -> (chat::User, NotificationReceiver) {
+        let mut users = users.lock().unwrap();
+        let user = users.create_user(name).unwrap().user();
+        users.set_user_online(&user.id, true).unwrap();
+        let rx = users.take_user_receiver(&user.id).unwrap();
+
+        (user, rx)
+    }
+
+    fn message(content: &str) -> chat::IncomingNotification {
+        chat::IncomingNotification {
+            from: None,
+            types: Some(chat::incoming_notification::Types::Message(
+                chat::incoming_notification::Message {
+                    message_id: None,
+                    message_content: Some(chat::MessageContent {
+                        time_sent: None,
+                        content: String::from(content),
+                    }),
+                    sequence_number: 0,
+                },
+            )),
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_presence_and_notifications_between_nodes() {
+        let backplane = MemoryBackplane::new();
+        let (node_a, users_a) = start_node(&backplane).await;
+        let (node_b, users_b) = start_node(&backplane).await;
+
+        let (alice, mut alice_rx) = login(&users_a, "alice");
+        let (_bob, mut bob_rx) = login(&users_b, "bob");
+
+        node_a.publish_presence(alice.clone(), true).await;
+
+        match next(&mut bob_rx).await {
+            chat::incoming_notification::Types::Online(online) => assert!(online.is_online),
+            other => panic!("unexpected notification {:?}", other),
+        }
+        assert!(users_b.lock().unwrap().is_remote_user(&alice.id));
+
+        node_b
+            .deliver(alice.id.clone(), message("hello alice"))
+            .await
+            .unwrap();
+
+        match next(&mut alice_rx).await {
+            chat::incoming_notification::Types::Message(message) => {
+                assert_eq!(message.message_content.unwrap().content, "hello alice")
+            }
+            other => panic!("unexpected notification {:?}", other),
+        }
+
+        node_a.leave().await;
+
+        match next(&mut bob_rx).await {
+            chat::incoming_notification::Types::Online(online) => assert!(!online.is_online),
+            other => panic!("unexpected notification {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn joining_node_learns_existing_users() {
+        let backplane = MemoryBackplane::new();
+        let (_node_a, users_a) = start_node(&backplane).await;
+        let (alice, _alice_rx) = login(&users_a, "alice");
+
+        let users_b = Arc::new(Mutex::new(UserList::new()));
+        let (_bob, mut bob_rx) = login(&users_b, "bob");
+        let node_b = Arc::new(Node::new(
+            Box::new(backplane.clone()),
+            users_b.clone(),
+            Arc::new(Mutex::new(MessageIndex::new(0))),
+        ));
+        Node::join(&node_b).await.unwrap();
+
+        match next(&mut bob_rx).await {
+            chat::incoming_notification::Types::Online(online) => assert!(online.is_online),
+            other => panic!("unexpected notification {:?}", other),
+        }
+        assert!(users_b.lock().unwrap().is_remote_user(&alice.id));
+
+        // names are unique across the cluster
+        assert_eq!(
+            users_b.lock().unwrap().create_user("alice").err(),
+            Some(ChatError::UserAlreadyExists(String::from("alice")))
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use super::resp::{self, Value};
+use super::{Backplane, BackplaneError, SUBSCRIPTION_CAPACITY};
+use prost::Message;
+use proto::chat;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+
+/// Time to wait before a lost subscription connection is re-established.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Exchanges events over a Redis pub/sub channel. Works with Redis and every server speaking
+/// its protocol, e.g. KeyDB or Dragonfly.
+pub struct RedisBackplane {
+    addr: String,
+    channel: String,
+    connection: Mutex<Option<BufReader<TcpStream>>>,
+}
+
+impl RedisBackplane {
+    /// Creates a backplane for the server at `url`, e.g. `redis://127.0.0.1:6379`. Connections are
+    /// established lazily.
+    pub fn new(url: &str, channel: &str) -> Result<RedisBackplane, BackplaneError> {
+        let addr = match url.strip_prefix("redis://") {
+            Some(addr) => addr.trim_end_matches('/'),
+            None => return Err(BackplaneError::InvalidUrl(String::from(url))),
+        };
+
+        let addr = match addr.contains(':') {
+            true => String::from(addr),
+            false => format!("{}:6379", addr),
+        };
+
+        Ok(RedisBackplane {
+            addr,
+            channel: String::from(channel),
+            connection: Mutex::new(None),
+        })
+    }
+
+    async fn publish_payload(
+        connection: &mut Option<BufReader<TcpStream>>,
+        addr: &str,
+        command: &[u8],
+    ) -> Result<(), BackplaneError> {
+        if connection.is_none() {
+            *connection = Some(BufReader::new(TcpStream::connect(addr).await?));
+        }
+
+        let stream = match connection {
+            Some(stream) => stream,
+            None => return Err(BackplaneError::Closed),
+        };
+
+        stream.get_mut().write_all(command).await?;
+
+        match resp::read_value(stream).await? {
+            Value::Integer(_) => Ok(()),
+            Value::Error(err) => Err(BackplaneError::Protocol(err)),
+            value => Err(BackplaneError::Protocol(format!(
+                "unexpected reply {:?}",
+                value
+            ))),
+        }
+    }
+
+    async fn subscribe_connection(
+        addr: &str,
+        channel: &str,
+    ) -> Result<BufReader<TcpStream>, BackplaneError> {
+        let mut stream = BufReader::new(TcpStream::connect(addr).await?);
+
+        stream
+            .get_mut()
+            .write_all(&resp::command(&[b"SUBSCRIBE", channel.as_bytes()]))
+            .await?;
+
+        match resp::read_value(&mut stream).await? {
+            Value::Array(Some(reply))
+                if reply.first().and_then(Value::as_bytes) == Some(b"subscribe") =>
+            {
+                Ok(stream)
+            }
+            Value::Error(err) => Err(BackplaneError::Protocol(err)),
+            value => Err(BackplaneError::Protocol(format!(
+                "unexpected reply {:?}",
+                value
+            ))),
+        }
+    }
+
+    /// Forwards all messages received on the subscription connection until it fails.
+    async fn forward_messages(
+        stream: &mut BufReader<TcpStream>,
+        events_tx: &mut mpsc::Sender<chat::BackplaneEvent>,
+    ) -> Result<(), BackplaneError> {
+        loop {
+            let message = match resp::read_value(stream).await? {
+                Value::Array(Some(message)) => message,
+                value => {
+                    return Err(BackplaneError::Protocol(format!(
+                        "unexpected message {:?}",
+                        value
+                    )))
+                }
+            };
+
+            // messages are sent as [message, channel, payload]
+            if message.len() != 3 || message[0].as_bytes() != Some(b"message") {
+                continue;
+            }
+
+            let payload = match message[2].as_bytes() {
+                Some(payload) => payload,
+                None => continue,
+            };
+
+            match chat::BackplaneEvent::decode(payload) {
+                Ok(event) => {
+                    if events_tx.send(event).await.is_err() {
+                        return Err(BackplaneError::Closed);
+                    }
+                }
+                Err(err) => tracing::warn!("could not decode backplane event: {}", err),
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Backplane for RedisBackplane {
+    async fn publish(&self, event: &chat::BackplaneEvent) -> Result<(), BackplaneError> {
+        let mut payload = Vec::with_capacity(event.encoded_len());
+        if let Err(err) = event.encode(&mut payload) {
+            return Err(BackplaneError::Protocol(err.to_string()));
+        }
+
+        let command = resp::command(&[b"PUBLISH", self.channel.as_bytes(), &payload]);
+
+        let mut connection = self.connection.lock().await;
+        let result = RedisBackplane::publish_payload(&mut connection, &self.addr, &command).await;
+
+        // reconnect on the next publish
+        if result.is_err() {
+            *connection = None;
+        }
+
+        result
+    }
+
+    async fn subscribe(&self) -> Result<mpsc::Receiver<chat::BackplaneEvent>, BackplaneError> {
+        let mut stream = RedisBackplane::subscribe_connection(&self.addr, &self.channel).await?;
+        let (mut events_tx, events_rx) = mpsc::channel(SUBSCRIPTION_CAPACITY);
+
+        let addr = self.addr.clone();
+        let channel = self.channel.clone();
+
+        tokio::spawn(async move {
+            loop {
+                // only returns once the subscription or the receiver is gone
+                match RedisBackplane::forward_messages(&mut stream, &mut events_tx).await {
+                    Err(BackplaneError::Closed) | Ok(()) => return,
+                    Err(err) => tracing::warn!("backplane subscription lost: {}", err),
+                }
+
+                // events published while reconnecting are lost
+                stream = loop {
+                    tokio::time::delay_for(RECONNECT_DELAY).await;
+
+                    match RedisBackplane::subscribe_connection(&addr, &channel).await {
+                        Ok(stream) => break stream,
+                        Err(err) => tracing::warn!("could not resubscribe to backplane: {}", err),
+                    }
+                };
+
+                tracing::info!("backplane subscription re-established");
+            }
+        });
+
+        Ok(events_rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backplane::stand_in;
+    use chat::backplane_event;
+
+    fn presence_request(node_id: &str) -> chat::BackplaneEvent {
+        chat::BackplaneEvent {
+            node_id: String::from(node_id),
+            types: Some(backplane_event::Types::PresenceRequest(
+                backplane_event::PresenceRequest {},
+            )),
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_urls() {
+        assert!(RedisBackplane::new("nats://127.0.0.1:4222", "chat").is_err());
+        assert_eq!(
+            RedisBackplane::new("redis://localhost/", "chat")
+                .unwrap()
+                .addr,
+            "localhost:6379"
+        );
+    }
+
+    #[tokio::test]
+    async fn exchanges_events_between_nodes() {
+        let url = format!("redis://{}", stand_in::start().await);
+        let node_a = RedisBackplane::new(&url, "chat").unwrap();
+        let node_b = RedisBackplane::new(&url, "chat").unwrap();
+        let other_channel = RedisBackplane::new(&url, "other").unwrap();
+
+        let mut events_a = node_a.subscribe().await.unwrap();
+        let mut events_b = node_b.subscribe().await.unwrap();
+        let mut other_events = other_channel.subscribe().await.unwrap();
+
+        node_a.publish(&presence_request("a")).await.unwrap();
+        node_a.publish(&presence_request("a")).await.unwrap();
+
+        let timeout = Duration::from_secs(5);
+        for events in [&mut events_a, &mut events_b].iter_mut() {
+            for _ in 0..2 {
+                let event = tokio::time::timeout(timeout, events.recv()).await.unwrap();
+                assert_eq!(event, Some(presence_request("a")));
+            }
+        }
+
+        assert!(other_events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Minimal implementation of the Redis serialization protocol (RESP2).
+
+use futures::future::BoxFuture;
+use std::io;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Largest bulk string accepted, the maximum Redis allows.
+const MAX_BULK_LENGTH: i64 = 512 * 1024 * 1024;
+/// Largest number of elements accepted in an array.
+const MAX_ARRAY_LENGTH: i64 = 1024 * 1024;
+/// Number of array elements space is reserved for up front, larger arrays grow as they are read.
+const ARRAY_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Value>>),
+}
+
+impl Value {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Simple(value) => {
+                buf.push(b'+');
+                buf.extend_from_slice(value.as_bytes());
+            }
+            Value::Error(value) => {
+                buf.push(b'-');
+                buf.extend_from_slice(value.as_bytes());
+            }
+            Value::Integer(value) => {
+                buf.push(b':');
+                buf.extend_from_slice(value.to_string().as_bytes());
+            }
+            Value::Bulk(None) => buf.extend_from_slice(b"$-1"),
+            Value::Bulk(Some(value)) => {
+                buf.push(b'$');
+                buf.extend_from_slice(value.len().to_string().as_bytes());
+                buf.extend_from_slice(b"\r\n");
+                buf.extend_from_slice(value);
+            }
+            Value::Array(None) => buf.extend_from_slice(b"*-1"),
+            Value::Array(Some(values)) => {
+                buf.push(b'*');
+                buf.extend_from_slice(values.len().to_string().as_bytes());
+                buf.extend_from_slice(b"\r\n");
+                for value in values {
+                    value.encode(buf);
+                }
+                return;
+            }
+        }
+
+        buf.extend_from_slice(b"\r\n");
+    }
+
+    /// Returns the content of a bulk or simple string.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bulk(Some(value)) => Some(value),
+            Value::Simple(value) => Some(value.as_bytes()),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes a command as an array of bulk strings.
+pub fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut buf = vec![];
+
+    Value::Array(Some(
+        args.iter()
+            .map(|arg| Value::Bulk(Some(arg.to_vec())))
+            .collect(),
+    ))
+    .encode(&mut buf);
+
+    buf
+}
+
+/// Reads a single value, fails with `UnexpectedEof` if the connection was closed. Lengths sent
+/// by the server are only trusted up to `MAX_BULK_LENGTH` and `MAX_ARRAY_LENGTH`.
+pub fn read_value<'a, R>(reader: &'a mut R) -> BoxFuture<'a, io::Result<Value>>
+where
+    R: AsyncBufRead + Unpin + Send,
+{
+    Box::pin(async move {
+        let line = read_line(reader).await?;
+        let (kind, content) = match line.split_first() {
+            Some((kind, content)) => (*kind, content),
+            None => return Err(invalid_data("empty line")),
+        };
+
+        match kind {
+            b'+' => Ok(Value::Simple(String::from_utf8_lossy(content).into_owned())),
+            b'-' => Ok(Value::Error(String::from_utf8_lossy(content).into_owned())),
+            b':' => Ok(Value::Integer(parse_integer(content)?)),
+            b'$' => {
+                let len = parse_integer(content)?;
+                if len < 0 {
+                    return Ok(Value::Bulk(None));
+                }
+                if len > MAX_BULK_LENGTH {
+                    return Err(invalid_data("bulk string too long"));
+                }
+
+                // the content is followed by a line break
+                let mut value = vec![0; len as usize + 2];
+                reader.read_exact(&mut value).await?;
+                value.truncate(len as usize);
+
+                Ok(Value::Bulk(Some(value)))
+            }
+            b'*' => {
+                let len = parse_integer(content)?;
+                if len < 0 {
+                    return Ok(Value::Array(None));
+                }
+                if len > MAX_ARRAY_LENGTH {
+                    return Err(invalid_data("array too long"));
+                }
+
+                let mut values = Vec::with_capacity((len as usize).min(ARRAY_CAPACITY));
+                for _ in 0..len {
+                    values.push(read_value(reader).await?);
+                }
+
+                Ok(Value::Array(Some(values)))
+            }
+            _ => Err(invalid_data("unknown value type")),
+        }
+    })
+}
+
+async fn read_line<R>(reader: &mut R) -> io::Result<Vec<u8>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = vec![];
+    reader.read_until(b'\n', &mut line).await?;
+
+    if line.is_empty() {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+
+    if !line.ends_with(b"\r\n") {
+        return Err(invalid_data("line not terminated by CRLF"));
+    }
+
+    line.truncate(line.len() - 2);
+
+    Ok(line)
+}
+
+fn parse_integer(content: &[u8]) -> io::Result<i64> {
+    match std::str::from_utf8(content)
+        .ok()
+        .and_then(|v| v.parse().ok())
+    {
+        Some(value) => Ok(value),
+        None => Err(invalid_data("invalid integer")),
+    }
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn decodes_encoded_values() {
+        let value = Value::Array(Some(vec![
+            Value::Simple(String::from("OK")),
+            Value::Error(String::from("ERR failed")),
+            Value::Integer(-42),
+            Value::Bulk(Some(b"binary\r\n\0payload".to_vec())),
+            Value::Bulk(None),
+            Value::Array(None),
+        ]));
+
+        let mut buf = vec![];
+        value.encode(&mut buf);
+
+        assert_eq!(read_value(&mut buf.as_slice()).await.unwrap(), value);
+    }
+
+    #[tokio::test]
+    async fn fails_on_closed_connection() {
+        let err = read_value(&mut &b"*2\r\n:1\r\n"[..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn rejects_lengths_above_the_limits() {
+        let too_long = [
+            format!("${}\r\n", MAX_BULK_LENGTH + 1),
+            format!("*{}\r\n", MAX_ARRAY_LENGTH + 1),
+            format!("${}\r\n", i64::MAX),
+        ];
+
+        for line in &too_long {
+            let err = read_value(&mut line.as_bytes()).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", line);
+        }
+
+        // large arrays are read as far as they were sent
+        let line = format!("*{}\r\n:1\r\n", MAX_ARRAY_LENGTH);
+        let err = read_value(&mut line.as_bytes()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
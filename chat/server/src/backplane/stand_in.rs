@@ -0,0 +1,104 @@
+//! Minimal Redis stand-in supporting `SUBSCRIBE`, `PUBLISH` and `PING`, used to test the Redis
+//! backplane without a Redis server.
+
+use super::resp::{self, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+type Channels = Arc<Mutex<HashMap<Vec<u8>, Vec<mpsc::UnboundedSender<Vec<u8>>>>>>;
+
+/// Starts the stand-in on a random local port and returns its address.
+pub async fn start() -> SocketAddr {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let channels = Channels::default();
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle_connection(stream, channels.clone()));
+        }
+    });
+
+    addr
+}
+
+async fn handle_connection(stream: TcpStream, channels: Channels) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    // replies and published messages are written by a single task
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::spawn(async move {
+        while let Some(buf) = out_rx.recv().await {
+            if writer.write_all(&buf).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    loop {
+        let args: Vec<Vec<u8>> = match resp::read_value(&mut reader).await {
+            Ok(Value::Array(Some(args))) => args
+                .iter()
+                .filter_map(|arg| arg.as_bytes().map(<[u8]>::to_vec))
+                .collect(),
+            _ => return,
+        };
+
+        let command = match args.first() {
+            Some(command) => command.to_ascii_uppercase(),
+            None => return,
+        };
+
+        let mut replies = vec![];
+
+        match command.as_slice() {
+            b"PING" => replies.push(Value::Simple(String::from("PONG"))),
+            b"SUBSCRIBE" => {
+                let mut channels = channels.lock().unwrap();
+
+                for (count, channel) in args[1..].iter().enumerate() {
+                    channels
+                        .entry(channel.clone())
+                        .or_default()
+                        .push(out_tx.clone());
+
+                    replies.push(Value::Array(Some(vec![
+                        Value::Bulk(Some(b"subscribe".to_vec())),
+                        Value::Bulk(Some(channel.clone())),
+                        Value::Integer(count as i64 + 1),
+                    ])));
+                }
+            }
+            b"PUBLISH" if args.len() == 3 => {
+                let mut message = vec![];
+                Value::Array(Some(vec![
+                    Value::Bulk(Some(b"message".to_vec())),
+                    Value::Bulk(Some(args[1].clone())),
+                    Value::Bulk(Some(args[2].clone())),
+                ]))
+                .encode(&mut message);
+
+                let mut channels = channels.lock().unwrap();
+                let subscribers = channels.entry(args[1].clone()).or_default();
+                subscribers.retain(|subscriber| subscriber.send(message.clone()).is_ok());
+
+                replies.push(Value::Integer(subscribers.len() as i64));
+            }
+            _ => replies.push(Value::Error(String::from("ERR unknown command"))),
+        }
+
+        for reply in replies {
+            let mut buf = vec![];
+            reply.encode(&mut buf);
+
+            if out_tx.send(buf).is_err() {
+                return;
+            }
+        }
+    }
+}
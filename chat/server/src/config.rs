@@ -0,0 +1,350 @@
+use crate::backplane::{Backplane, BackplaneError, MemoryBackplane, RedisBackplane};
+use crate::rate_limiter::RateLimit;
+use crate::store::{FileStore, Store};
+use crate::validation::MessageLimits;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use server_common::config::CommonConfig;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Prefix of the environment variables overriding the configuration, e.g. `CHAT__LIMITS__SEND_RATE`.
+pub const ENV_PREFIX: &str = "CHAT";
+
+/// Configuration of the chat server, see `chat_server.toml` for all values and their defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub common: CommonConfig,
+    pub limits: LimitsConfig,
+    pub heartbeat: HeartbeatConfig,
+    pub search: SearchConfig,
+    pub storage: StorageConfig,
+    pub backplane: BackplaneConfig,
+    pub metrics: MetricsConfig,
+    pub web: WebConfig,
+    pub webhooks: WebhookConfig,
+    pub admin: AdminConfig,
+    pub shutdown: ShutdownConfig,
+    /// Sections neither `Config` nor `common` know, `deny_unknown_fields` doesn't work together
+    /// with `flatten` so they are collected here and rejected by `validate`.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+impl Config {
+    /// Rejects values the server can't run with, checked once at startup.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(section) = self.unknown.keys().next() {
+            return Err(format!("unknown section '{}'", section));
+        }
+
+        if let Err(err) = self.limits.send_rate_limit().validate() {
+            return Err(format!("invalid send rate limit: {}", err));
+        }
+
+        if let Err(err) = self.limits.auth_rate_limit().validate() {
+            return Err(format!("invalid auth rate limit: {}", err));
+        }
+
+        // users who answer every heartbeat would still be disconnected otherwise
+        let heartbeat = &self.heartbeat;
+        if heartbeat.interval > 0 && heartbeat.timeout <= heartbeat.interval {
+            return Err(format!(
+                "heartbeat timeout must be longer than the interval of {} seconds, got {}",
+                heartbeat.interval, heartbeat.timeout
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Number of messages per second a single user may send.
+    pub send_rate: f64,
+    /// Number of messages a single user may send in a burst.
+    pub send_burst: u32,
+    /// Number of authentications per second allowed from a single peer address.
+    pub auth_rate: f64,
+    /// Number of authentications allowed in a burst from a single peer address.
+    pub auth_burst: u32,
+    /// Maximum size of a single message in bytes.
+    pub max_message_length: usize,
+    /// Number of seconds a message's sent time may lie in the future.
+    pub max_clock_skew: u64,
+    /// Number of seconds after which a receive stream without any notification is closed, 0
+    /// keeps idle streams open.
+    pub receive_idle_timeout: u64,
+}
+
+impl LimitsConfig {
+    pub fn send_rate_limit(&self) -> RateLimit {
+        RateLimit {
+            rate: self.send_rate,
+            burst: self.send_burst,
+        }
+    }
+
+    pub fn auth_rate_limit(&self) -> RateLimit {
+        RateLimit {
+            rate: self.auth_rate,
+            burst: self.auth_burst,
+        }
+    }
+
+    pub fn message_limits(&self) -> MessageLimits {
+        MessageLimits {
+            max_content_length: self.max_message_length,
+            max_clock_skew: Duration::from_secs(self.max_clock_skew),
+        }
+    }
+
+    pub fn receive_idle_timeout(&self) -> Option<Duration> {
+        match self.receive_idle_timeout {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> LimitsConfig {
+        LimitsConfig {
+            send_rate: 10.0,
+            send_burst: 20,
+            auth_rate: 1.0,
+            auth_burst: 5,
+            max_message_length: 4096,
+            max_clock_skew: 300,
+            receive_idle_timeout: 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    /// Number of seconds between the heartbeats sent on every receive stream, 0 disables
+    /// heartbeats and the timeout.
+    pub interval: u64,
+    /// Number of seconds without any request after which a user is disconnected, has to be
+    /// longer than the interval.
+    pub timeout: u64,
+}
+
+impl HeartbeatConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> HeartbeatConfig {
+        HeartbeatConfig {
+            interval: 15,
+            timeout: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Number of attempts to deliver a notification to a bot before it is dead-lettered.
+    pub max_attempts: u32,
+    /// Number of milliseconds to wait before the first retry, doubled after every failed attempt.
+    pub initial_backoff: u64,
+    /// Maximum number of milliseconds to wait between two attempts.
+    pub max_backoff: u64,
+    /// Number of seconds to wait for a bot to answer a delivery.
+    pub timeout: u64,
+    /// File notifications which could not be delivered are appended to as JSON lines, they are
+    /// only logged if not set.
+    pub dead_letter_file: Option<PathBuf>,
+}
+
+impl WebhookConfig {
+    /// Returns the time to wait after the given failed attempt, starting at 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1_u64
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+
+        Duration::from_millis(
+            self.initial_backoff
+                .saturating_mul(factor)
+                .min(self.max_backoff),
+        )
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> WebhookConfig {
+        WebhookConfig {
+            max_attempts: 5,
+            initial_backoff: 500,
+            max_backoff: 30_000,
+            timeout: 10,
+            dead_letter_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    /// Number of most recent messages kept in the search index, 0 disables the index.
+    pub max_messages: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> SearchConfig {
+        SearchConfig {
+            max_messages: 100_000,
+        }
+    }
+}
+
+/// Where the server state is persisted on shutdown.
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase", deny_unknown_fields)]
+pub enum StorageConfig {
+    /// The state is not persisted.
+    #[default]
+    None,
+    /// The state is written to a single file.
+    File { path: PathBuf },
+}
+
+impl StorageConfig {
+    pub fn open(&self) -> Option<Box<dyn Store>> {
+        match self {
+            StorageConfig::None => None,
+            StorageConfig::File { path } => Some(Box::new(FileStore::new(path.clone()))),
+        }
+    }
+}
+
+/// How notifications and presence are exchanged with the other nodes of a cluster.
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase", deny_unknown_fields)]
+pub enum BackplaneConfig {
+    /// The server runs as a single node.
+    #[default]
+    Memory,
+    /// The nodes share a Redis pub/sub channel.
+    Redis {
+        url: String,
+        #[serde(default = "default_backplane_channel")]
+        channel: String,
+    },
+}
+
+pub fn default_backplane_channel() -> String {
+    String::from("chat")
+}
+
+impl BackplaneConfig {
+    pub fn open(&self) -> Result<Box<dyn Backplane>, BackplaneError> {
+        match self {
+            BackplaneConfig::Memory => Ok(Box::new(MemoryBackplane::new())),
+            BackplaneConfig::Redis { url, channel } => {
+                Ok(Box::new(RedisBackplane::new(url, channel)?))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Port on which Prometheus metrics are served at `/metrics`, disabled if not set.
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebConfig {
+    /// Port on which browser clients are served gRPC-Web and the JSON API over HTTP/1.1,
+    /// disabled if not set.
+    pub port: Option<u16>,
+    /// Origins of the web pages allowed to call the server, `*` allows every origin.
+    pub allowed_origins: Vec<String>,
+    /// Number of seconds browsers may cache the answer to a CORS preflight request.
+    pub cors_max_age: u64,
+}
+
+impl Default for WebConfig {
+    fn default() -> WebConfig {
+        WebConfig {
+            port: None,
+            allowed_origins: vec![],
+            cors_max_age: 600,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Token expected in the `admin_token` metadata of admin requests, the admin service is
+    /// disabled if not set.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// Number of seconds to wait for pending notifications to be delivered on shutdown.
+    pub timeout: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> ShutdownConfig {
+        ShutdownConfig { timeout: 10 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(Config::default().validate().is_ok());
+
+        let mut config = Config::default();
+        config.limits.send_burst = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.limits.auth_rate = f64::NAN;
+        assert!(config.validate().is_err());
+
+        let heartbeat = |interval, timeout| Config {
+            heartbeat: HeartbeatConfig { interval, timeout },
+            ..Config::default()
+        };
+
+        for (interval, timeout) in &[(15, 0), (15, 15), (15, 10)] {
+            let config = heartbeat(*interval, *timeout);
+            assert!(config.validate().is_err(), "{} {}", interval, timeout);
+        }
+
+        // the timeout doesn't matter without heartbeats
+        assert!(heartbeat(0, 0).validate().is_ok());
+    }
+}
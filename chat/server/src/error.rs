@@ -0,0 +1,239 @@
+use crate::util;
+use proto::google::rpc;
+use std::collections::HashMap;
+use std::fmt;
+use tonic::{Code, Status};
+
+/// Domain reported in the `google.rpc.ErrorInfo` details of all chat errors.
+const ERROR_DOMAIN: &str = "chat";
+
+/// Errors raised by the user registry and the chat services.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatError {
+    /// No user with the given id is registered.
+    UserNotFound(String),
+    /// A user with the given name is already registered.
+    UserAlreadyExists(String),
+    /// The request did not carry the named credential in its metadata.
+    MissingCredentials(&'static str),
+    /// The user id and token in the request metadata do not belong together.
+    InvalidCredentials,
+    /// The notifications of the given user are already being received by another stream.
+    ReceiverTaken(String),
+    /// The notification queue of the given user is full.
+    NotificationQueueFull(String),
+    /// The given user is not receiving notifications anymore.
+    RecipientUnavailable(String),
+    /// The server is shutting down and does not accept new sessions or streams.
+    ShuttingDown,
+    /// A notification could not be published to the other nodes of the backplane.
+    BackplaneUnavailable(String),
+    /// A lock guarding shared state was poisoned.
+    LockPoisoned,
+}
+
+impl ChatError {
+    pub fn code(&self) -> Code {
+        match self {
+            ChatError::UserNotFound(_) => Code::NotFound,
+            ChatError::UserAlreadyExists(_) => Code::AlreadyExists,
+            ChatError::MissingCredentials(_) | ChatError::InvalidCredentials => {
+                Code::Unauthenticated
+            }
+            ChatError::ReceiverTaken(_) => Code::FailedPrecondition,
+            ChatError::NotificationQueueFull(_) => Code::ResourceExhausted,
+            ChatError::RecipientUnavailable(_)
+            | ChatError::ShuttingDown
+            | ChatError::BackplaneUnavailable(_) => Code::Unavailable,
+            ChatError::LockPoisoned => Code::Internal,
+        }
+    }
+
+    /// Machine readable reason, reported as `google.rpc.ErrorInfo.reason`.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ChatError::UserNotFound(_) => "USER_NOT_FOUND",
+            ChatError::UserAlreadyExists(_) => "USER_ALREADY_EXISTS",
+            ChatError::MissingCredentials(_) => "MISSING_CREDENTIALS",
+            ChatError::InvalidCredentials => "INVALID_CREDENTIALS",
+            ChatError::ReceiverTaken(_) => "RECEIVER_TAKEN",
+            ChatError::NotificationQueueFull(_) => "NOTIFICATION_QUEUE_FULL",
+            ChatError::RecipientUnavailable(_) => "RECIPIENT_UNAVAILABLE",
+            ChatError::ShuttingDown => "SHUTTING_DOWN",
+            ChatError::BackplaneUnavailable(_) => "BACKPLANE_UNAVAILABLE",
+            ChatError::LockPoisoned => "INTERNAL",
+        }
+    }
+
+    fn metadata(&self) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+
+        match self {
+            ChatError::UserNotFound(user_id)
+            | ChatError::ReceiverTaken(user_id)
+            | ChatError::NotificationQueueFull(user_id)
+            | ChatError::RecipientUnavailable(user_id) => {
+                metadata.insert(String::from("user_id"), user_id.clone());
+            }
+            ChatError::UserAlreadyExists(name) => {
+                metadata.insert(String::from("name"), name.clone());
+            }
+            ChatError::MissingCredentials(key) => {
+                metadata.insert(String::from("metadata_key"), String::from(*key));
+            }
+            ChatError::InvalidCredentials
+            | ChatError::ShuttingDown
+            | ChatError::BackplaneUnavailable(_)
+            | ChatError::LockPoisoned => {}
+        }
+
+        metadata
+    }
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::UserNotFound(user_id) => write!(f, "user id {} not found", user_id),
+            ChatError::UserAlreadyExists(name) => write!(f, "user {} already exists", name),
+            ChatError::MissingCredentials(key) => write!(f, "no valid {} in metadata", key),
+            ChatError::InvalidCredentials => write!(f, "could not authenticate"),
+            ChatError::ReceiverTaken(user_id) => write!(
+                f,
+                "notifications of user {} are already being received",
+                user_id
+            ),
+            ChatError::NotificationQueueFull(user_id) => {
+                write!(f, "notification queue of user {} is full", user_id)
+            }
+            ChatError::RecipientUnavailable(user_id) => {
+                write!(f, "user {} is not receiving notifications", user_id)
+            }
+            ChatError::ShuttingDown => write!(f, "server is shutting down"),
+            ChatError::BackplaneUnavailable(reason) => {
+                write!(f, "backplane is unavailable: {}", reason)
+            }
+            ChatError::LockPoisoned => write!(f, "unable to acquire lock"),
+        }
+    }
+}
+
+impl std::error::Error for ChatError {}
+
+impl From<ChatError> for Status {
+    fn from(error: ChatError) -> Status {
+        let error_info = rpc::ErrorInfo {
+            reason: String::from(error.reason()),
+            domain: String::from(ERROR_DOMAIN),
+            metadata: error.metadata(),
+        };
+
+        util::status_with_details(
+            error.code(),
+            error.to_string(),
+            vec![util::to_any("google.rpc.ErrorInfo", &error_info)],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    fn metadata(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (String::from(*key), String::from(*value)))
+            .collect()
+    }
+
+    #[test]
+    fn errors_map_to_codes_and_error_info() {
+        let user_id = String::from("alice-id");
+        let with_user_id = metadata(&[("user_id", "alice-id")]);
+
+        let cases = vec![
+            (
+                ChatError::UserNotFound(user_id.clone()),
+                Code::NotFound,
+                "USER_NOT_FOUND",
+                with_user_id.clone(),
+            ),
+            (
+                ChatError::UserAlreadyExists(String::from("alice")),
+                Code::AlreadyExists,
+                "USER_ALREADY_EXISTS",
+                metadata(&[("name", "alice")]),
+            ),
+            (
+                ChatError::MissingCredentials("user_token"),
+                Code::Unauthenticated,
+                "MISSING_CREDENTIALS",
+                metadata(&[("metadata_key", "user_token")]),
+            ),
+            (
+                ChatError::InvalidCredentials,
+                Code::Unauthenticated,
+                "INVALID_CREDENTIALS",
+                metadata(&[]),
+            ),
+            (
+                ChatError::ReceiverTaken(user_id.clone()),
+                Code::FailedPrecondition,
+                "RECEIVER_TAKEN",
+                with_user_id.clone(),
+            ),
+            (
+                ChatError::NotificationQueueFull(user_id.clone()),
+                Code::ResourceExhausted,
+                "NOTIFICATION_QUEUE_FULL",
+                with_user_id.clone(),
+            ),
+            (
+                ChatError::RecipientUnavailable(user_id),
+                Code::Unavailable,
+                "RECIPIENT_UNAVAILABLE",
+                with_user_id,
+            ),
+            (
+                ChatError::ShuttingDown,
+                Code::Unavailable,
+                "SHUTTING_DOWN",
+                metadata(&[]),
+            ),
+            (
+                ChatError::BackplaneUnavailable(String::from("connection refused")),
+                Code::Unavailable,
+                "BACKPLANE_UNAVAILABLE",
+                metadata(&[]),
+            ),
+            (
+                ChatError::LockPoisoned,
+                Code::Internal,
+                "INTERNAL",
+                metadata(&[]),
+            ),
+        ];
+
+        for (error, code, reason, metadata) in cases {
+            let message = error.to_string();
+            let status = Status::from(error.clone());
+            assert_eq!(status.code(), code, "{:?}", error);
+            assert_eq!(status.message(), message);
+
+            let details = rpc::Status::decode(status.details()).unwrap();
+            assert_eq!(details.code, code as i32);
+            assert_eq!(details.details.len(), 1);
+            assert_eq!(
+                details.details[0].type_url,
+                "type.googleapis.com/google.rpc.ErrorInfo"
+            );
+
+            let error_info = rpc::ErrorInfo::decode(details.details[0].value.as_slice()).unwrap();
+            assert_eq!(error_info.reason, reason, "{:?}", error);
+            assert_eq!(error_info.domain, ERROR_DOMAIN);
+            assert_eq!(error_info.metadata, metadata, "{:?}", error);
+        }
+    }
+}
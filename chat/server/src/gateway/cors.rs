@@ -0,0 +1,167 @@
+use crate::config::WebConfig;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+
+/// Headers browsers may send with cross-origin requests, preflight requests asking for others
+/// are answered with these all the same.
+const ALLOWED_HEADERS: &str = "content-type, x-grpc-web, x-user-agent, grpc-timeout, user_id, \
+                               user_token";
+
+/// Response headers browser clients need to read, gRPC-Web sends the status of failed calls
+/// without a body as headers.
+const EXPOSED_HEADERS: &str = "grpc-status, grpc-message, grpc-status-details-bin";
+
+/// Decides which origins may call the gateway from a browser.
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allowed_methods: &'static str,
+    max_age: u64,
+}
+
+impl Cors {
+    pub fn new(config: &WebConfig, allowed_methods: &'static str) -> Cors {
+        Cors {
+            allowed_origins: config.allowed_origins.clone(),
+            allowed_methods,
+            max_age: config.cors_max_age,
+        }
+    }
+
+    /// Returns whether requests of the origin are allowed, requests without an origin are made by
+    /// the same origin or by clients other than browsers.
+    pub fn is_allowed(&self, origin: Option<&HeaderValue>) -> bool {
+        let origin = match origin {
+            Some(origin) => origin,
+            None => return true,
+        };
+
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || origin.as_bytes() == allowed.as_bytes())
+    }
+
+    /// Answers a preflight request, forbidden if the origin isn't allowed.
+    pub fn preflight(&self, request: &Request<Body>) -> Response<Body> {
+        let origin = request.headers().get(header::ORIGIN);
+        if origin.is_none() || !self.is_allowed(origin) {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            return response;
+        }
+
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+
+        let headers = response.headers_mut();
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static(self.allowed_methods),
+        );
+
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_static(ALLOWED_HEADERS),
+        );
+
+        if let Ok(max_age) = HeaderValue::from_str(&self.max_age.to_string()) {
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age);
+        }
+
+        response
+    }
+
+    /// Adds the headers allowing the origin of the request to read the response.
+    pub fn apply(&self, origin: Option<&HeaderValue>, headers: &mut HeaderMap) {
+        let origin = match origin {
+            Some(origin) if self.is_allowed(Some(origin)) => origin,
+            _ => return,
+        };
+
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSED_HEADERS),
+        );
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowing(allowed_origins: &[&str]) -> Cors {
+        let config = WebConfig {
+            allowed_origins: allowed_origins.iter().map(|v| String::from(*v)).collect(),
+            ..WebConfig::default()
+        };
+
+        Cors::new(&config, "POST, OPTIONS")
+    }
+
+    fn preflight_request(origin: &str) -> Request<Body> {
+        Request::builder()
+            .method("OPTIONS")
+            .header(header::ORIGIN, origin)
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "content-type, cookie",
+            )
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn only_configured_origins_are_allowed() {
+        let cors = allowing(&["https://chat.example.com"]);
+
+        assert!(cors.is_allowed(None));
+        assert!(cors.is_allowed(Some(&HeaderValue::from_static("https://chat.example.com"))));
+        assert!(!cors.is_allowed(Some(&HeaderValue::from_static("https://evil.example.com"))));
+
+        let any = allowing(&["*"]);
+        assert!(any.is_allowed(Some(&HeaderValue::from_static("https://any.example.com"))));
+    }
+
+    #[test]
+    fn preflight_of_allowed_origin_succeeds() {
+        let cors = allowing(&["https://chat.example.com"]);
+
+        let response = cors.preflight(&preflight_request("https://chat.example.com"));
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "POST, OPTIONS"
+        );
+        // the requested headers aren't echoed
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            ALLOWED_HEADERS
+        );
+
+        let response = cors.preflight(&preflight_request("https://evil.example.com"));
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn responses_are_readable_by_allowed_origins_only() {
+        let cors = allowing(&["https://chat.example.com"]);
+
+        let mut headers = HeaderMap::new();
+        cors.apply(
+            Some(&HeaderValue::from_static("https://chat.example.com")),
+            &mut headers,
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://chat.example.com"
+        );
+
+        let mut headers = HeaderMap::new();
+        cors.apply(
+            Some(&HeaderValue::from_static("https://evil.example.com")),
+            &mut headers,
+        );
+        assert!(headers.is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+//! Translates the gRPC-Web calls of browsers into gRPC calls of the services and back, see
+//! https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md. Browsers can only make unary and
+//! server streaming calls.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::stream::{self, StreamExt};
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, Version};
+use std::convert::Infallible;
+use tonic::body::BoxBody;
+use tonic::Status;
+
+/// Maximum size of a request body, browsers send a single message per call.
+const MAX_REQUEST_SIZE: usize = 1 << 20;
+
+/// Flag of the frame carrying the trailers at the end of a response body.
+const TRAILER_FLAG: u8 = 0x80;
+
+/// How the frames of a call are encoded in the HTTP bodies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// The frames are sent as they are.
+    Binary,
+    /// The frames are base64 encoded, for clients which can't read binary streams.
+    Text,
+}
+
+impl Encoding {
+    /// Returns the encoding of a gRPC-Web request, `None` if it isn't one.
+    pub fn from_content_type(content_type: Option<&HeaderValue>) -> Option<Encoding> {
+        let content_type = content_type?.to_str().ok()?;
+
+        match content_type.split(';').next()?.trim() {
+            "application/grpc-web" | "application/grpc-web+proto" => Some(Encoding::Binary),
+            "application/grpc-web-text" | "application/grpc-web-text+proto" => Some(Encoding::Text),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Binary => "application/grpc-web+proto",
+            Encoding::Text => "application/grpc-web-text+proto",
+        }
+    }
+
+    fn decode(self, body: Bytes) -> Result<Bytes, Status> {
+        match self {
+            Encoding::Binary => Ok(body),
+            Encoding::Text => decode_base64(&body),
+        }
+    }
+
+    fn encode(self, frame: Bytes) -> Bytes {
+        match self {
+            Encoding::Binary => frame,
+            Encoding::Text => Bytes::from(base64::encode(&frame)),
+        }
+    }
+}
+
+/// Decodes a base64 body, which clients may send as several separately padded chunks.
+fn decode_base64(body: &[u8]) -> Result<Bytes, Status> {
+    let body: Vec<u8> = body
+        .iter()
+        .copied()
+        .filter(|v| !v.is_ascii_whitespace())
+        .collect();
+
+    if !body.len().is_multiple_of(4) {
+        return Err(Status::invalid_argument("request body is not valid base64"));
+    }
+
+    // every group of 4 characters decodes on its own, no matter where the chunks were padded
+    let mut decoded = Vec::with_capacity(body.len() / 4 * 3);
+    for group in body.chunks(4) {
+        if base64::decode_config_buf(group, base64::STANDARD, &mut decoded).is_err() {
+            return Err(Status::invalid_argument("request body is not valid base64"));
+        }
+    }
+
+    Ok(Bytes::from(decoded))
+}
+
+/// Turns a gRPC-Web request into the gRPC request a service expects.
+pub async fn into_grpc_request(
+    request: Request<Body>,
+    encoding: Encoding,
+) -> Result<Request<Body>, Status> {
+    let (mut parts, mut body) = request.into_parts();
+
+    let mut buffer = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => return Err(Status::cancelled(format!("request body failed: {}", err))),
+        };
+
+        if buffer.len() + chunk.len() > MAX_REQUEST_SIZE {
+            return Err(Status::resource_exhausted("request body is too large"));
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+
+    let body = encoding.decode(buffer.freeze())?;
+
+    parts.version = Version::HTTP_2;
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/grpc"),
+    );
+    parts
+        .headers
+        .insert(header::TE, HeaderValue::from_static("trailers"));
+
+    Ok(Request::from_parts(parts, Body::from(body)))
+}
+
+/// Turns the response of a service into a gRPC-Web response, its trailers are sent as the last
+/// frame of the body.
+pub fn from_grpc_response(response: Response<BoxBody>, encoding: Encoding) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+
+    parts.version = Version::HTTP_11;
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(encoding.content_type()),
+    );
+
+    let frames = stream::unfold(Some(body), |body| async move {
+        let mut body = body?;
+
+        match body.data().await {
+            Some(Ok(data)) => Some((data, Some(body))),
+            Some(Err(status)) => Some((trailer_frame(&status_headers(&status)), None)),
+            None => match body.trailers().await {
+                Ok(Some(trailers)) if !trailers.is_empty() => {
+                    Some((trailer_frame(&trailers), None))
+                }
+                // the status has been sent as headers
+                Ok(_) => None,
+                Err(status) => Some((trailer_frame(&status_headers(&status)), None)),
+            },
+        }
+    });
+
+    let frames = frames.map(move |frame| Ok::<_, Infallible>(encoding.encode(frame)));
+
+    Response::from_parts(parts, Body::wrap_stream(frames))
+}
+
+/// Returns the response of a call which failed before it reached a service, the status is sent
+/// as headers without a body.
+pub fn error_response(status: &Status, encoding: Encoding) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(encoding.content_type()),
+    );
+    headers.extend(status_headers(status));
+
+    response
+}
+
+fn status_headers(status: &Status) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    headers.insert("grpc-status", HeaderValue::from(status.code() as i32));
+    if let Ok(message) = HeaderValue::from_str(&percent_encode(status.message())) {
+        headers.insert("grpc-message", message);
+    }
+
+    headers
+}
+
+/// Percent encodes a status message as gRPC requires for the grpc-message header.
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+
+    for byte in message.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+fn trailer_frame(trailers: &HeaderMap) -> Bytes {
+    let mut block = Vec::new();
+    for (name, value) in trailers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+
+    let mut frame = BytesMut::with_capacity(5 + block.len());
+    frame.put_u8(TRAILER_FLAG);
+    frame.put_u32(block.len() as u32);
+    frame.extend_from_slice(&block);
+
+    frame.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_grpc_web_content_types() {
+        let encoding = |content_type| {
+            Encoding::from_content_type(Some(&HeaderValue::from_static(content_type)))
+        };
+
+        assert_eq!(encoding("application/grpc-web"), Some(Encoding::Binary));
+        assert_eq!(
+            encoding("application/grpc-web+proto"),
+            Some(Encoding::Binary)
+        );
+        assert_eq!(
+            encoding("application/grpc-web-text; charset=utf-8"),
+            Some(Encoding::Text)
+        );
+        assert_eq!(encoding("application/grpc"), None);
+        assert_eq!(Encoding::from_content_type(None), None);
+    }
+
+    #[test]
+    fn decodes_separately_padded_base64_chunks() {
+        let body = format!("{}{}", base64::encode(b"hello"), base64::encode(b" world"));
+
+        assert_eq!(
+            decode_base64(body.as_bytes()).unwrap(),
+            Bytes::from("hello world")
+        );
+        assert!(decode_base64(b"abc").is_err());
+    }
+
+    #[test]
+    fn trailers_are_sent_as_last_frame() {
+        let frame = trailer_frame(&status_headers(&Status::not_found("no 100% match")));
+
+        assert_eq!(frame[0], TRAILER_FLAG);
+        assert_eq!(
+            u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]) as usize,
+            frame.len() - 5
+        );
+        assert_eq!(
+            &frame[5..],
+            &b"grpc-status: 5\r\ngrpc-message: no 100%25 match\r\n"[..]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Serves browser clients, which can't make gRPC calls over HTTP/2, on a port of its own. Their
+//! gRPC-Web calls and the requests of the JSON API are handed to the authentication and chat
+//! services in-process.
+
+mod cors;
+mod grpc_web;
+mod rest;
+#[cfg(test)]
+mod tests;
+
+use crate::config::WebConfig;
+use crate::rate_limiter::RateLimiter;
+use crate::services::{AuthenticationService, ChatService};
+use cors::Cors;
+use grpc_web::Encoding;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use proto::chat::authentication_service_server::AuthenticationServiceServer;
+use proto::chat::chat_service_server::ChatServiceServer;
+use rest::Sessions;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower_service::Service;
+use tracing::Instrument;
+
+/// Path of the call creating users, limited per peer address like gRPC clients are.
+const AUTHENTICATE_PATH: &str = "/chat.AuthenticationService/Authenticate";
+
+const AUTHENTICATION_SERVICE_PREFIX: &str = "/chat.AuthenticationService/";
+const CHAT_SERVICE_PREFIX: &str = "/chat.ChatService/";
+
+pub struct Gateway {
+    authentication_service: AuthenticationServiceServer<AuthenticationService>,
+    chat_service: ChatServiceServer<ChatService>,
+    auth_rate_limiter: Arc<RateLimiter<IpAddr>>,
+    cors: Cors,
+    sessions: Sessions,
+}
+
+impl Gateway {
+    pub fn new(
+        config: &WebConfig,
+        authentication_service: AuthenticationServiceServer<AuthenticationService>,
+        chat_service: ChatServiceServer<ChatService>,
+        auth_rate_limiter: Arc<RateLimiter<IpAddr>>,
+    ) -> Gateway {
+        Gateway {
+            authentication_service,
+            chat_service,
+            auth_rate_limiter,
+            cors: Cors::new(config, "GET, POST, DELETE, OPTIONS"),
+            sessions: Sessions::default(),
+        }
+    }
+
+    async fn handle(&self, peer: SocketAddr, request: Request<Body>) -> Response<Body> {
+        if request.method() == Method::OPTIONS {
+            return self.cors.preflight(&request);
+        }
+
+        if !self.cors.is_allowed(request.headers().get(header::ORIGIN)) {
+            return plain_response(StatusCode::FORBIDDEN, "origin is not allowed");
+        }
+
+        if request.uri().path().starts_with(rest::API_PREFIX) {
+            return self.call_rest(peer, request).await;
+        }
+
+        match Encoding::from_content_type(request.headers().get(header::CONTENT_TYPE)) {
+            Some(encoding) => self.call_grpc_web(peer, request, encoding).await,
+            None => plain_response(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    async fn call_grpc_web(
+        &self,
+        peer: SocketAddr,
+        request: Request<Body>,
+        encoding: Encoding,
+    ) -> Response<Body> {
+        if request.method() != Method::POST {
+            return plain_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+        }
+
+        let path = String::from(request.uri().path());
+
+        // the services don't see the peer address of gateway requests
+        if path == AUTHENTICATE_PATH {
+            if let Err(status) = self.auth_rate_limiter.check(peer.ip()) {
+                return grpc_web::error_response(&status, encoding);
+            }
+        }
+
+        let request = match grpc_web::into_grpc_request(request, encoding).await {
+            Ok(request) => request,
+            Err(status) => return grpc_web::error_response(&status, encoding),
+        };
+
+        let response = if path.starts_with(AUTHENTICATION_SERVICE_PREFIX) {
+            call(self.authentication_service.clone(), request).await
+        } else if path.starts_with(CHAT_SERVICE_PREFIX) {
+            call(self.chat_service.clone(), request).await
+        } else {
+            let status = Status::unimplemented(format!("{} is not served to browsers", path));
+            return grpc_web::error_response(&status, encoding);
+        };
+
+        grpc_web::from_grpc_response(response, encoding)
+    }
+}
+
+async fn call<S>(mut service: S, request: Request<Body>) -> Response<BoxBody>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>, Error = tonic::codegen::Never>,
+{
+    let result = match futures::future::poll_fn(|cx| service.poll_ready(cx)).await {
+        Ok(()) => service.call(request).await,
+        Err(never) => Err(never),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+fn plain_response(status: StatusCode, message: &'static str) -> Response<Body> {
+    let mut response = Response::new(Body::from(message));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("text/plain"),
+    );
+
+    response
+}
+
+/// Serves the gateway on the incoming connections until `signal` resolves, all responses carry
+/// the CORS headers of the request's origin.
+pub async fn serve(
+    gateway: Gateway,
+    incoming: AddrIncoming,
+    signal: impl Future<Output = ()>,
+) -> Result<(), hyper::Error> {
+    let gateway = Arc::new(gateway);
+
+    tracing::info!("web gateway listening on {}", incoming.local_addr());
+
+    let make_service = make_service_fn(move |connection: &AddrStream| {
+        let gateway = gateway.clone();
+        let peer = connection.remote_addr();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let gateway = gateway.clone();
+                let span = tracing::info_span!(
+                    "gateway",
+                    peer = %peer,
+                    path = %request.uri().path(),
+                );
+
+                async move {
+                    let origin = request.headers().get(header::ORIGIN).cloned();
+
+                    let mut response = gateway.handle(peer, request).await;
+                    gateway.cors.apply(origin.as_ref(), response.headers_mut());
+                    tracing::debug!(status = %response.status(), "request handled");
+
+                    Ok::<_, Infallible>(response)
+                }
+                .instrument(span)
+            }))
+        }
+    });
+
+    Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(signal)
+        .await
+}
@@ -0,0 +1,661 @@
+//! A JSON API for clients without gRPC support, served under `/api/v1/`. Requests are turned into
+//! calls of the services, their messages are mapped to JSON as configured in the proto crate, so
+//! fields are lowerCamelCase and timestamps are RFC 3339 strings.
+//!
+//! | Request                                | Call                                               |
+//! |----------------------------------------|----------------------------------------------------|
+//! | `POST /api/v1/login`                   | `Authenticate`, returns the credentials            |
+//! | `DELETE /api/v1/session`               | ends the session opened by the login               |
+//! | `POST /api/v1/messages`                | `Send`                                             |
+//! | `GET /api/v1/messages?query=`          | `Search`, with the fields of `SearchRequest`       |
+//! | `GET /api/v1/messages?conversationId=` | `Search` without a query, the history newest first |
+//! | `GET /api/v1/users`                    | `ListUsers`                                        |
+//! | `POST /api/v1/heartbeat`               | `Heartbeat`                                        |
+//! | `GET /api/v1/notifications`            | `Receive`, as server-sent events                   |
+//!
+//! The credentials are passed in the `user_id` and `user_token` headers. `EventSource` can't send
+//! headers, so they may also be passed as query parameters. Failed requests are answered with
+//! `{"code": .., "message": ..}`, the gRPC status code mapped to an HTTP status.
+
+use super::{call, Gateway, AUTHENTICATE_PATH};
+use crate::error::ChatError;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::future;
+use futures::stream::{self, StreamExt};
+use hyper::body::HttpBody;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri, Version};
+use percent_encoding::percent_decode;
+use proto::chat::{self, incoming_notification};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tonic::body::BoxBody;
+use tonic::{Code, Status};
+use tracing::Instrument;
+
+pub const API_PREFIX: &str = "/api/v1/";
+
+/// Maximum size of a request body.
+const MAX_REQUEST_SIZE: usize = 1 << 16;
+
+const SEND_PATH: &str = "/chat.ChatService/Send";
+const RECEIVE_PATH: &str = "/chat.ChatService/Receive";
+const HEARTBEAT_PATH: &str = "/chat.ChatService/Heartbeat";
+const SEARCH_PATH: &str = "/chat.ChatService/Search";
+const LIST_USERS_PATH: &str = "/chat.ChatService/ListUsers";
+
+const CREDENTIALS: &[&str] = &["user_id", "user_token"];
+
+/// The sessions of the users who logged in through the API. The authentication service logs out
+/// a user once their session ends, which happens on logout or when the service ends it.
+#[derive(Default)]
+pub struct Sessions {
+    logouts: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
+}
+
+impl Sessions {
+    /// Keeps the session open until the user logs out.
+    fn open(
+        &self,
+        user_id: &str,
+        mut session: GrpcResponse<chat::AuthenticateResponse>,
+    ) -> Result<(), Status> {
+        let (logout_tx, logout_rx) = oneshot::channel();
+
+        match self.logouts.lock() {
+            Ok(mut logouts) => logouts.insert(String::from(user_id), logout_tx),
+            Err(_) => return Err(ChatError::LockPoisoned.into()),
+        };
+
+        let logouts = self.logouts.clone();
+        let user_id = String::from(user_id);
+        let span = tracing::info_span!("api_session", user_id = %user_id);
+
+        tokio::spawn(
+            async move {
+                let ended =
+                    Box::pin(async move { while let Ok(Some(_)) = session.next().await {} });
+                future::select(ended, logout_rx).await;
+
+                if let Ok(mut logouts) = logouts.lock() {
+                    logouts.remove(&user_id);
+                }
+                tracing::debug!("session closed");
+            }
+            .instrument(span),
+        );
+
+        Ok(())
+    }
+
+    /// Ends the session of the user, returns false if they didn't log in through the API.
+    fn close(&self, user_id: &str) -> Result<bool, Status> {
+        let logout_tx = match self.logouts.lock() {
+            Ok(mut logouts) => logouts.remove(user_id),
+            Err(_) => return Err(ChatError::LockPoisoned.into()),
+        };
+
+        match logout_tx {
+            Some(logout_tx) => Ok(logout_tx.send(()).is_ok()),
+            None => Ok(false),
+        }
+    }
+}
+
+impl Gateway {
+    pub(super) async fn call_rest(
+        &self,
+        peer: SocketAddr,
+        request: Request<Body>,
+    ) -> Response<Body> {
+        let route = String::from(&request.uri().path()[API_PREFIX.len()..]);
+
+        let response = match (request.method(), route.as_str()) {
+            (&Method::POST, "login") => self.login(peer, request).await,
+            (&Method::DELETE, "session") => self.logout(request).await,
+            (&Method::POST, "messages") => self.send(request).await,
+            (&Method::GET, "messages") => self.search(request).await,
+            (&Method::GET, "users") => self.list_users(request).await,
+            (&Method::POST, "heartbeat") => self.heartbeat(request).await,
+            (&Method::GET, "notifications") => self.notifications(request).await,
+            (_, "login")
+            | (_, "session")
+            | (_, "messages")
+            | (_, "users")
+            | (_, "heartbeat")
+            | (_, "notifications") => {
+                let status = Status::unimplemented(format!("method not allowed on {}", route));
+                let mut response = error_response(&status);
+                *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                return response;
+            }
+            _ => Err(Status::not_found(format!("no such resource {}", route))),
+        };
+
+        match response {
+            Ok(response) => response,
+            Err(status) => error_response(&status),
+        }
+    }
+
+    async fn login(
+        &self,
+        peer: SocketAddr,
+        request: Request<Body>,
+    ) -> Result<Response<Body>, Status> {
+        // the services don't see the peer address of gateway requests
+        self.auth_rate_limiter.check(peer.ip())?;
+
+        let message: chat::AuthenticateRequest = read_json(request).await?;
+        let request = grpc_request(AUTHENTICATE_PATH, &HeaderMap::new(), &message);
+
+        let mut session =
+            GrpcResponse::new(call(self.authentication_service.clone(), request).await)?;
+
+        let credentials: chat::AuthenticateResponse = match session.next().await? {
+            Some(credentials) => credentials,
+            None => {
+                return Err(Status::internal(
+                    "session ended before credentials were sent",
+                ))
+            }
+        };
+
+        self.sessions.open(&credentials.id, session)?;
+
+        Ok(json_response(&credentials))
+    }
+
+    async fn logout(&self, request: Request<Body>) -> Result<Response<Body>, Status> {
+        let credentials = credentials(&request);
+
+        // only the user may end their session
+        let request = grpc_request(HEARTBEAT_PATH, &credentials, &chat::HeartbeatRequest {});
+        let _: chat::HeartbeatResponse = self.unary(request).await?;
+
+        let user_id = match credentials.get("user_id").map(|v| v.to_str()) {
+            Some(Ok(user_id)) => user_id,
+            _ => return Err(ChatError::MissingCredentials("user_id").into()),
+        };
+
+        match self.sessions.close(user_id)? {
+            true => Ok(empty_response()),
+            false => Err(Status::failed_precondition(
+                "the user didn't log in through this API",
+            )),
+        }
+    }
+
+    async fn send(&self, request: Request<Body>) -> Result<Response<Body>, Status> {
+        let credentials = credentials(&request);
+        let message: chat::SendRequest = read_json(request).await?;
+
+        let request = grpc_request(SEND_PATH, &credentials, &message);
+        let response: chat::SendResponse = self.unary(request).await?;
+
+        Ok(json_response(&response))
+    }
+
+    async fn search(&self, request: Request<Body>) -> Result<Response<Body>, Status> {
+        let message = search_request(request.uri())?;
+
+        let request = grpc_request(SEARCH_PATH, &credentials(&request), &message);
+        let response: chat::SearchResponse = self.unary(request).await?;
+
+        Ok(json_response(&response))
+    }
+
+    async fn list_users(&self, request: Request<Body>) -> Result<Response<Body>, Status> {
+        let request = grpc_request(
+            LIST_USERS_PATH,
+            &credentials(&request),
+            &chat::ListUsersRequest {},
+        );
+        let response: chat::ListUsersResponse = self.unary(request).await?;
+
+        Ok(json_response(&response))
+    }
+
+    async fn heartbeat(&self, request: Request<Body>) -> Result<Response<Body>, Status> {
+        let request = grpc_request(
+            HEARTBEAT_PATH,
+            &credentials(&request),
+            &chat::HeartbeatRequest {},
+        );
+        let _: chat::HeartbeatResponse = self.unary(request).await?;
+
+        Ok(empty_response())
+    }
+
+    /// Streams the incoming notifications as server-sent events named after their type, e.g.
+    /// `message` or `server_shutdown`. A failed stream ends with a `disconnected` event carrying
+    /// the reason.
+    async fn notifications(&self, request: Request<Body>) -> Result<Response<Body>, Status> {
+        let request = grpc_request(
+            RECEIVE_PATH,
+            &credentials(&request),
+            &chat::ReceiveRequest {},
+        );
+        let notifications: GrpcResponse<chat::ReceiveResponse> =
+            GrpcResponse::new(call(self.chat_service.clone(), request).await)?;
+
+        let events = stream::unfold(Some(notifications), |notifications| async move {
+            let mut notifications = notifications?;
+
+            match notifications.next().await {
+                Ok(Some(response)) => Some((response.notification, Some(notifications))),
+                Ok(None) => None,
+                Err(status) => {
+                    let disconnected = incoming_notification::Types::Disconnected(
+                        incoming_notification::Disconnected {
+                            reason: String::from(status.message()),
+                        },
+                    );
+                    tracing::debug!(%status, "notification stream failed");
+                    Some((Some(without_sender(disconnected)), None))
+                }
+            }
+        });
+
+        let events = events.filter_map(|notification| {
+            future::ready(notification.map(|v| Ok::<_, Infallible>(server_sent_event(&v))))
+        });
+
+        let mut response = Response::new(Body::wrap_stream(events));
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/event-stream"),
+        );
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+        Ok(response)
+    }
+
+    /// Makes a unary call of the chat service.
+    async fn unary<M>(&self, request: Request<Body>) -> Result<M, Status>
+    where
+        M: prost::Message + Default,
+    {
+        let mut response = GrpcResponse::new(call(self.chat_service.clone(), request).await)?;
+
+        let message = match response.next().await? {
+            Some(message) => message,
+            None => return Err(Status::internal("call ended without a response")),
+        };
+
+        // reads the status from the trailers
+        response.next().await?;
+
+        Ok(message)
+    }
+}
+
+/// Returns a notification of the server rather than of a user.
+fn without_sender(types: incoming_notification::Types) -> chat::IncomingNotification {
+    chat::IncomingNotification {
+        from: None,
+        types: Some(types),
+    }
+}
+
+fn server_sent_event(notification: &chat::IncomingNotification) -> Bytes {
+    let event = match &notification.types {
+        Some(incoming_notification::Types::Delivered(_)) => "delivered",
+        Some(incoming_notification::Types::Read(_)) => "read",
+        Some(incoming_notification::Types::Typing(_)) => "typing",
+        Some(incoming_notification::Types::Online(_)) => "online",
+        Some(incoming_notification::Types::Message(_)) => "message",
+        Some(incoming_notification::Types::ServerShutdown(_)) => "server_shutdown",
+        Some(incoming_notification::Types::Disconnected(_)) => "disconnected",
+        Some(incoming_notification::Types::SystemMessage(_)) => "system_message",
+        Some(incoming_notification::Types::Heartbeat(_)) => "heartbeat",
+        None => "notification",
+    };
+
+    // JSON without pretty printing contains no line breaks
+    let data = match serde_json::to_string(notification) {
+        Ok(data) => data,
+        Err(err) => {
+            tracing::error!("could not serialize notification: {}", err);
+            String::from("{}")
+        }
+    };
+
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+/// The response of a call of a service, its messages are read one by one.
+struct GrpcResponse<M> {
+    body: BoxBody,
+    buffer: BytesMut,
+    message: PhantomData<M>,
+}
+
+impl<M: prost::Message + Default> GrpcResponse<M> {
+    /// Fails if the call failed right away, its status is then sent as headers.
+    fn new(response: Response<BoxBody>) -> Result<GrpcResponse<M>, Status> {
+        if let Some(status) = status_from_headers(response.headers()) {
+            if status.code() != Code::Ok {
+                return Err(status);
+            }
+        }
+
+        Ok(GrpcResponse {
+            body: response.into_body(),
+            buffer: BytesMut::new(),
+            message: PhantomData,
+        })
+    }
+
+    /// Returns the next message, `None` once the call has succeeded.
+    async fn next(&mut self) -> Result<Option<M>, Status> {
+        loop {
+            if self.buffer.len() >= 5 {
+                let length = u32::from_be_bytes([
+                    self.buffer[1],
+                    self.buffer[2],
+                    self.buffer[3],
+                    self.buffer[4],
+                ]) as usize;
+
+                if self.buffer.len() >= 5 + length {
+                    let is_compressed = self.buffer[0] != 0;
+                    self.buffer.advance(5);
+                    let payload = self.buffer.split_to(length).freeze();
+
+                    if is_compressed {
+                        return Err(Status::internal("compressed responses are not supported"));
+                    }
+
+                    return match M::decode(payload) {
+                        Ok(message) => Ok(Some(message)),
+                        Err(err) => Err(Status::internal(format!("invalid response: {}", err))),
+                    };
+                }
+            }
+
+            match self.body.data().await {
+                Some(Ok(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Some(Err(status)) => return Err(status),
+                None => {
+                    let trailers = match self.body.trailers().await {
+                        Ok(trailers) => trailers,
+                        Err(status) => return Err(status),
+                    };
+
+                    return match trailers.as_ref().and_then(status_from_headers) {
+                        Some(status) if status.code() != Code::Ok => Err(status),
+                        _ => Ok(None),
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn status_from_headers(headers: &HeaderMap) -> Option<Status> {
+    let code = headers.get("grpc-status")?.to_str().ok()?.parse().ok()?;
+
+    let message = match headers.get("grpc-message") {
+        Some(message) => percent_decode(message.as_bytes())
+            .decode_utf8_lossy()
+            .into_owned(),
+        None => String::new(),
+    };
+
+    Some(Status::new(Code::from_i32(code), message))
+}
+
+/// Returns the credentials of a request, taken from its headers or else its query parameters.
+fn credentials(request: &Request<Body>) -> HeaderMap {
+    let mut credentials = HeaderMap::new();
+    let query = query_params(request.uri());
+
+    for name in CREDENTIALS {
+        let value = match request.headers().get(*name) {
+            Some(value) => Some(value.clone()),
+            None => query.get(*name).and_then(|v| HeaderValue::from_str(v).ok()),
+        };
+
+        if let Some(value) = value {
+            credentials.insert(HeaderName::from_static(name), value);
+        }
+    }
+
+    credentials
+}
+
+/// Returns the gRPC request of a call to the service method at the path.
+fn grpc_request<M: prost::Message>(
+    path: &str,
+    credentials: &HeaderMap,
+    message: &M,
+) -> Request<Body> {
+    let mut frame = BytesMut::with_capacity(5 + message.encoded_len());
+    frame.put_u8(0);
+    frame.put_u32(message.encoded_len() as u32);
+    if let Err(err) = message.encode(&mut frame) {
+        tracing::error!("could not encode request: {}", err);
+    }
+
+    let mut request = Request::new(Body::from(frame.freeze()));
+    *request.method_mut() = Method::POST;
+    *request.version_mut() = Version::HTTP_2;
+    if let Ok(uri) = path.parse() {
+        *request.uri_mut() = uri;
+    }
+
+    let headers = request.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/grpc"),
+    );
+    headers.insert(header::TE, HeaderValue::from_static("trailers"));
+    headers.extend(credentials.clone());
+
+    request
+}
+
+/// Parses the query parameters of a search into the request.
+fn search_request(uri: &Uri) -> Result<chat::SearchRequest, Status> {
+    let mut request = chat::SearchRequest::default();
+
+    for (name, value) in query_params(uri) {
+        match name.as_str() {
+            "query" => request.query = value,
+            "fromUserId" | "from_user_id" => request.from_user_id = value,
+            "conversationId" | "conversation_id" => request.conversation_id = value,
+            "sentAfter" | "sent_after" => {
+                request.sent_after = Some(parse_timestamp(&name, &value)?)
+            }
+            "sentBefore" | "sent_before" => {
+                request.sent_before = Some(parse_timestamp(&name, &value)?)
+            }
+            "pageSize" | "page_size" => {
+                request.page_size = match value.parse() {
+                    Ok(page_size) => page_size,
+                    Err(_) => {
+                        return Err(Status::invalid_argument(format!(
+                            "{} is not a number",
+                            name
+                        )))
+                    }
+                }
+            }
+            "pageToken" | "page_token" => request.page_token = value,
+            _ => {}
+        }
+    }
+
+    Ok(request)
+}
+
+fn parse_timestamp(name: &str, value: &str) -> Result<prost_types::Timestamp, Status> {
+    match proto::json::timestamp::parse(value) {
+        Some(timestamp) => Ok(timestamp),
+        None => Err(Status::invalid_argument(format!(
+            "{} is not an RFC 3339 timestamp",
+            name
+        ))),
+    }
+}
+
+/// Returns the decoded query parameters, the last of several with the same name wins.
+fn query_params(uri: &Uri) -> HashMap<String, String> {
+    let decode = |value: &str| {
+        let value = value.replace('+', " ");
+        percent_decode(value.as_bytes())
+            .decode_utf8_lossy()
+            .into_owned()
+    };
+
+    uri.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|v| !v.is_empty())
+        .map(|param| match param.find('=') {
+            Some(index) => (decode(&param[..index]), decode(&param[index + 1..])),
+            None => (decode(param), String::new()),
+        })
+        .collect()
+}
+
+async fn read_json<T: DeserializeOwned>(request: Request<Body>) -> Result<T, Status> {
+    let mut body = request.into_body();
+
+    let mut buffer = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => return Err(Status::cancelled(format!("request body failed: {}", err))),
+        };
+
+        if buffer.len() + chunk.len() > MAX_REQUEST_SIZE {
+            return Err(Status::resource_exhausted("request body is too large"));
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+
+    match serde_json::from_slice(&buffer) {
+        Ok(message) => Ok(message),
+        Err(err) => Err(Status::invalid_argument(format!(
+            "request body is not valid: {}",
+            err
+        ))),
+    }
+}
+
+fn json_response<T: Serialize>(message: &T) -> Response<Body> {
+    let body = match serde_json::to_vec(message) {
+        Ok(body) => body,
+        Err(err) => return error_response(&Status::internal(err.to_string())),
+    };
+
+    let mut response = Response::new(Body::from(body));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+
+    response
+}
+
+fn empty_response() -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+
+    response
+}
+
+/// Answers a failed request with its status, see `http_status`.
+fn error_response(status: &Status) -> Response<Body> {
+    let body = serde_json::json!({
+        "code": status.code() as i32,
+        "message": status.message(),
+    });
+
+    let mut response = json_response(&body);
+    *response.status_mut() = http_status(status.code());
+
+    response
+}
+
+/// Maps a gRPC status code to an HTTP status like Google's HTTP APIs do.
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        // the client closed the request
+        Code::Cancelled => StatusCode::from_u16(499).unwrap_or(StatusCode::BAD_REQUEST),
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_parameters_are_decoded() {
+        let uri: Uri = "/api/v1/messages?query=hello+w%C3%B6rld&pageSize=5&sentAfter=2020-05-01T12%3A30%3A00Z&unknown"
+            .parse()
+            .unwrap();
+
+        let request = search_request(&uri).unwrap();
+        assert_eq!(request.query, "hello wörld");
+        assert_eq!(request.page_size, 5);
+        assert_eq!(request.sent_after.unwrap().seconds, 1_588_336_200);
+
+        let uri: Uri = "/api/v1/messages?page_size=many".parse().unwrap();
+        assert_eq!(
+            search_request(&uri).unwrap_err().code(),
+            Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn credentials_fall_back_to_query_parameters() {
+        let request = Request::builder()
+            .uri("/api/v1/notifications?user_id=alice-id&user_token=secret")
+            .header("user_id", "bob-id")
+            .body(Body::empty())
+            .unwrap();
+
+        let credentials = credentials(&request);
+        assert_eq!(credentials["user_id"], "bob-id");
+        assert_eq!(credentials["user_token"], "secret");
+    }
+
+    #[test]
+    fn notifications_are_sent_as_named_events() {
+        let notification = without_sender(incoming_notification::Types::ServerShutdown(
+            incoming_notification::ServerShutdown {
+                reason: String::from("maintenance"),
+            },
+        ));
+
+        assert_eq!(
+            server_sent_event(&notification),
+            Bytes::from(
+                "event: server_shutdown\ndata: {\"from\":null,\"serverShutdown\":{\"reason\":\"maintenance\"}}\n\n"
+            )
+        );
+    }
+}
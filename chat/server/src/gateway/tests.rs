@@ -0,0 +1,570 @@
+use crate::search;
+use crate::test_support::{TestServer, WEB_ORIGIN};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hyper::body::HttpBody;
+use hyper::{header, Body, Client, Method, Request, Response, StatusCode};
+use proto::chat;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// A frame of a gRPC-Web response body.
+#[derive(Debug)]
+enum Frame {
+    Message(Bytes),
+    Trailers(String),
+}
+
+/// Reads the frames of a gRPC-Web response body.
+struct Frames {
+    body: Body,
+    is_text: bool,
+    text: Vec<u8>,
+    buffer: BytesMut,
+}
+
+impl Frames {
+    fn new(response: Response<Body>) -> Frames {
+        let is_text = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("application/grpc-web-text");
+
+        Frames {
+            body: response.into_body(),
+            is_text,
+            text: vec![],
+            buffer: BytesMut::new(),
+        }
+    }
+
+    async fn next(&mut self) -> Frame {
+        loop {
+            if self.buffer.len() >= 5 {
+                let length = u32::from_be_bytes([
+                    self.buffer[1],
+                    self.buffer[2],
+                    self.buffer[3],
+                    self.buffer[4],
+                ]) as usize;
+
+                if self.buffer.len() >= 5 + length {
+                    let flag = self.buffer[0];
+                    self.buffer.advance(5);
+                    let payload = self.buffer.split_to(length).freeze();
+
+                    return match flag {
+                        0x80 => Frame::Trailers(String::from_utf8(payload.to_vec()).unwrap()),
+                        _ => Frame::Message(payload),
+                    };
+                }
+            }
+
+            let chunk = tokio::time::timeout(Duration::from_secs(5), self.body.data())
+                .await
+                .expect("no frame received")
+                .expect("response body ended")
+                .expect("response body failed");
+
+            if self.is_text {
+                // decode all complete groups of 4 characters
+                self.text.extend_from_slice(&chunk);
+                let complete = self.text.len() / 4 * 4;
+                let text: Vec<u8> = self.text.drain(..complete).collect();
+                for group in text.chunks(4) {
+                    self.buffer.extend(base64::decode(group).unwrap());
+                }
+            } else {
+                self.buffer.extend_from_slice(&chunk);
+            }
+        }
+    }
+
+    async fn next_message<M: prost::Message + Default>(&mut self) -> M {
+        match self.next().await {
+            Frame::Message(payload) => M::decode(payload).unwrap(),
+            frame => panic!("expected a message, got {:?}", frame),
+        }
+    }
+}
+
+fn frame<M: prost::Message>(message: &M) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(5 + message.encoded_len());
+    frame.put_u8(0);
+    frame.put_u32(message.encoded_len() as u32);
+    message.encode(&mut frame).unwrap();
+
+    frame
+}
+
+async fn call<M: prost::Message>(
+    server: &TestServer,
+    path: &str,
+    message: &M,
+    is_text: bool,
+    credentials: Option<&chat::AuthenticateResponse>,
+) -> Response<Body> {
+    let (content_type, body) = match is_text {
+        true => (
+            "application/grpc-web-text",
+            base64::encode(frame(message)).into_bytes(),
+        ),
+        false => ("application/grpc-web+proto", frame(message)),
+    };
+
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}{}", server.web_url(), path))
+        .header(header::ORIGIN, WEB_ORIGIN)
+        .header(header::CONTENT_TYPE, content_type)
+        .header("x-grpc-web", "1");
+
+    if let Some(credentials) = credentials {
+        request = request
+            .header("user_id", credentials.id.as_str())
+            .header("user_token", credentials.token.as_str());
+    }
+
+    let request = request.body(Body::from(body)).unwrap();
+
+    Client::new().request(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn browser_logs_in_sends_and_receives() {
+    let server = TestServer::start().await;
+
+    let response = call(
+        &server,
+        "/chat.AuthenticationService/Authenticate",
+        &chat::AuthenticateRequest {
+            name: String::from("alice"),
+        },
+        true,
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        WEB_ORIGIN
+    );
+
+    // the session stays open while the user is logged in
+    let mut session = Frames::new(response);
+    let credentials: chat::AuthenticateResponse = session.next_message().await;
+    let alice = chat::User {
+        id: credentials.id.clone(),
+        name: String::from("alice"),
+    };
+
+    let response = call(
+        &server,
+        "/chat.ChatService/Receive",
+        &chat::ReceiveRequest {},
+        false,
+        Some(&credentials),
+    )
+    .await;
+    let mut notifications = Frames::new(response);
+
+    let mut bob = server.login("bob").await;
+    bob.expect_presence(&alice, true).await;
+
+    let response: chat::ReceiveResponse = notifications.next_message().await;
+    assert_eq!(response.notification.unwrap().from, Some(bob.user.clone()));
+
+    // messages of gRPC clients reach the browser
+    let message_id = bob.send_message(&alice, "hello alice").await.unwrap();
+    let response: chat::ReceiveResponse = notifications.next_message().await;
+    match response.notification.unwrap().types {
+        Some(chat::incoming_notification::Types::Message(message)) => {
+            assert_eq!(message.message_id, Some(message_id))
+        }
+        types => panic!("expected a message, got {:?}", types),
+    }
+
+    // and the other way round
+    let send_request = chat::SendRequest {
+        notification: Some(chat::OutgoingNotification {
+            to: Some(bob.user.clone()),
+            types: Some(chat::outgoing_notification::Types::Message(
+                chat::MessageContent {
+                    time_sent: None,
+                    content: String::from("hello bob"),
+                },
+            )),
+        }),
+        idempotency_key: String::new(),
+    };
+    let response = call(
+        &server,
+        "/chat.ChatService/Send",
+        &send_request,
+        true,
+        Some(&credentials),
+    )
+    .await;
+
+    let mut frames = Frames::new(response);
+    let response: chat::SendResponse = frames.next_message().await;
+    match frames.next().await {
+        Frame::Trailers(trailers) => assert!(trailers.contains("grpc-status: 0\r\n")),
+        frame => panic!("expected trailers, got {:?}", frame),
+    }
+
+    assert_eq!(
+        bob.expect_message(&alice, "hello bob").await,
+        response.message_id.unwrap()
+    );
+
+    // closing the session logs out the user
+    drop(session);
+    bob.expect_presence(&alice, false).await;
+}
+
+#[tokio::test]
+async fn failed_calls_report_their_status() {
+    let server = TestServer::start().await;
+
+    let credentials = chat::AuthenticateResponse {
+        id: String::from("unknown"),
+        token: String::from("invalid"),
+    };
+    let response = call(
+        &server,
+        "/chat.ChatService/Search",
+        &chat::SearchRequest::default(),
+        false,
+        Some(&credentials),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let status = match response.headers().get("grpc-status") {
+        Some(status) => String::from(status.to_str().unwrap()),
+        None => match Frames::new(response).next().await {
+            Frame::Trailers(trailers) => trailers,
+            frame => panic!("expected trailers, got {:?}", frame),
+        },
+    };
+    assert!(status.contains(&(tonic::Code::Unauthenticated as i32).to_string()));
+}
+
+#[tokio::test]
+async fn only_allowed_origins_may_call() {
+    let server = TestServer::start().await;
+    let client = Client::new();
+
+    let preflight = |origin: &str| {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri(format!("{}/chat.ChatService/Send", server.web_url()))
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "content-type,user_id,user_token,x-forwarded-for",
+            )
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = client.request(preflight(WEB_ORIGIN)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        WEB_ORIGIN
+    );
+    // the allowed headers are listed instead of echoing the requested ones
+    let allowed_headers = response.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS]
+        .to_str()
+        .unwrap();
+    assert!(allowed_headers.contains("user_token"));
+    assert!(!allowed_headers.contains("x-forwarded-for"));
+
+    let response = client
+        .request(preflight("https://evil.example.com"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(response
+        .headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .is_none());
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!(
+            "{}/chat.AuthenticationService/Authenticate",
+            server.web_url()
+        ))
+        .header(header::ORIGIN, "https://evil.example.com")
+        .header(header::CONTENT_TYPE, "application/grpc-web")
+        .body(Body::from(frame(&chat::AuthenticateRequest {
+            name: String::from("mallory"),
+        })))
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+/// Makes a request of the JSON API, returns the status and the decoded body.
+async fn api(
+    server: &TestServer,
+    method: Method,
+    path: &str,
+    credentials: Option<&Value>,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(format!("{}/api/v1/{}", server.web_url(), path))
+        .header(header::ORIGIN, WEB_ORIGIN);
+
+    if let Some(credentials) = credentials {
+        request = request
+            .header("user_id", credentials["id"].as_str().unwrap())
+            .header("user_token", credentials["token"].as_str().unwrap());
+    }
+
+    let body = match body {
+        Some(body) => Body::from(body.to_string()),
+        None => Body::empty(),
+    };
+
+    let response = Client::new()
+        .request(request.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    match body.is_empty() {
+        true => (status, Value::Null),
+        false => (status, serde_json::from_slice(&body).unwrap()),
+    }
+}
+
+/// Reads the server-sent events of a notification stream.
+struct Events {
+    body: Body,
+    buffer: String,
+}
+
+impl Events {
+    async fn next(&mut self) -> (String, Value) {
+        loop {
+            if let Some(index) = self.buffer.find("\n\n") {
+                let event: String = self.buffer.drain(..index + 2).collect();
+
+                let mut lines = event.lines();
+                let name = lines.next().unwrap().strip_prefix("event: ").unwrap();
+                let data = lines.next().unwrap().strip_prefix("data: ").unwrap();
+
+                return (String::from(name), serde_json::from_str(data).unwrap());
+            }
+
+            let chunk = tokio::time::timeout(Duration::from_secs(5), self.body.data())
+                .await
+                .expect("no event received")
+                .expect("event stream ended")
+                .expect("event stream failed");
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+}
+
+#[tokio::test]
+async fn json_clients_log_in_send_and_receive() {
+    let server = TestServer::start().await;
+
+    let (status, credentials) = api(
+        &server,
+        Method::POST,
+        "login",
+        None,
+        Some(json!({"name": "alice"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let alice = chat::User {
+        id: String::from(credentials["id"].as_str().unwrap()),
+        name: String::from("alice"),
+    };
+
+    // EventSource can't send headers, the credentials are passed as query parameters
+    let request = Request::builder()
+        .uri(format!(
+            "{}/api/v1/notifications?user_id={}&user_token={}",
+            server.web_url(),
+            credentials["id"].as_str().unwrap(),
+            credentials["token"].as_str().unwrap()
+        ))
+        .body(Body::empty())
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    let mut events = Events {
+        body: response.into_body(),
+        buffer: String::new(),
+    };
+
+    let mut bob = server.login("bob").await;
+    bob.expect_presence(&alice, true).await;
+
+    let (event, notification) = events.next().await;
+    assert_eq!(event, "online");
+    assert_eq!(notification["from"]["name"], "bob");
+    assert_eq!(notification["online"]["isOnline"], true);
+
+    let (status, users) = api(&server, Method::GET, "users", Some(&credentials), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(users["users"][0]["name"], "alice");
+    assert_eq!(users["users"][1]["name"], "bob");
+
+    // messages of gRPC clients arrive as events
+    let message_id = bob.send_message(&alice, "hello alice").await.unwrap();
+    let (event, notification) = events.next().await;
+    assert_eq!(event, "message");
+    assert_eq!(notification["message"]["messageId"]["id"], message_id.id);
+    assert_eq!(
+        notification["message"]["messageContent"]["content"],
+        "hello alice"
+    );
+
+    // and JSON clients send messages to them
+    let (status, response) = api(
+        &server,
+        Method::POST,
+        "messages",
+        Some(&credentials),
+        Some(json!({
+            "notification": {
+                "to": {"id": bob.user.id},
+                "message": {"content": "hello bob"},
+            }
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let message_id = bob.expect_message(&alice, "hello bob").await;
+    assert_eq!(response["messageId"]["id"], message_id.id);
+
+    let (status, results) = api(
+        &server,
+        Method::GET,
+        "messages?query=hello+bob",
+        Some(&credentials),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(results["totalHits"], 1);
+    assert_eq!(results["hits"][0]["from"]["name"], "alice");
+
+    let (status, _) = api(&server, Method::POST, "heartbeat", Some(&credentials), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // logging out ends the session
+    let (status, _) = api(&server, Method::DELETE, "session", Some(&credentials), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    bob.expect_presence(&alice, false).await;
+}
+
+#[tokio::test]
+async fn json_clients_list_the_history_of_a_conversation() {
+    let server = TestServer::start().await;
+
+    let (_, credentials) = api(
+        &server,
+        Method::POST,
+        "login",
+        None,
+        Some(json!({"name": "alice"})),
+    )
+    .await;
+    let alice_id = credentials["id"].as_str().unwrap();
+
+    let bob = server.login("bob").await;
+    let carol = server.login("carol").await;
+
+    for (to, content) in &[(&bob, "hello bob"), (&carol, "hello carol"), (&bob, "bye")] {
+        let (status, _) = api(
+            &server,
+            Method::POST,
+            "messages",
+            Some(&credentials),
+            Some(json!({
+                "notification": {
+                    "to": {"id": to.user.id},
+                    "message": {"content": content},
+                }
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let conversation_id = search::conversation_id(alice_id, &bob.user.id);
+    let (status, history) = api(
+        &server,
+        Method::GET,
+        &format!("messages?conversationId={}", conversation_id),
+        Some(&credentials),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(history["totalHits"], 2);
+    assert_eq!(history["hits"][0]["snippet"], "bye");
+    assert_eq!(history["hits"][1]["snippet"], "hello bob");
+
+    // only a conversation may be listed without a query
+    let (status, _) = api(&server, Method::GET, "messages", Some(&credentials), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn json_errors_carry_the_status() {
+    let server = TestServer::start().await;
+
+    let credentials = json!({"id": "unknown", "token": "invalid"});
+    let (status, error) = api(&server, Method::GET, "users", Some(&credentials), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error["code"], tonic::Code::Unauthenticated as i32);
+
+    let (status, _) = api(
+        &server,
+        Method::POST,
+        "login",
+        None,
+        Some(json!({"name": "alice"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, error) = api(
+        &server,
+        Method::POST,
+        "login",
+        None,
+        Some(json!({"name": "alice"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error["code"], tonic::Code::AlreadyExists as i32);
+
+    let (status, error) = api(&server, Method::POST, "login", None, Some(json!([1]))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(error["message"].as_str().unwrap().contains("not valid"));
+
+    let (status, _) = api(&server, Method::PUT, "users", None, None).await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+
+    let (status, _) = api(&server, Method::GET, "unknown", None, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
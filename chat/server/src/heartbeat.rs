@@ -0,0 +1,85 @@
+use crate::metrics;
+use crate::UserList;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Sends a heartbeat to every receive stream each `interval` and disconnects the users who have
+/// not made any request within `timeout`. Their sessions end like a logout, so the other users
+/// see them go offline even if their connection died without being closed.
+pub async fn run(users: Arc<Mutex<UserList>>, interval: Duration, timeout: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+
+    loop {
+        ticks.tick().await;
+
+        let mut users = match users.lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                tracing::error!("unable to acquire lock, stopping heartbeats");
+                return;
+            }
+        };
+
+        for user_id in users.expired_users(timeout) {
+            tracing::info!(%user_id, "heartbeat timed out");
+            metrics::HEARTBEAT_TIMEOUTS.inc();
+
+            if let Err(err) = users.disconnect_user(&user_id, "heartbeat timed out") {
+                tracing::error!(%user_id, "could not disconnect user: {}", err);
+            }
+        }
+
+        users.send_heartbeats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::chat::incoming_notification::Types;
+
+    #[tokio::test]
+    async fn silent_users_are_disconnected() {
+        let users = Arc::new(Mutex::new(UserList::new()));
+
+        let (alice, mut receiver) = {
+            let mut users = users.lock().unwrap();
+            let alice = users.create_user("alice").unwrap();
+            let receiver = users.take_user_receiver(&alice.id()).unwrap();
+            users.set_user_online(&alice.id(), true).unwrap();
+
+            (alice, receiver)
+        };
+
+        tokio::spawn(run(
+            users.clone(),
+            Duration::from_millis(20),
+            Duration::from_millis(100),
+        ));
+
+        let mut heartbeats = 0;
+        let reason = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match receiver.recv().await.unwrap().types {
+                    Some(Types::Heartbeat(_)) => heartbeats += 1,
+                    Some(Types::Disconnected(disconnected)) => return disconnected.reason,
+                    types => panic!("unexpected notification {:?}", types),
+                }
+            }
+        })
+        .await
+        .expect("alice was not disconnected");
+
+        assert_eq!(reason, "heartbeat timed out");
+        assert!(heartbeats > 0);
+        assert!(users
+            .lock()
+            .unwrap()
+            .expired_users(Duration::ZERO)
+            .is_empty());
+        assert_eq!(
+            alice.disconnected().await.as_deref(),
+            Some("heartbeat timed out")
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use std::future::Future;
+use tonic::{Request, Status};
+use tracing::Instrument;
+
+/// Formats the peer address of a request for use as a span field.
+pub fn peer<T>(request: &Request<T>) -> String {
+    match request.remote_addr() {
+        Some(addr) => addr.to_string(),
+        None => String::from("unknown"),
+    }
+}
+
+/// Reads the user id an authenticated request was made with, for use as a span field.
+pub fn user_id<T>(request: &Request<T>) -> String {
+    match request.metadata().get("user_id") {
+        Some(user_id) => String::from(user_id.to_str().unwrap_or("invalid")),
+        None => String::from("unknown"),
+    }
+}
+
+/// Runs an RPC handler inside `span` and logs its outcome.
+pub async fn traced<F, T>(span: tracing::Span, handler: F) -> Result<T, Status>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    async move {
+        let result = handler.await;
+
+        match &result {
+            Ok(_) => tracing::debug!("request succeeded"),
+            Err(status) => {
+                tracing::warn!(code = ?status.code(), "request failed: {}", status.message())
+            }
+        }
+
+        result
+    }
+    .instrument(span)
+    .await
+}
@@ -1,42 +1,441 @@
+// tonic::Status is large by design and dictated by the generated service traits
+#![allow(clippy::result_large_err)]
+
+mod backplane;
+mod config;
+mod error;
+mod gateway;
+mod heartbeat;
+mod logging;
+mod metrics;
+mod rate_limiter;
+mod search;
 mod services;
+mod shutdown;
+mod store;
+#[cfg(test)]
+mod test_support;
 mod user_list;
 mod util;
+mod validation;
+mod webhook;
 
-use futures::prelude::*;
+use backplane::Node;
+use config::{BackplaneConfig, Config, StorageConfig};
+use gateway::Gateway;
+use hyper::server::conn::AddrIncoming;
+use proto::chat::admin_service_server::AdminServiceServer;
+use proto::chat::authentication_service_server::AuthenticationServiceServer;
+use proto::chat::chat_service_server::ChatServiceServer;
+use rate_limiter::RateLimiter;
+use search::MessageIndex;
+use server_common::config::CommonArgs;
+use server_common::health;
+use server_common::reflection;
+use server_common::uds::UnixIncoming;
+use shutdown::Shutdown;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use structopt::StructOpt;
-use tonic::transport::Server;
 use user_list::UserList;
+use webhook::Webhooks;
 
+use services::AdminService;
 use services::AuthenticationService;
 use services::ChatService;
 
 #[derive(StructOpt)]
 #[structopt(about = "A gRPC test server")]
 struct Cli {
-    #[structopt(short, help = "The port on which the gRPC server will be opened")]
-    port: u32,
+    #[structopt(flatten)]
+    common: CommonArgs,
+
+    #[structopt(long, help = "Number of messages per second a single user may send")]
+    send_rate: Option<f64>,
+
+    #[structopt(long, help = "Number of messages a single user may send in a burst")]
+    send_burst: Option<u32>,
+
+    #[structopt(
+        long,
+        help = "Number of authentications per second allowed from a single peer address"
+    )]
+    auth_rate: Option<f64>,
+
+    #[structopt(
+        long,
+        help = "Number of authentications allowed in a burst from a single peer address"
+    )]
+    auth_burst: Option<u32>,
+
+    #[structopt(long, help = "Maximum size of a single message in bytes")]
+    max_message_length: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Number of seconds a message's sent time may lie in the future"
+    )]
+    max_clock_skew: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "The port on which Prometheus metrics will be served at /metrics"
+    )]
+    metrics_port: Option<u16>,
+
+    #[structopt(
+        long,
+        help = "The port on which browser clients will be served gRPC-Web and the JSON API"
+    )]
+    web_port: Option<u16>,
+
+    #[structopt(
+        long,
+        help = "Number of seconds to wait for pending notifications to be delivered on shutdown"
+    )]
+    shutdown_timeout: Option<u64>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "File in which the server state is persisted on shutdown"
+    )]
+    state_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Redis server used as backplane to run multiple nodes, e.g. redis://127.0.0.1:6379"
+    )]
+    backplane_url: Option<String>,
+}
+
+impl Cli {
+    /// Loads the configuration file and environment variables and applies the arguments on top.
+    fn load_config(self) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut config: Config = self.common.load(config::ENV_PREFIX)?;
+        self.common.apply(&mut config.common)?;
+
+        let limits = &mut config.limits;
+        if let Some(send_rate) = self.send_rate {
+            limits.send_rate = send_rate;
+        }
+        if let Some(send_burst) = self.send_burst {
+            limits.send_burst = send_burst;
+        }
+        if let Some(auth_rate) = self.auth_rate {
+            limits.auth_rate = auth_rate;
+        }
+        if let Some(auth_burst) = self.auth_burst {
+            limits.auth_burst = auth_burst;
+        }
+        if let Some(max_message_length) = self.max_message_length {
+            limits.max_message_length = max_message_length;
+        }
+        if let Some(max_clock_skew) = self.max_clock_skew {
+            limits.max_clock_skew = max_clock_skew;
+        }
+
+        if let Some(metrics_port) = self.metrics_port {
+            config.metrics.port = Some(metrics_port);
+        }
+
+        if let Some(web_port) = self.web_port {
+            config.web.port = Some(web_port);
+        }
+
+        if let Some(shutdown_timeout) = self.shutdown_timeout {
+            config.shutdown.timeout = shutdown_timeout;
+        }
+
+        if let Some(path) = self.state_file {
+            config.storage = StorageConfig::File { path };
+        }
+
+        if let Some(url) = self.backplane_url {
+            let channel = match config.backplane {
+                BackplaneConfig::Redis { channel, .. } => channel,
+                BackplaneConfig::Memory => config::default_backplane_channel(),
+            };
+
+            config.backplane = BackplaneConfig::Redis { url, channel };
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Cli::from_args();
+    let config = Cli::from_args().load_config()?;
+
+    config.common.logging.init()?;
 
-    let addr = format!("127.0.0.1:{}", args.port).parse().unwrap();
+    if let Some(metrics_port) = config.metrics.port {
+        metrics::register();
 
-    println!("Server listening on {}", addr);
+        let incoming =
+            AddrIncoming::bind(&SocketAddr::new(config.common.listen.address, metrics_port))?;
 
-    let shutdown_signal = tokio::signal::ctrl_c().map(|_| ());
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(incoming).await {
+                tracing::error!("metrics endpoint failed: {}", err);
+            }
+        });
+    }
 
     let users = Arc::new(Mutex::new(UserList::new()));
+    let shutdown = Arc::new(Shutdown::new());
+    let store = config.storage.open();
+
+    let index = Arc::new(Mutex::new(MessageIndex::new(config.search.max_messages)));
+
+    let node = Arc::new(Node::new(
+        config.backplane.open()?,
+        users.clone(),
+        index.clone(),
+    ));
+    Node::join(&node).await?;
+
+    if config.heartbeat.interval > 0 {
+        tokio::spawn(heartbeat::run(
+            users.clone(),
+            config.heartbeat.interval(),
+            config.heartbeat.timeout(),
+        ));
+    }
+
+    let auth_rate_limiter = Arc::new(RateLimiter::new(config.limits.auth_rate_limit()));
+    let send_rate_limiter = Arc::new(RateLimiter::new(config.limits.send_rate_limit()));
+
+    let (health_reporter, health_service) = health::health_reporter();
+    health_reporter.set_serving::<AuthenticationServiceServer<AuthenticationService>>();
+    health_reporter.set_serving::<ChatServiceServer<ChatService>>();
+
+    let admin_service = match config.admin.token.clone() {
+        Some(admin_token) => {
+            health_reporter.set_serving::<AdminServiceServer<AdminService>>();
+
+            let webhooks = Arc::new(Webhooks::new(
+                config.webhooks.clone(),
+                users.clone(),
+                node.clone(),
+                shutdown.clone(),
+            ));
 
-    Server::builder()
-        .add_service(AuthenticationService::new(users.clone()))
-        .add_service(ChatService::new(users))
-        .serve_with_shutdown(addr, shutdown_signal)
-        .await?;
+            Some(AdminService::new(
+                users.clone(),
+                node.clone(),
+                config.limits.message_limits(),
+                webhooks,
+                admin_token,
+            ))
+        }
+        None => None,
+    };
 
-    println!("Server finished");
+    let reflection_service = reflection::Builder::new()
+        .register_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    let shutdown_signal = {
+        let users = users.clone();
+        let shutdown = shutdown.clone();
+        let node = node.clone();
+        let shutdown_timeout = Duration::from_secs(config.shutdown.timeout);
+
+        async move {
+            let _ = tokio::signal::ctrl_c().await;
+            tracing::info!("shutting down");
+
+            shutdown
+                .run(
+                    &users,
+                    &node,
+                    &health_reporter,
+                    store.as_deref(),
+                    shutdown_timeout,
+                )
+                .await;
+        }
+    };
+
+    let authentication_service = AuthenticationService::new(
+        users.clone(),
+        auth_rate_limiter.clone(),
+        shutdown.clone(),
+        node.clone(),
+    );
+    let chat_service = ChatService::new(
+        users,
+        send_rate_limiter,
+        config.limits.message_limits(),
+        config.limits.receive_idle_timeout(),
+        shutdown.clone(),
+        node,
+        index,
+    );
+
+    // the gateway keeps serving browser clients their shutdown notifications and stops with the
+    // sessions at the end of the shutdown sequence
+    let gateway_task = match config.web.port {
+        Some(web_port) => {
+            let gateway = Gateway::new(
+                &config.web,
+                authentication_service.clone(),
+                chat_service.clone(),
+                auth_rate_limiter,
+            );
+            let incoming =
+                AddrIncoming::bind(&SocketAddr::new(config.common.listen.address, web_port))?;
+
+            Some(tokio::spawn(async move {
+                let signal = shutdown.sessions_closed();
+                if let Err(err) = gateway::serve(gateway, incoming, signal).await {
+                    tracing::error!("web gateway failed: {}", err);
+                }
+            }))
+        }
+        None => None,
+    };
+
+    let router = config
+        .common
+        .server_builder()
+        .await?
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(authentication_service)
+        .add_service(chat_service)
+        .add_optional_service(admin_service);
+
+    match &config.common.listen.unix_socket {
+        Some(path) => {
+            let incoming = UnixIncoming::bind(path)?;
+            tracing::info!("server listening on {}", path.display());
+
+            router
+                .serve_with_incoming_shutdown(incoming, shutdown_signal)
+                .await?;
+        }
+        None => {
+            let addr = config.common.listen.socket_addr();
+            tracing::info!("server listening on {}", addr);
+
+            router.serve_with_shutdown(addr, shutdown_signal).await?;
+        }
+    }
+
+    if let Some(gateway_task) = gateway_task {
+        let _ = gateway_task.await;
+    }
+
+    tracing::info!("server finished");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::sync::MutexGuard;
+
+    /// Held by every test loading the configuration, the environment variables they read are
+    /// shared by all tests.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn lock_env() -> MutexGuard<'static, ()> {
+        // a failed test must not fail the others as well
+        ENV.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Loads the configuration from a file with the given arguments, callers hold `lock_env`.
+    fn load(toml: &str, args: &[&str]) -> Result<Config, Box<dyn std::error::Error>> {
+        let path = env::temp_dir().join(format!(
+            "chat_server_{}.toml",
+            uuid::Uuid::new_v4().to_simple()
+        ));
+        fs::write(&path, toml).unwrap();
+
+        let mut cli = vec!["chat_server", "--config", path.to_str().unwrap()];
+        cli.extend_from_slice(args);
+        let config = Cli::from_iter(cli).load_config();
+
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn arguments_override_variables_override_the_file() {
+        let _env = lock_env();
+        let toml = "
+            [listen]
+            port = 50010
+
+            [limits]
+            send_rate = 2.5
+            send_burst = 3
+            auth_burst = 4
+
+            [heartbeat]
+            interval = 5
+            timeout = 20
+        ";
+
+        env::set_var("CHAT__LIMITS__SEND_BURST", "6");
+        env::set_var("CHAT__LIMITS__AUTH_BURST", "7");
+        env::set_var("CHAT__HEARTBEAT__TIMEOUT", "30");
+        env::set_var("CHAT__LISTEN__PORT", "50020");
+        let config = load(toml, &["--auth-burst", "8", "-p", "50030"]);
+
+        env::set_var("CHAT__LIMITS__SEND_RATEE", "1");
+        let misspelled = load(toml, &[]);
+        env::remove_var("CHAT__LIMITS__SEND_RATEE");
+
+        env::set_var("CHAT__LIMITS", "1");
+        let not_a_section = load(toml, &[]);
+
+        for name in &[
+            "CHAT__LIMITS__SEND_BURST",
+            "CHAT__LIMITS__AUTH_BURST",
+            "CHAT__HEARTBEAT__TIMEOUT",
+            "CHAT__LISTEN__PORT",
+            "CHAT__LIMITS",
+        ] {
+            env::remove_var(name);
+        }
+
+        let config = config.unwrap();
+        assert_eq!(config.limits.send_rate, 2.5);
+        assert_eq!(config.limits.send_burst, 6);
+        assert_eq!(config.limits.auth_burst, 8);
+        assert_eq!(config.heartbeat.interval, 5);
+        assert_eq!(config.heartbeat.timeout, 30);
+        assert_eq!(config.common.listen.port, 50030);
+
+        assert!(misspelled.is_err());
+        assert!(not_a_section.is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let _env = lock_env();
+
+        assert!(load("[limits]\nsend_burst = 2", &[]).is_ok());
+        assert!(load(include_str!("../chat_server.toml"), &[]).is_ok());
+
+        for toml in &[
+            "[limits]\nsend_bursts = 2",
+            "[limit]\nsend_burst = 2",
+            "[listen]\nports = 50010",
+            "[storage]\nbackend = \"file\"\npath = \"state.json\"\nformat = \"json\"",
+        ] {
+            assert!(load(toml, &[]).is_err(), "{}", toml);
+        }
+    }
+}
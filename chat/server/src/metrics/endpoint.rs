@@ -0,0 +1,124 @@
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, TextEncoder};
+use std::convert::Infallible;
+
+/// Serves the metrics of the default registry in the Prometheus text format on
+/// `GET /metrics` of the incoming connections.
+pub async fn serve(incoming: AddrIncoming) -> Result<(), hyper::Error> {
+    let make_service =
+        make_service_fn(|_connection| async { Ok::<_, Infallible>(service_fn(handle)) });
+
+    tracing::info!("metrics endpoint listening on {}", incoming.local_addr());
+
+    Server::builder(incoming).serve(make_service).await
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Ok(response(
+            StatusCode::NOT_FOUND,
+            "text/plain",
+            Vec::from("not found"),
+        ));
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => Ok(response(StatusCode::OK, encoder.format_type(), buffer)),
+        Err(err) => {
+            tracing::error!("could not encode metrics: {}", err);
+            Ok(response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "text/plain",
+                Vec::from("could not encode metrics"),
+            ))
+        }
+    }
+}
+
+fn response(status: StatusCode, content_type: &str, body: Vec<u8>) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+
+    if let Ok(content_type) = header::HeaderValue::from_str(content_type) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, content_type);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestServer;
+    use hyper::Client;
+    use std::net::SocketAddr;
+
+    /// Returns the value of the sample with the given name and labels, 0 if it isn't exported.
+    fn sample(metrics: &str, name: &str, labels: &[&str]) -> f64 {
+        metrics
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.rsplit_once(' '))
+            .find(|(series, _)| {
+                let (series_name, series_labels) = series.split_once('{').unwrap_or((series, ""));
+                series_name == name && labels.iter().all(|v| series_labels.contains(v))
+            })
+            .map(|(_, value)| value.parse().expect("invalid sample value"))
+            .unwrap_or(0.0)
+    }
+
+    async fn scrape(url: &str) -> String {
+        let response = Client::new().get(url.parse().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    // the registry is shared with the other tests, so the counters only ever grow by at least
+    // the requests of this test
+    #[tokio::test]
+    async fn sends_are_counted_and_timed() {
+        crate::metrics::register();
+
+        let incoming = AddrIncoming::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let url = format!("http://{}/metrics", incoming.local_addr());
+        tokio::spawn(serve(incoming));
+
+        let server = TestServer::start().await;
+        let mut alice = server.login("alice").await;
+        let mut bob = server.login("bob").await;
+        bob.expect_presence(&alice.user, true).await;
+
+        let sent_ok = ["method=\"send\"", "code=\"Ok\""];
+        let before = scrape(&url).await;
+
+        alice.send_message(&bob.user, "hello").await.unwrap();
+        bob.expect_message(&alice.user, "hello").await;
+
+        let after = scrape(&url).await;
+        let grown = |name: &str, labels: &[&str]| {
+            sample(&after, name, labels) - sample(&before, name, labels)
+        };
+
+        assert!(grown("chat_messages_sent_total", &[]) >= 1.0);
+        assert!(grown("chat_notifications_delivered_total", &[]) >= 1.0);
+        assert!(grown("chat_rpc_duration_seconds_count", &sent_ok) >= 1.0);
+        assert!(grown("chat_rpc_duration_seconds_sum", &sent_ok) > 0.0);
+
+        let all_buckets = ["method=\"send\"", "code=\"Ok\"", "le=\"+Inf\""];
+        assert_eq!(
+            sample(&after, "chat_rpc_duration_seconds_bucket", &all_buckets),
+            sample(&after, "chat_rpc_duration_seconds_count", &sent_ok)
+        );
+
+        assert!(after.contains("# TYPE chat_rpc_duration_seconds histogram"));
+    }
+}
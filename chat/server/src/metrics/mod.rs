@@ -0,0 +1,98 @@
+mod endpoint;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use std::future::Future;
+use std::time::Instant;
+use tonic::{Code, Status};
+
+pub use endpoint::serve;
+
+lazy_static! {
+    pub static ref CONNECTED_USERS: IntGauge = register_int_gauge!(
+        "chat_connected_users",
+        "Number of users currently logged in"
+    )
+    .unwrap();
+    pub static ref OPEN_STREAMS: IntGaugeVec = register_int_gauge_vec!(
+        "chat_open_streams",
+        "Number of currently open response streams",
+        &["stream"]
+    )
+    .unwrap();
+    pub static ref STREAMS_CLOSED: IntCounterVec = register_int_counter_vec!(
+        "chat_streams_closed_total",
+        "Number of response streams which have ended, by the reason they ended",
+        &["stream", "reason"]
+    )
+    .unwrap();
+    pub static ref MESSAGES_SENT: IntCounter = register_int_counter!(
+        "chat_messages_sent_total",
+        "Number of chat messages accepted for delivery"
+    )
+    .unwrap();
+    pub static ref NOTIFICATIONS_DELIVERED: IntCounter = register_int_counter!(
+        "chat_notifications_delivered_total",
+        "Number of notifications written to receive streams"
+    )
+    .unwrap();
+    pub static ref NOTIFICATIONS_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "chat_notifications_dropped_total",
+        "Number of notifications which could not be enqueued for their recipient",
+        &["type"]
+    )
+    .unwrap();
+    pub static ref HEARTBEAT_TIMEOUTS: IntCounter = register_int_counter!(
+        "chat_heartbeat_timeouts_total",
+        "Number of users disconnected because they stopped answering heartbeats"
+    )
+    .unwrap();
+    pub static ref WEBHOOK_DELIVERIES: IntCounterVec = register_int_counter_vec!(
+        "chat_webhook_deliveries_total",
+        "Number of attempts to post a notification to a bot's webhook",
+        &["outcome"]
+    )
+    .unwrap();
+    pub static ref RPC_DURATION: HistogramVec = register_histogram_vec!(
+        "chat_rpc_duration_seconds",
+        "Time spent handling an RPC until its response (or response stream) was ready",
+        &["method", "code"]
+    )
+    .unwrap();
+}
+
+/// Registers all metrics up front, so they are exported before their first update.
+pub fn register() {
+    lazy_static::initialize(&CONNECTED_USERS);
+    lazy_static::initialize(&OPEN_STREAMS);
+    lazy_static::initialize(&STREAMS_CLOSED);
+    lazy_static::initialize(&MESSAGES_SENT);
+    lazy_static::initialize(&NOTIFICATIONS_DELIVERED);
+    lazy_static::initialize(&NOTIFICATIONS_DROPPED);
+    lazy_static::initialize(&HEARTBEAT_TIMEOUTS);
+    lazy_static::initialize(&WEBHOOK_DELIVERIES);
+    lazy_static::initialize(&RPC_DURATION);
+}
+
+/// Runs an RPC handler and records its duration and resulting status code.
+pub async fn timed<F, T>(method: &'static str, handler: F) -> Result<T, Status>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    let start = Instant::now();
+    let result = handler.await;
+
+    let code = match &result {
+        Ok(_) => Code::Ok,
+        Err(status) => status.code(),
+    };
+
+    RPC_DURATION
+        .with_label_values(&[method, &format!("{:?}", code)])
+        .observe(start.elapsed().as_secs_f64());
+
+    result
+}
@@ -0,0 +1,137 @@
+mod token_bucket;
+
+use crate::error::ChatError;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::Duration;
+use token_bucket::TokenBucket;
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tonic::{Code, Status};
+
+/// Number of buckets after which idle (completely refilled) buckets are dropped.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Limits for a single rate limiter: `burst` requests may be made at once, after
+/// which requests are admitted at `rate` per second.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Rejects limits which would never admit a request or whose retry times can't be computed.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.rate.is_finite() || self.rate <= 0.0 {
+            return Err(format!("rate must be a positive number, got {}", self.rate));
+        }
+
+        if self.burst == 0 {
+            return Err(String::from("burst must be at least 1"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Token bucket rate limiter keeping a separate bucket per key (e.g. user id or
+/// peer address).
+pub struct RateLimiter<K> {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<K, TokenBucket>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(limit: RateLimit) -> RateLimiter<K> {
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Consumes a token for `key`. Returns `resource_exhausted` with a `retry-after`
+    /// metadata entry (in seconds) if the key has exceeded its limit.
+    pub fn check(&self, key: K) -> Result<(), Status> {
+        let mut buckets = match self.buckets.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(ChatError::LockPoisoned.into()),
+        };
+
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| !bucket.is_full());
+        }
+
+        let limit = self.limit;
+        let bucket = buckets
+            .entry(key)
+            .or_insert_with(|| TokenBucket::new(limit.burst, limit.rate));
+
+        match bucket.try_acquire() {
+            Ok(()) => Ok(()),
+            Err(retry_after) => Err(RateLimiter::<K>::exhausted(retry_after)),
+        }
+    }
+
+    fn exhausted(retry_after: Duration) -> Status {
+        // round up, a client retrying after 0 seconds would just be rejected again
+        let seconds = retry_after
+            .as_secs()
+            .saturating_add(u64::from(retry_after.subsec_nanos() > 0));
+
+        let mut metadata = MetadataMap::new();
+        if let Ok(value) = AsciiMetadataValue::from_str(&seconds.to_string()) {
+            metadata.insert("retry-after", value);
+        }
+
+        Status::with_metadata(
+            Code::ResourceExhausted,
+            format!("rate limit exceeded, retry after {} seconds", seconds),
+            metadata,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(rate: f64, burst: u32) -> RateLimit {
+        RateLimit { rate, burst }
+    }
+
+    #[test]
+    fn limits_must_admit_requests() {
+        assert!(limit(0.5, 1).validate().is_ok());
+
+        for invalid in &[
+            limit(0.0, 1),
+            limit(-1.0, 1),
+            limit(f64::NAN, 1),
+            limit(f64::INFINITY, 1),
+            limit(1.0, 0),
+        ] {
+            assert!(invalid.validate().is_err(), "{:?} is valid", invalid);
+        }
+    }
+
+    #[test]
+    fn exhausted_keys_are_told_when_to_retry() {
+        let limiter = RateLimiter::new(limit(0.5, 1));
+
+        assert!(limiter.check("alice").is_ok());
+        assert!(limiter.check("bob").is_ok());
+
+        let status = limiter.check("alice").unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "2");
+
+        // seconds are rounded up and can't overflow
+        let status = RateLimiter::<&str>::exhausted(Duration::from_millis(1500));
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "2");
+
+        let status = RateLimiter::<&str>::exhausted(Duration::MAX);
+        let retry_after = status.metadata().get("retry-after").unwrap();
+        assert_eq!(retry_after, u64::MAX.to_string().as_str());
+    }
+}
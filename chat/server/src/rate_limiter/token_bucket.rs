@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+
+/// A classic token bucket: holds up to `capacity` tokens and refills
+/// continuously at `refill_rate` tokens per second, see `RateLimit::validate`
+/// for the values it works with.
+pub struct TokenBucket {
+    capacity: f64,
+    refill_rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, refill_rate: f64) -> TokenBucket {
+        TokenBucket {
+            capacity: capacity as f64,
+            refill_rate,
+            tokens: capacity as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a single token from the bucket. If the bucket is empty, returns the
+    /// time until the next token becomes available.
+    pub fn try_acquire(&mut self) -> Result<(), Duration> {
+        self.refill();
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        if self.refill_rate.is_nan() || self.refill_rate <= 0.0 {
+            return Err(Duration::MAX);
+        }
+
+        // a tiny rate can exceed what a duration holds
+        let missing = 1.0 - self.tokens;
+        Err(Duration::try_from_secs_f64(missing / self.refill_rate).unwrap_or(Duration::MAX))
+    }
+
+    /// Returns true if the bucket has refilled completely, i.e. the key it belongs to
+    /// has been idle long enough that the bucket can be dropped.
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Moves the last refill of the bucket back, as if the time had passed.
+    fn wait(bucket: &mut TokenBucket, seconds: f64) {
+        bucket.last_refill -= Duration::from_secs_f64(seconds);
+    }
+
+    #[test]
+    fn bursts_exhaust_the_bucket() {
+        let mut bucket = TokenBucket::new(3, 1.0);
+
+        for _ in 0..3 {
+            assert_eq!(bucket.try_acquire(), Ok(()));
+        }
+        assert!(bucket.try_acquire().is_err());
+        assert!(!bucket.is_full());
+    }
+
+    #[test]
+    fn tokens_are_refilled_up_to_the_capacity() {
+        let mut bucket = TokenBucket::new(2, 4.0);
+        bucket.try_acquire().unwrap();
+        bucket.try_acquire().unwrap();
+
+        wait(&mut bucket, 0.25);
+        assert_eq!(bucket.try_acquire(), Ok(()));
+        assert!(bucket.try_acquire().is_err());
+
+        wait(&mut bucket, 60.0);
+        assert!(bucket.is_full());
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn retry_after_is_the_time_until_the_next_token() {
+        let mut bucket = TokenBucket::new(1, 2.0);
+        bucket.try_acquire().unwrap();
+
+        let retry_after = bucket.try_acquire().unwrap_err();
+        assert!(retry_after <= Duration::from_millis(500));
+        assert!(retry_after > Duration::from_millis(400));
+
+        // rates too small or invalid to ever refill don't overflow
+        let mut bucket = TokenBucket::new(1, 1e-300);
+        bucket.try_acquire().unwrap();
+        assert_eq!(bucket.try_acquire(), Err(Duration::MAX));
+
+        let mut bucket = TokenBucket::new(0, f64::NAN);
+        assert_eq!(bucket.try_acquire(), Err(Duration::MAX));
+    }
+}
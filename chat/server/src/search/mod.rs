@@ -0,0 +1,553 @@
+mod snippet;
+mod tokenizer;
+
+use proto::chat;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::SystemTime;
+
+/// BM25 term frequency saturation.
+const K1: f32 = 1.2;
+/// BM25 document length normalization.
+const B: f32 = 0.75;
+
+/// Returns the id of the conversation between two users, independent of who sent the message.
+pub fn conversation_id(user_id: &str, other_user_id: &str) -> String {
+    match user_id < other_user_id {
+        true => format!("{}:{}", user_id, other_user_id),
+        false => format!("{}:{}", other_user_id, user_id),
+    }
+}
+
+/// Splits a search query into terms, a word ending with `*` becomes a prefix term.
+pub fn parse_query(query: &str) -> Vec<QueryTerm> {
+    let mut terms = vec![];
+
+    for word in query.split_whitespace() {
+        let tokens = tokenizer::tokenize(word);
+        let count = tokens.len();
+
+        // "e-mail*" is split into "e" and a prefix term "mail"
+        for (index, token) in tokens.into_iter().enumerate() {
+            terms.push(QueryTerm {
+                term: token.term,
+                is_prefix: index + 1 == count && word.ends_with('*'),
+            });
+        }
+    }
+
+    terms
+}
+
+/// A message as stored in the index.
+pub struct IndexedMessage {
+    pub message_id: String,
+    pub from: chat::User,
+    pub to: chat::User,
+    /// Time the server accepted the message.
+    pub time_sent: SystemTime,
+    pub content: String,
+    /// See `IncomingNotification.Message.sequence_number`.
+    pub sequence_number: u64,
+}
+
+/// A word of a search query.
+pub struct QueryTerm {
+    pub term: String,
+    /// Matches every term starting with `term`.
+    pub is_prefix: bool,
+}
+
+pub struct SearchQuery {
+    pub terms: Vec<QueryTerm>,
+    pub from_user_id: Option<String>,
+    pub conversation_id: Option<String>,
+    pub sent_after: Option<SystemTime>,
+    pub sent_before: Option<SystemTime>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+pub struct SearchResults {
+    pub hits: Vec<chat::SearchHit>,
+    /// Number of hits on all pages.
+    pub total: usize,
+}
+
+struct Document {
+    id: u64,
+    conversation_id: String,
+    message: IndexedMessage,
+    /// Distinct terms of the message.
+    terms: Vec<String>,
+    length: usize,
+}
+
+struct Posting {
+    document_id: u64,
+    frequency: usize,
+}
+
+/// In-memory full-text index over the most recent messages, ranked with BM25.
+///
+/// Once `capacity` messages are indexed, the oldest message is dropped for every new one.
+pub struct MessageIndex {
+    capacity: usize,
+    next_id: u64,
+    /// Ordered by id, the id of a document is `documents[0].id + index`.
+    documents: VecDeque<Document>,
+    /// Documents containing a term, ordered by id.
+    postings: BTreeMap<String, VecDeque<Posting>>,
+    total_length: usize,
+}
+
+impl MessageIndex {
+    pub fn new(capacity: usize) -> MessageIndex {
+        MessageIndex {
+            capacity,
+            next_id: 0,
+            documents: VecDeque::new(),
+            postings: BTreeMap::new(),
+            total_length: 0,
+        }
+    }
+
+    pub fn add(&mut self, message: IndexedMessage) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let tokens = tokenizer::tokenize(&message.content);
+
+        let mut frequencies: HashMap<String, usize> = HashMap::new();
+        for token in &tokens {
+            *frequencies.entry(token.term.clone()).or_default() += 1;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        for (term, frequency) in &frequencies {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .push_back(Posting {
+                    document_id: id,
+                    frequency: *frequency,
+                });
+        }
+
+        self.total_length += tokens.len();
+        self.documents.push_back(Document {
+            id,
+            conversation_id: conversation_id(&message.from.id, &message.to.id),
+            message,
+            terms: frequencies.into_keys().collect(),
+            length: tokens.len(),
+        });
+
+        while self.documents.len() > self.capacity {
+            self.remove_oldest();
+        }
+    }
+
+    fn remove_oldest(&mut self) {
+        let document = match self.documents.pop_front() {
+            Some(document) => document,
+            None => return,
+        };
+
+        // the oldest document is at the front of all of its postings
+        for term in &document.terms {
+            if let Some(postings) = self.postings.get_mut(term) {
+                postings.pop_front();
+
+                if postings.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+
+        self.total_length -= document.length;
+    }
+
+    fn document(&self, id: u64) -> Option<&Document> {
+        let first_id = self.documents.front()?.id;
+        self.documents.get((id - first_id) as usize)
+    }
+
+    /// Returns the frequency of the term in every document containing it, for a prefix term
+    /// summed up over all matching terms.
+    fn term_frequencies(&self, query_term: &QueryTerm) -> HashMap<u64, usize> {
+        let mut frequencies = HashMap::new();
+
+        let postings: Vec<&VecDeque<Posting>> = match query_term.is_prefix {
+            true => self
+                .postings
+                .range(query_term.term.clone()..)
+                .take_while(|(term, _)| term.starts_with(&query_term.term))
+                .map(|(_, postings)| postings)
+                .collect(),
+            false => self.postings.get(&query_term.term).into_iter().collect(),
+        };
+
+        for posting in postings.into_iter().flatten() {
+            *frequencies.entry(posting.document_id).or_default() += posting.frequency;
+        }
+
+        frequencies
+    }
+
+    fn matches_filters(&self, document: &Document, user_id: &str, query: &SearchQuery) -> bool {
+        let message = &document.message;
+
+        // users can only find messages they have sent or received
+        if message.from.id != user_id && message.to.id != user_id {
+            return false;
+        }
+
+        if let Some(from_user_id) = &query.from_user_id {
+            if &message.from.id != from_user_id {
+                return false;
+            }
+        }
+
+        if let Some(conversation_id) = &query.conversation_id {
+            if &document.conversation_id != conversation_id {
+                return false;
+            }
+        }
+
+        if let Some(sent_after) = query.sent_after {
+            if message.time_sent < sent_after {
+                return false;
+            }
+        }
+
+        if let Some(sent_before) = query.sent_before {
+            if message.time_sent >= sent_before {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Returns the messages of the user which contain all query terms, best matches first.
+    pub fn search(&self, user_id: &str, query: &SearchQuery) -> SearchResults {
+        let document_count = self.documents.len() as f32;
+        let average_length = match self.documents.len() {
+            0 => 1.0,
+            count => self.total_length as f32 / count as f32,
+        };
+
+        let mut scores: Option<HashMap<u64, f32>> = None;
+
+        for query_term in &query.terms {
+            let frequencies = self.term_frequencies(query_term);
+
+            let document_frequency = frequencies.len() as f32;
+            let idf = (1.0
+                + (document_count - document_frequency + 0.5) / (document_frequency + 0.5))
+                .ln();
+
+            let mut term_scores = HashMap::new();
+
+            for (id, frequency) in frequencies {
+                // all terms have to match
+                let score = match &scores {
+                    Some(scores) => match scores.get(&id) {
+                        Some(score) => *score,
+                        None => continue,
+                    },
+                    None => 0.0,
+                };
+
+                let length = self.document(id).map_or(0, |document| document.length) as f32;
+                let frequency = frequency as f32;
+                let term_score = idf * frequency * (K1 + 1.0)
+                    / (frequency + K1 * (1.0 - B + B * length / average_length));
+
+                term_scores.insert(id, score + term_score);
+            }
+
+            scores = Some(term_scores);
+        }
+
+        // without terms all messages match, which lists the history of a conversation
+        let scores = match scores {
+            Some(scores) => scores,
+            None => self
+                .documents
+                .iter()
+                .map(|document| (document.id, 0.0))
+                .collect(),
+        };
+
+        let mut hits: Vec<(&Document, f32)> = scores
+            .into_iter()
+            .filter_map(|(id, score)| self.document(id).map(|document| (document, score)))
+            .filter(|(document, _)| self.matches_filters(document, user_id, query))
+            .collect();
+
+        // best matches first, newer messages first if they match equally well
+        hits.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .partial_cmp(a_score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(b.id.cmp(&a.id))
+        });
+
+        let total = hits.len();
+
+        let hits = hits
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit)
+            .map(|(document, score)| MessageIndex::hit(document, score, query))
+            .collect();
+
+        SearchResults { hits, total }
+    }
+
+    /// Returns the messages `from_user_id` sent to `to_user_id` with a sequence number greater
+    /// than `after`, ordered by sequence number.
+    pub fn messages_after(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        after: u64,
+        limit: usize,
+    ) -> Vec<&IndexedMessage> {
+        let mut messages: Vec<&IndexedMessage> = self
+            .documents
+            .iter()
+            .map(|document| &document.message)
+            .filter(|message| {
+                message.from.id == from_user_id
+                    && message.to.id == to_user_id
+                    && message.sequence_number > after
+            })
+            .collect();
+
+        messages.sort_by_key(|message| message.sequence_number);
+        messages.truncate(limit);
+
+        messages
+    }
+
+    fn hit(document: &Document, score: f32, query: &SearchQuery) -> chat::SearchHit {
+        let message = &document.message;
+        let tokens = tokenizer::tokenize(&message.content);
+
+        let matches: Vec<usize> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| {
+                query
+                    .terms
+                    .iter()
+                    .any(|query_term| match query_term.is_prefix {
+                        true => token.term.starts_with(&query_term.term),
+                        false => token.term == query_term.term,
+                    })
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        let (snippet, highlights) = snippet::snippet(&message.content, &tokens, &matches);
+
+        chat::SearchHit {
+            message_id: Some(chat::MessageId {
+                id: message.message_id.clone(),
+            }),
+            conversation_id: document.conversation_id.clone(),
+            from: Some(message.from.clone()),
+            to: Some(message.to.clone()),
+            time_sent: Some(message.time_sent.into()),
+            snippet,
+            highlights,
+            score,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn user(name: &str) -> chat::User {
+        chat::User {
+            id: format!("{}-id", name),
+            name: String::from(name),
+        }
+    }
+
+    fn add(index: &mut MessageIndex, from: &str, to: &str, content: &str, seconds: u64) {
+        index.add(IndexedMessage {
+            message_id: format!("{}-{}", from, content),
+            from: user(from),
+            to: user(to),
+            time_sent: UNIX_EPOCH + Duration::from_secs(seconds),
+            content: String::from(content),
+            sequence_number: 0,
+        });
+    }
+
+    fn query(text: &str) -> SearchQuery {
+        SearchQuery {
+            terms: parse_query(text),
+            from_user_id: None,
+            conversation_id: None,
+            sent_after: None,
+            sent_before: None,
+            offset: 0,
+            limit: 20,
+        }
+    }
+
+    /// Returns the snippets of the hits, which are the whole content of short messages.
+    fn search(index: &MessageIndex, user_id: &str, query: &SearchQuery) -> Vec<String> {
+        let results = index.search(user_id, query);
+        assert_eq!(results.total, results.hits.len());
+
+        results.hits.into_iter().map(|hit| hit.snippet).collect()
+    }
+
+    #[test]
+    fn hits_contain_all_terms_and_are_ranked() {
+        let mut index = MessageIndex::new(10);
+        add(&mut index, "alice", "bob", "apple banana", 1);
+        add(&mut index, "alice", "bob", "apple apple apple cherry", 2);
+        add(&mut index, "alice", "bob", "banana", 3);
+
+        assert_eq!(
+            search(&index, "alice-id", &query("apple")),
+            vec!["apple apple apple cherry", "apple banana"]
+        );
+        assert_eq!(
+            search(&index, "alice-id", &query("Apple BANANA")),
+            vec!["apple banana"]
+        );
+        assert!(search(&index, "alice-id", &query("apple durian")).is_empty());
+    }
+
+    #[test]
+    fn prefix_terms_match_the_beginning_of_words() {
+        let mut index = MessageIndex::new(10);
+        add(&mut index, "alice", "bob", "an apple", 1);
+        add(&mut index, "alice", "bob", "the application", 2);
+        add(&mut index, "alice", "bob", "a pear", 3);
+
+        // equally good matches, the newer one first
+        assert_eq!(
+            search(&index, "alice-id", &query("app*")),
+            vec!["the application", "an apple"]
+        );
+        assert!(search(&index, "alice-id", &query("app")).is_empty());
+    }
+
+    #[test]
+    fn oldest_messages_are_dropped_at_capacity() {
+        let mut index = MessageIndex::new(2);
+        add(&mut index, "alice", "bob", "first common", 1);
+        add(&mut index, "alice", "bob", "second common", 2);
+        add(&mut index, "alice", "bob", "third common", 3);
+
+        assert!(search(&index, "alice-id", &query("first")).is_empty());
+        assert_eq!(
+            search(&index, "alice-id", &query("common")),
+            vec!["third common", "second common"]
+        );
+        assert_eq!(index.total_length, 4);
+        assert!(!index.postings.contains_key("first"));
+
+        let mut disabled = MessageIndex::new(0);
+        add(&mut disabled, "alice", "bob", "first", 1);
+        assert!(search(&disabled, "alice-id", &query("first")).is_empty());
+    }
+
+    #[test]
+    fn users_only_find_their_own_messages() {
+        let mut index = MessageIndex::new(10);
+        add(&mut index, "alice", "bob", "hello bob", 1);
+        add(&mut index, "bob", "alice", "hello alice", 2);
+        add(&mut index, "carol", "dave", "hello dave", 3);
+
+        assert_eq!(
+            search(&index, "alice-id", &query("hello")),
+            vec!["hello alice", "hello bob"]
+        );
+        assert_eq!(
+            search(&index, "dave-id", &query("hello")),
+            vec!["hello dave"]
+        );
+        assert!(search(&index, "erin-id", &query("hello")).is_empty());
+    }
+
+    #[test]
+    fn hits_are_filtered_by_sender_conversation_and_time() {
+        let mut index = MessageIndex::new(10);
+        add(&mut index, "alice", "bob", "hello bob", 10);
+        add(&mut index, "bob", "alice", "hello alice", 20);
+        add(&mut index, "alice", "carol", "hello carol", 30);
+
+        let mut from_bob = query("hello");
+        from_bob.from_user_id = Some(String::from("bob-id"));
+        assert_eq!(search(&index, "alice-id", &from_bob), vec!["hello alice"]);
+
+        let mut with_carol = query("hello");
+        with_carol.conversation_id = Some(conversation_id("carol-id", "alice-id"));
+        assert_eq!(search(&index, "alice-id", &with_carol), vec!["hello carol"]);
+
+        // sent_after is inclusive, sent_before exclusive
+        let mut between = query("hello");
+        between.sent_after = Some(UNIX_EPOCH + Duration::from_secs(10));
+        between.sent_before = Some(UNIX_EPOCH + Duration::from_secs(30));
+        assert_eq!(
+            search(&index, "alice-id", &between),
+            vec!["hello alice", "hello bob"]
+        );
+    }
+
+    #[test]
+    fn queries_without_terms_list_the_history() {
+        let mut index = MessageIndex::new(10);
+        add(&mut index, "alice", "bob", "hello bob", 10);
+        add(&mut index, "carol", "alice", "hello alice", 20);
+        add(&mut index, "bob", "alice", "how are you?", 30);
+
+        let mut with_bob = query("");
+        with_bob.conversation_id = Some(conversation_id("alice-id", "bob-id"));
+        assert_eq!(
+            search(&index, "alice-id", &with_bob),
+            vec!["how are you?", "hello bob"]
+        );
+
+        // other users can't list the conversation
+        assert!(search(&index, "carol-id", &with_bob).is_empty());
+    }
+
+    #[test]
+    fn pages_are_cut_from_all_hits() {
+        let mut index = MessageIndex::new(10);
+        for second in 0..5 {
+            add(
+                &mut index,
+                "alice",
+                "bob",
+                &format!("hello {}", second),
+                second,
+            );
+        }
+
+        let mut page = query("hello");
+        page.offset = 3;
+        page.limit = 3;
+
+        let results = index.search("alice-id", &page);
+        assert_eq!(results.total, 5);
+        let snippets: Vec<String> = results.hits.into_iter().map(|hit| hit.snippet).collect();
+        assert_eq!(snippets, vec!["hello 1", "hello 0"]);
+    }
+}
@@ -0,0 +1,126 @@
+use super::tokenizer::Token;
+use proto::chat;
+
+/// Maximum size of a snippet in bytes, without the ellipses.
+const MAX_SNIPPET_LENGTH: usize = 160;
+/// Number of words shown before the first match.
+const CONTEXT_WORDS: usize = 5;
+const ELLIPSIS: &str = "…";
+
+/// Cuts the part around the first match out of `content` and returns it together with the byte
+/// ranges of all matches within it. `matches` are the indexes of the matching tokens.
+pub fn snippet(
+    content: &str,
+    tokens: &[Token],
+    matches: &[usize],
+) -> (String, Vec<chat::search_hit::Highlight>) {
+    let first_match = matches.first().copied().unwrap_or(0);
+
+    let start = match first_match.checked_sub(CONTEXT_WORDS) {
+        Some(index) if index > 0 => tokens[index].start,
+        _ => 0,
+    };
+
+    let mut end = content.len();
+    if end - start > MAX_SNIPPET_LENGTH {
+        // end after the last word that fits completely, but show at least the first match
+        end = tokens
+            .iter()
+            .map(|token| token.end)
+            .rev()
+            .find(|&token_end| token_end > start && token_end - start <= MAX_SNIPPET_LENGTH)
+            .unwrap_or_else(|| tokens.get(first_match).map_or(content.len(), |t| t.end));
+    }
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push_str(ELLIPSIS);
+    }
+
+    let offset = snippet.len();
+    snippet.push_str(&content[start..end]);
+
+    if end < content.len() {
+        snippet.push_str(ELLIPSIS);
+    }
+
+    let highlights = matches
+        .iter()
+        .map(|&index| &tokens[index])
+        .filter(|token| token.start >= start && token.end <= end)
+        .map(|token| chat::search_hit::Highlight {
+            start: (token.start - start + offset) as u32,
+            end: (token.end - start + offset) as u32,
+        })
+        .collect();
+
+    (snippet, highlights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tokenizer;
+    use super::*;
+
+    /// Returns the snippet and the highlighted parts of it for the tokens equal to `term`.
+    fn snippet_of(content: &str, term: &str) -> (String, Vec<String>) {
+        let tokens = tokenizer::tokenize(content);
+        let matches: Vec<usize> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| token.term == term)
+            .map(|(index, _)| index)
+            .collect();
+
+        let (snippet, highlights) = snippet(content, &tokens, &matches);
+        let highlighted = highlights
+            .iter()
+            .map(|highlight| {
+                String::from(&snippet[highlight.start as usize..highlight.end as usize])
+            })
+            .collect();
+
+        (snippet, highlighted)
+    }
+
+    #[test]
+    fn short_messages_are_shown_whole() {
+        let (snippet, highlighted) = snippet_of("Hello World, hello!", "hello");
+
+        assert_eq!(snippet, "Hello World, hello!");
+        assert_eq!(highlighted, vec!["Hello", "hello"]);
+    }
+
+    #[test]
+    fn snippets_start_a_few_words_before_the_first_match() {
+        let (snippet, highlighted) = snippet_of("one two three four five six seven match", "match");
+
+        assert_eq!(snippet, "…three four five six seven match");
+        assert_eq!(highlighted, vec!["match"]);
+    }
+
+    #[test]
+    fn long_multi_byte_messages_are_cut_at_word_boundaries() {
+        // every word is 16 bytes long, followed by a space
+        let content = vec!["ééééüüüü"; 40].join(" ") + " größe " + &vec!["ñ"; 200].join(" ");
+        let (snippet, highlighted) = snippet_of(&content, "größe");
+
+        assert!(snippet.starts_with(ELLIPSIS));
+        assert!(snippet.ends_with(ELLIPSIS));
+
+        let text = &snippet[ELLIPSIS.len()..snippet.len() - ELLIPSIS.len()];
+        assert!(text.len() <= MAX_SNIPPET_LENGTH, "{} bytes", text.len());
+        assert!(text.starts_with("ééééüüüü"));
+        assert!(text.ends_with('ñ'));
+        assert_eq!(highlighted, vec!["größe"]);
+    }
+
+    #[test]
+    fn matches_beyond_the_cut_are_not_highlighted() {
+        let content = String::from("match ") + &vec!["word"; 50].join(" ") + " match";
+        let (snippet, highlighted) = snippet_of(&content, "match");
+
+        assert!(snippet.starts_with("match word"));
+        assert_eq!(highlighted, vec!["match"]);
+    }
+}
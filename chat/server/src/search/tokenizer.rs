@@ -0,0 +1,22 @@
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A word of a text, normalized for matching.
+pub struct Token {
+    pub term: String,
+    /// Byte range of the word in the original text.
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Splits a text into words at Unicode word boundaries. The terms are in normalization form KC and
+/// lowercase, so that e.g. "Ｃafé" and "cafe\u{301}" match.
+pub fn tokenize(text: &str) -> Vec<Token> {
+    text.unicode_word_indices()
+        .map(|(start, word)| Token {
+            term: word.nfkc().collect::<String>().to_lowercase(),
+            start,
+            end: start + word.len(),
+        })
+        .collect()
+}
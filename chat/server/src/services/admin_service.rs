@@ -0,0 +1,263 @@
+use crate::backplane::Node;
+use crate::error::ChatError;
+use crate::logging;
+use crate::metrics;
+use crate::validation::{self, MessageLimits};
+use crate::webhook::Webhooks;
+use crate::UserList;
+use chat::admin_service_server;
+use chat::*;
+use proto::chat;
+use std::sync::{Arc, Mutex};
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+pub struct AdminService {
+    users: Arc<Mutex<UserList>>,
+    node: Arc<Node>,
+    message_limits: MessageLimits,
+    webhooks: Arc<Webhooks>,
+}
+
+impl AdminService {
+    pub fn new(
+        users: Arc<Mutex<UserList>>,
+        node: Arc<Node>,
+        message_limits: MessageLimits,
+        webhooks: Arc<Webhooks>,
+        admin_token: String,
+    ) -> admin_service_server::AdminServiceServer<AdminService> {
+        let service = AdminService {
+            users,
+            node,
+            message_limits,
+            webhooks,
+        };
+
+        let check_auth = move |request: Request<()>| -> Result<Request<()>, Status> {
+            AdminService::authenticate(request, &admin_token)
+        };
+
+        admin_service_server::AdminServiceServer::with_interceptor(service, check_auth)
+    }
+
+    fn authenticate(request: Request<()>, admin_token: &str) -> Result<Request<()>, Status> {
+        let token = match request.metadata().get("admin_token") {
+            Some(token) => token.as_bytes(),
+            None => return Err(ChatError::MissingCredentials("admin_token").into()),
+        };
+
+        // compare in constant time to not leak the length of a matching prefix
+        let admin_token = admin_token.as_bytes();
+        let is_equal = token.len() == admin_token.len()
+            && token
+                .iter()
+                .zip(admin_token)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0;
+
+        match is_equal {
+            true => Ok(request),
+            false => Err(ChatError::InvalidCredentials.into()),
+        }
+    }
+
+    fn lock_users(&self) -> Result<std::sync::MutexGuard<'_, UserList>, ChatError> {
+        match self.users.lock() {
+            Ok(guard) => Ok(guard),
+            Err(_) => Err(ChatError::LockPoisoned),
+        }
+    }
+
+    async fn disconnect(
+        &self,
+        request: Request<DisconnectUserRequest>,
+    ) -> Result<Response<DisconnectUserResponse>, Status> {
+        let request = request.into_inner();
+        let reason = match request.reason.trim() {
+            "" => "disconnected by administrator",
+            reason => reason,
+        };
+
+        tracing::Span::current().record("user_id", request.user_id.as_str());
+
+        self.lock_users()?
+            .disconnect_user(&request.user_id, reason)?;
+
+        tracing::info!(%reason, "user disconnected");
+
+        Ok(Response::new(DisconnectUserResponse {}))
+    }
+
+    async fn register_bot(
+        &self,
+        request: Request<RegisterWebhookRequest>,
+    ) -> Result<Response<RegisterWebhookResponse>, Status> {
+        let request = request.into_inner();
+
+        let name = validation::validate_user_name("name", &request.name)?;
+        let url = validation::validate_webhook_url("url", &request.url)?;
+
+        let registration = self.webhooks.register(&name, url).await?;
+
+        tracing::Span::current().record("user_id", registration.user.id().as_str());
+        tracing::info!(%name, "webhook registered");
+
+        Ok(Response::new(RegisterWebhookResponse {
+            user: Some(registration.user.user()),
+            token: registration.user.token(),
+            signing_secret: registration.signing_secret,
+        }))
+    }
+
+    async fn broadcast_message(
+        &self,
+        request: Request<BroadcastRequest>,
+    ) -> Result<Response<BroadcastResponse>, Status> {
+        let request = request.into_inner();
+        let message = match request.message {
+            Some(message) => message,
+            None => return Err(Status::invalid_argument("request.message is invalid")),
+        };
+
+        validation::validate_message_content("message", &message, &self.message_limits)?;
+
+        let mut user_ids = request.user_ids;
+        user_ids.sort();
+        user_ids.dedup();
+
+        let notification = chat::IncomingNotification {
+            from: None,
+            types: Some(chat::incoming_notification::Types::SystemMessage(
+                chat::incoming_notification::SystemMessage {
+                    message_id: Some(chat::MessageId {
+                        id: Uuid::new_v4().to_hyphenated().to_string(),
+                    }),
+                    message_content: Some(message),
+                },
+            )),
+        };
+
+        let (mut recipients, remote_user_ids) = {
+            let users = self.lock_users()?;
+
+            let remote_user_ids = match user_ids.is_empty() {
+                true => users.remote_user_ids(),
+                false => {
+                    // fail before the message is sent to anyone
+                    if let Some(user_id) = user_ids
+                        .iter()
+                        .find(|id| users.get_user(id).is_err() && !users.is_remote_user(id))
+                    {
+                        return Err(ChatError::UserNotFound(user_id.clone()).into());
+                    }
+
+                    user_ids
+                        .iter()
+                        .filter(|id| users.is_remote_user(id))
+                        .cloned()
+                        .collect()
+                }
+            };
+
+            (users.broadcast(&notification, &user_ids), remote_user_ids)
+        };
+
+        for user_id in remote_user_ids {
+            match self.node.deliver(user_id, notification.clone()).await {
+                Ok(()) => recipients += 1,
+                Err(err) => tracing::warn!("could not broadcast to remote user: {}", err),
+            }
+        }
+
+        tracing::info!(recipients, "message broadcast");
+
+        Ok(Response::new(BroadcastResponse {
+            recipients: recipients as u32,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl admin_service_server::AdminService for AdminService {
+    async fn list_sessions(
+        &self,
+        request: Request<ListSessionsRequest>,
+    ) -> Result<Response<ListSessionsResponse>, Status> {
+        let span = tracing::info_span!("list_sessions", peer = %logging::peer(&request));
+
+        metrics::timed(
+            "list_sessions",
+            logging::traced(span, async {
+                let sessions = self.lock_users()?.sessions();
+
+                Ok(Response::new(ListSessionsResponse { sessions }))
+            }),
+        )
+        .await
+    }
+
+    async fn disconnect_user(
+        &self,
+        request: Request<DisconnectUserRequest>,
+    ) -> Result<Response<DisconnectUserResponse>, Status> {
+        let span = tracing::info_span!(
+            "disconnect_user",
+            peer = %logging::peer(&request),
+            user_id = tracing::field::Empty,
+        );
+
+        metrics::timed(
+            "disconnect_user",
+            logging::traced(span, self.disconnect(request)),
+        )
+        .await
+    }
+
+    async fn broadcast(
+        &self,
+        request: Request<BroadcastRequest>,
+    ) -> Result<Response<BroadcastResponse>, Status> {
+        let span = tracing::info_span!("broadcast", peer = %logging::peer(&request));
+
+        metrics::timed(
+            "broadcast",
+            logging::traced(span, self.broadcast_message(request)),
+        )
+        .await
+    }
+
+    async fn register_webhook(
+        &self,
+        request: Request<RegisterWebhookRequest>,
+    ) -> Result<Response<RegisterWebhookResponse>, Status> {
+        let span = tracing::info_span!(
+            "register_webhook",
+            peer = %logging::peer(&request),
+            user_id = tracing::field::Empty,
+        );
+
+        metrics::timed(
+            "register_webhook",
+            logging::traced(span, self.register_bot(request)),
+        )
+        .await
+    }
+
+    async fn get_queue_depths(
+        &self,
+        request: Request<GetQueueDepthsRequest>,
+    ) -> Result<Response<GetQueueDepthsResponse>, Status> {
+        let span = tracing::info_span!("get_queue_depths", peer = %logging::peer(&request));
+
+        metrics::timed(
+            "get_queue_depths",
+            logging::traced(span, async {
+                let queues = self.lock_users()?.queue_depths();
+
+                Ok(Response::new(GetQueueDepthsResponse { queues }))
+            }),
+        )
+        .await
+    }
+}
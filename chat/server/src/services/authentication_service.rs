@@ -1,86 +1,234 @@
-use crate::user_list::UserManagement;
+use crate::backplane::Node;
+use crate::logging;
+use crate::metrics;
+use crate::rate_limiter::RateLimiter;
+use crate::shutdown::Shutdown;
+use crate::user_list::UserRegistry;
 use crate::util;
+use crate::validation;
 use chat::authentication_service_server;
 use chat::*;
 use futures::channel::oneshot;
 use proto::chat;
-use std::sync::{Arc, Mutex};
+use std::net::IpAddr;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use tonic::{Request, Response, Status};
+use tracing::Instrument;
 
 pub struct AuthenticationService {
-    users: Arc<Mutex<dyn UserManagement + Send + Sync>>,
+    users: Arc<dyn UserRegistry>,
+    rate_limiter: Arc<RateLimiter<IpAddr>>,
+    shutdown: Arc<Shutdown>,
+    node: Arc<Node>,
 }
 
 impl AuthenticationService {
     pub fn new(
-        users: Arc<Mutex<dyn UserManagement + Send + Sync>>,
+        users: Arc<dyn UserRegistry>,
+        rate_limiter: Arc<RateLimiter<IpAddr>>,
+        shutdown: Arc<Shutdown>,
+        node: Arc<Node>,
     ) -> authentication_service_server::AuthenticationServiceServer<AuthenticationService> {
-        let service = AuthenticationService { users: users };
+        let service = AuthenticationService {
+            users,
+            rate_limiter,
+            shutdown,
+            node,
+        };
 
         authentication_service_server::AuthenticationServiceServer::new(service)
     }
+
+    async fn create_session(
+        &self,
+        request: Request<AuthenticateRequest>,
+    ) -> Result<Response<util::ResponseStream<AuthenticateResponse>>, Status> {
+        self.shutdown.check()?;
+
+        // limit the number of users a single peer can create
+        if let Some(remote_addr) = request.remote_addr() {
+            self.rate_limiter.check(remote_addr.ip())?;
+        }
+
+        let request = request.into_inner();
+
+        let name = validation::validate_user_name("name", &request.name)?;
+
+        // create user
+        let user = match self.users.create_user(&name).await {
+            Ok(user) => user,
+            Err(err) => return Err(err.into()),
+        };
+
+        tracing::Span::current().record("user_id", user.id().as_str());
+        tracing::info!(name = %user.name(), "user logged in");
+
+        let (finish_tx, mut finish_rx) = oneshot::channel();
+        let (mut stream_tx, stream_rx) = mpsc::channel(4);
+
+        let users = self.users.clone();
+        let shutdown = self.shutdown.clone();
+        let node = self.node.clone();
+
+        tokio::spawn(
+            async move {
+                // report new user id back to caller
+                let response = Ok(AuthenticateResponse {
+                    id: user.id(),
+                    token: user.token(),
+                });
+
+                stream_tx.try_send(response).unwrap();
+
+                // wait until stream is finished, the user is disconnected or the server shuts down
+                let finished = tokio::select! {
+                    reason = &mut finish_rx => {
+                        if let Ok(reason) = reason {
+                            tracing::debug!(%reason, "session stream closed");
+                        }
+                        true
+                    }
+                    Some(reason) = user.disconnected() => {
+                        // the user has already been notified, end the stream and remove the user
+                        drop(stream_tx);
+                        tracing::info!(%reason, "user disconnected by the server");
+                        false
+                    }
+                    _ = shutdown.sessions_closed() => {
+                        // the shutdown sequence has already notified all users, just end the stream
+                        drop(stream_tx);
+                        let _ = finish_rx.await;
+
+                        tracing::info!("session closed by server shutdown");
+                        return;
+                    }
+                };
+
+                // remove user from internal list
+                match users.remove_user(user.id().as_str()).await {
+                    Ok(()) => {
+                        tracing::info!("user logged out");
+                        node.publish_presence(user.user(), false).await;
+                    }
+                    Err(e) => tracing::error!("error removing user: {}", e),
+                };
+
+                // keep the close notification of a disconnected user's stream deliverable
+                if !finished {
+                    let _ = finish_rx.await;
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+
+        // the session ends however the stream is closed
+        let response_stream =
+            util::ResponseStream::new("authenticate", stream_rx).on_close(move |reason| {
+                let _ = finish_tx.send(reason);
+            });
+
+        Ok(Response::new(response_stream))
+    }
 }
 
 #[tonic::async_trait]
 impl authentication_service_server::AuthenticationService for AuthenticationService {
-    type AuthenticateStream = util::ResponseStream<Result<AuthenticateResponse, Status>>;
+    type AuthenticateStream = util::ResponseStream<AuthenticateResponse>;
 
     async fn authenticate(
         &self,
         request: Request<AuthenticateRequest>,
     ) -> Result<Response<Self::AuthenticateStream>, Status> {
-        let request = request.into_inner();
+        let span = tracing::info_span!(
+            "authenticate",
+            peer = %logging::peer(&request),
+            user_id = tracing::field::Empty,
+        );
+
+        metrics::timed(
+            "authenticate",
+            logging::traced(span, self.create_session(request)),
+        )
+        .await
+    }
+}
 
-        // create user
-        let user;
-        {
-            let mut users = match self.users.lock() {
-                Ok(guard) => guard,
-                Err(_) => return Err(Status::internal("unable to acquire lock")),
-            };
-
-            user = match users.create_user(&request.name) {
-                Ok(user) => user,
-                Err(err) => return Err(Status::internal(err)),
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backplane::MemoryBackplane;
+    use crate::config::LimitsConfig;
+    use crate::search::MessageIndex;
+    use crate::user_list::{MockRegistry, UserList};
+    use futures::StreamExt;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tonic::Code;
+
+    fn authentication_service(users: Arc<MockRegistry>) -> AuthenticationService {
+        let node = Node::new(
+            Box::new(MemoryBackplane::new()),
+            Arc::new(Mutex::new(UserList::new())),
+            Arc::new(Mutex::new(MessageIndex::new(0))),
+        );
+
+        AuthenticationService {
+            users,
+            rate_limiter: Arc::new(RateLimiter::new(LimitsConfig::default().auth_rate_limit())),
+            shutdown: Arc::new(Shutdown::new()),
+            node: Arc::new(node),
         }
+    }
 
-        let (finish_tx, finish_rx) = oneshot::channel();
-        let (mut stream_tx, stream_rx) = mpsc::channel(4);
+    fn authenticate_request(name: &str) -> Request<AuthenticateRequest> {
+        Request::new(AuthenticateRequest {
+            name: String::from(name),
+        })
+    }
 
-        let users = self.users.clone();
+    #[tokio::test]
+    async fn session_registers_the_user_until_it_ends() {
+        let users = Arc::new(MockRegistry::new());
+        let service = authentication_service(users.clone());
 
-        tokio::spawn(async move {
-            // report new user id back to caller
-            let response = Ok(AuthenticateResponse {
-                id: user.id(),
-                token: user.token(),
-            });
+        let mut session = service
+            .create_session(authenticate_request("alice"))
+            .await
+            .unwrap()
+            .into_inner();
 
-            stream_tx.try_send(response).unwrap();
+        let credentials = session.next().await.unwrap().unwrap();
+        let alice = users.user(&credentials.id).unwrap();
+        assert_eq!(alice.token(), credentials.token);
+        assert!(!alice.is_online());
+        assert_eq!(users.calls(), vec!["create_user alice"]);
 
-            // wait until stream is finished
-            finish_rx.await.unwrap();
+        drop(session);
 
-            // remove user from internal list
-            let remove_user_result;
-            {
-                let mut users = users.lock().unwrap();
-                remove_user_result = users.remove_user(user.id().as_str());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while users.user(&credentials.id).is_some() {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
             }
+        })
+        .await
+        .expect("user was not removed");
+        assert_eq!(users.calls().last().unwrap(), "remove_user alice");
+    }
 
-            match remove_user_result {
-                Ok(()) => {}
-                Err(e) => {
-                    eprintln!("Error removing user: {}", e);
-                }
-            };
-        });
+    #[tokio::test]
+    async fn taken_names_are_rejected() {
+        let users = Arc::new(MockRegistry::new());
+        users.add_user("alice");
+        let service = authentication_service(users.clone());
 
-        let response_stream =
-            util::ResponseStream::new_with_close_notification(finish_tx, stream_rx);
+        let status = service
+            .create_session(authenticate_request("alice"))
+            .await
+            .err()
+            .unwrap();
 
-        Ok(Response::new(response_stream))
+        assert_eq!(status.code(), Code::AlreadyExists);
+        assert_eq!(users.calls(), vec!["create_user alice"]);
     }
 }
@@ -1,45 +1,131 @@
+use crate::backplane::Node;
+use crate::error::ChatError;
+use crate::logging;
+use crate::metrics;
+use crate::rate_limiter::RateLimiter;
+use crate::search::{IndexedMessage, MessageIndex};
+use crate::shutdown::{DrainGuard, Shutdown};
+use crate::user_list::{
+    self, Idempotency, NotificationReceiver, Recipient, UserData, UserRegistry,
+};
 use crate::util;
-use crate::UserList;
+use crate::validation::{self, MessageLimits};
 use chat::chat_service_server;
 use chat::*;
-use futures::stream::StreamExt;
+use futures::future;
+use futures::stream::{self, Stream, StreamExt};
 use proto::chat;
+use std::convert::TryFrom;
 use std::sync::{Arc, Mutex};
-use tonic::{Request, Response, Status};
+use std::time::{Duration, SystemTime};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Mutex as AsyncMutex;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::Instrument;
 use uuid::Uuid;
 
+/// Number of acks a connect stream buffers before its requests are no longer read.
+const CONNECT_ACK_CAPACITY: usize = 16;
+
+/// The notification queue of a user while a stream is receiving it.
+type SharedReceiver = Arc<AsyncMutex<Option<NotificationReceiver>>>;
+
+#[derive(Clone)]
 pub struct ChatService {
-    users: Arc<Mutex<UserList>>,
+    users: Arc<dyn UserRegistry>,
+    rate_limiter: Arc<RateLimiter<String>>,
+    message_limits: MessageLimits,
+    receive_idle_timeout: Option<Duration>,
+    shutdown: Arc<Shutdown>,
+    node: Arc<Node>,
+    index: Arc<Mutex<MessageIndex>>,
 }
 
 impl ChatService {
-    pub fn new(users: Arc<Mutex<UserList>>) -> chat_service_server::ChatServiceServer<ChatService> {
-        let service = ChatService { users: users };
-
-        let check_auth;
-        {
-            let users = service.users.clone();
-            check_auth = move |request: Request<()>| -> Result<Request<()>, Status> {
-                UserList::authenticate(request, &users)
-            };
-        }
+    pub fn new(
+        users: Arc<dyn UserRegistry>,
+        rate_limiter: Arc<RateLimiter<String>>,
+        message_limits: MessageLimits,
+        receive_idle_timeout: Option<Duration>,
+        shutdown: Arc<Shutdown>,
+        node: Arc<Node>,
+        index: Arc<Mutex<MessageIndex>>,
+    ) -> chat_service_server::ChatServiceServer<ChatService> {
+        let service = ChatService {
+            users,
+            rate_limiter,
+            message_limits,
+            receive_idle_timeout,
+            shutdown,
+            node,
+            index,
+        };
 
-        chat_service_server::ChatServiceServer::with_interceptor(service, check_auth)
+        chat_service_server::ChatServiceServer::new(service)
     }
-}
 
-#[tonic::async_trait]
-impl chat_service_server::ChatService for ChatService {
-    type ReceiveStream = util::ResponseStream<Result<ReceiveResponse, Status>>;
+    /// Returns the user making the request. Interceptors can't wait for the registry, so every
+    /// request authenticates itself.
+    async fn authenticate<T: Sync>(&self, request: &Request<T>) -> Result<UserData, ChatError> {
+        let (user_id, user_token) = user_list::credentials(request)?;
 
-    async fn send(&self, request: Request<SendRequest>) -> Result<Response<SendResponse>, Status> {
-        let user = match UserList::get_user_from_request(&request, &self.users) {
+        self.users.authenticate(&user_id, &user_token).await
+    }
+
+    async fn send_notification(
+        &self,
+        request: Request<SendRequest>,
+    ) -> Result<Response<SendResponse>, Status> {
+        let user = match self.authenticate(&request).await {
             Ok(user) => user,
-            Err(err) => return Err(Status::internal(err)),
+            Err(err) => return Err(err.into()),
         };
 
         let request = request.into_inner();
-        let notification = match request.notification {
+        validation::validate_idempotency_key("idempotency_key", &request.idempotency_key)?;
+
+        // without a key every request is sent
+        if request.idempotency_key.is_empty() {
+            let reply = self
+                .deliver_notification(&user, request.notification)
+                .await?;
+            return Ok(Response::new(reply));
+        }
+
+        match user.outbox().begin(&request.idempotency_key) {
+            Ok(Idempotency::Send) => {}
+            Ok(Idempotency::Sent(reply)) => {
+                tracing::debug!("request already sent");
+                return Ok(Response::new(reply));
+            }
+            Ok(Idempotency::InProgress) => {
+                return Err(Status::aborted(
+                    "a request with this idempotency key is in progress",
+                ))
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        // failed requests release their key, so they can be retried
+        let result = self.deliver_notification(&user, request.notification).await;
+        user.outbox()
+            .finish(&request.idempotency_key, result.as_ref().ok());
+
+        Ok(Response::new(result?))
+    }
+
+    /// Delivers a notification sent by `user` to its recipient, shared by Send and Connect.
+    async fn deliver_notification(
+        &self,
+        user: &UserData,
+        notification: Option<OutgoingNotification>,
+    ) -> Result<SendResponse, Status> {
+        let span = tracing::Span::current();
+
+        self.rate_limiter.check(user.id())?;
+
+        let notification = match notification {
             Some(notification) => notification,
             None => return Err(Status::invalid_argument("request.notification is invalid")),
         };
@@ -53,22 +139,20 @@ impl chat_service_server::ChatService for ChatService {
             }
         };
 
-        // get the receiving user
-        let mut to_user_sender;
-        {
-            let users = match self.users.lock() {
-                Ok(guard) => guard,
-                Err(_) => return Err(Status::internal("unable to acquire lock")),
-            };
+        span.record("to_user_id", to_user.id.as_str());
 
-            to_user_sender = match users.get_user(to_user.id.as_str()) {
-                Ok(user) => user.user_data.sender(),
-                Err(e) => return Err(Status::internal(e)),
-            };
-        }
+        // get the receiving user, users connected to other nodes are reached via the backplane
+        let (recipient, mut to_user_sender) = match self.users.lookup(&to_user.id).await {
+            Ok(Recipient::Local(user)) => (user.user(), Some(user.sender())),
+            Ok(Recipient::Remote(user)) => (user, None),
+            Err(err) => return Err(err.into()),
+        };
 
         // create a default reply
-        let mut reply = chat::SendResponse { message_id: None };
+        let mut reply = chat::SendResponse {
+            message_id: None,
+            sequence_number: 0,
+        };
 
         let notification_type = match notification.types {
             Some(notification_type) => notification_type,
@@ -79,66 +163,834 @@ impl chat_service_server::ChatService for ChatService {
             }
         };
 
-        let mut incoming_notification = None;
-        match notification_type {
-            chat::outgoing_notification::Types::Typing(_typing) => {
-                // TODO
+        let mut indexed_message = None;
+        // the number of the last message to the recipient, locked until the message is delivered
+        let mut sequence_number_guard = None;
+        let incoming_notification = match notification_type {
+            chat::outgoing_notification::Types::Typing(typing) => {
+                // the user is typing until the expiration, or until they say otherwise without one
+                let is_typing = match typing.expiration.map(SystemTime::try_from) {
+                    Some(Ok(expiration)) => expiration > SystemTime::now(),
+                    Some(Err(_)) => {
+                        return Err(Status::invalid_argument(
+                            "request.notification.typing.expiration is invalid",
+                        ))
+                    }
+                    None => true,
+                };
+
+                chat::IncomingNotification {
+                    from: Some(user.user()),
+                    types: Some(chat::incoming_notification::Types::Typing(
+                        chat::incoming_notification::Typing { is_typing },
+                    )),
+                }
             }
-            chat::outgoing_notification::Types::Read(_read) => {
-                // TODO
+            chat::outgoing_notification::Types::Read(read) => {
+                if read.message_id.is_none() {
+                    return Err(Status::invalid_argument(
+                        "request.notification.read.message_id is invalid",
+                    ));
+                }
+
+                let time_read = match read.time_read {
+                    Some(time_read) => time_read,
+                    None => SystemTime::now().into(),
+                };
+
+                chat::IncomingNotification {
+                    from: Some(user.user()),
+                    types: Some(chat::incoming_notification::Types::Read(
+                        chat::incoming_notification::Read {
+                            message_id: read.message_id,
+                            time_read: Some(time_read),
+                        },
+                    )),
+                }
             }
             chat::outgoing_notification::Types::Message(message) => {
+                validation::validate_message_content(
+                    "notification.message",
+                    &message,
+                    &self.message_limits,
+                )?;
+
                 // TODO: enqueue message id somewhere
                 let message_id = Uuid::new_v4();
                 let message_id_string = message_id.to_hyphenated().to_string();
 
-                incoming_notification = Some(chat::IncomingNotification {
+                span.record("message_id", message_id_string.as_str());
+
+                let last_sequence_number = match user.outbox().sequence_number(&recipient.id) {
+                    Ok(sequence_number) => sequence_number.lock_owned().await,
+                    Err(err) => return Err(err.into()),
+                };
+                let sequence_number = *last_sequence_number + 1;
+                sequence_number_guard = Some(last_sequence_number);
+
+                indexed_message = Some(IndexedMessage {
+                    message_id: message_id_string.clone(),
+                    from: user.user(),
+                    to: recipient,
+                    time_sent: SystemTime::now(),
+                    content: message.content.clone(),
+                    sequence_number,
+                });
+
+                // return the message id of this message
+                reply.message_id = Some(chat::MessageId {
+                    id: message_id_string.clone(),
+                });
+                reply.sequence_number = sequence_number;
+
+                chat::IncomingNotification {
                     from: Some(user.user()),
                     types: Some(chat::incoming_notification::Types::Message(
                         chat::incoming_notification::Message {
                             message_id: Some(chat::MessageId {
-                                id: message_id_string.clone(),
+                                id: message_id_string,
                             }),
                             message_content: Some(message),
+                            sequence_number,
                         },
                     )),
-                });
+                }
+            }
+        };
 
-                // return the message id of this message
-                reply.message_id = Some(chat::MessageId {
-                    id: message_id_string,
-                });
+        match &mut to_user_sender {
+            // send notification to receiving user
+            Some(to_user_sender) => match to_user_sender.try_send(incoming_notification) {
+                Ok(()) => tracing::debug!("notification enqueued"),
+                Err(TrySendError::Full(_)) => {
+                    metrics::NOTIFICATIONS_DROPPED
+                        .with_label_values(&["message"])
+                        .inc();
+                    return Err(ChatError::NotificationQueueFull(to_user.id).into());
+                }
+                Err(TrySendError::Closed(_)) => {
+                    metrics::NOTIFICATIONS_DROPPED
+                        .with_label_values(&["message"])
+                        .inc();
+                    return Err(ChatError::RecipientUnavailable(to_user.id).into());
+                }
+            },
+            None => {
+                self.node.deliver(to_user.id, incoming_notification).await?;
+                tracing::debug!("notification published to backplane");
             }
         }
 
-        let incoming_notification = match incoming_notification {
-            Some(notification) => notification,
-            None => return Err(Status::internal("notification could not be created")),
-        };
+        // the number is used up once the message has been delivered
+        if let Some(mut last_sequence_number) = sequence_number_guard {
+            *last_sequence_number = reply.sequence_number;
+        }
+
+        if let Some(message) = indexed_message {
+            metrics::MESSAGES_SENT.inc();
 
-        // send notification to receiving user
-        if to_user_sender.try_send(incoming_notification).is_err() {
-            return Err(Status::internal("Could not send notification"));
+            match self.index.lock() {
+                Ok(mut index) => index.add(message),
+                Err(_) => tracing::error!("unable to acquire lock, message not indexed"),
+            }
         }
 
-        Ok(Response::new(reply))
+        Ok(reply)
     }
 
-    async fn receive(
+    async fn search_messages(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        let user = match self.authenticate(&request).await {
+            Ok(user) => user,
+            Err(err) => return Err(err.into()),
+        };
+
+        let query = validation::validate_search_request(request.get_ref())?;
+
+        let results = match self.index.lock() {
+            Ok(index) => index.search(&user.id(), &query),
+            Err(_) => return Err(ChatError::LockPoisoned.into()),
+        };
+
+        // the offset of the next page, as long as there are more hits
+        let next_offset = query.offset + results.hits.len();
+        let next_page_token = match next_offset < results.total && !results.hits.is_empty() {
+            true => next_offset.to_string(),
+            false => String::new(),
+        };
+
+        tracing::debug!(
+            hits = results.hits.len(),
+            total = results.total,
+            "search finished"
+        );
+
+        Ok(Response::new(SearchResponse {
+            hits: results.hits,
+            next_page_token,
+            total_hits: results.total as u32,
+        }))
+    }
+
+    async fn list_messages_sent(
+        &self,
+        request: Request<ListMessagesRequest>,
+    ) -> Result<Response<ListMessagesResponse>, Status> {
+        let user = match self.authenticate(&request).await {
+            Ok(user) => user,
+            Err(err) => return Err(err.into()),
+        };
+
+        let request = request.into_inner();
+        let limit = validation::validate_list_messages_request(&request)?;
+
+        let index = match self.index.lock() {
+            Ok(index) => index,
+            Err(_) => return Err(ChatError::LockPoisoned.into()),
+        };
+
+        let messages: Vec<IncomingNotification> = index
+            .messages_after(
+                &request.from_user_id,
+                &user.id(),
+                request.after_sequence_number,
+                limit,
+            )
+            .into_iter()
+            .map(|message| IncomingNotification {
+                from: Some(message.from.clone()),
+                types: Some(incoming_notification::Types::Message(
+                    incoming_notification::Message {
+                        message_id: Some(MessageId {
+                            id: message.message_id.clone(),
+                        }),
+                        message_content: Some(MessageContent {
+                            time_sent: Some(message.time_sent.into()),
+                            content: message.content.clone(),
+                        }),
+                        sequence_number: message.sequence_number,
+                    },
+                )),
+            })
+            .collect();
+
+        tracing::debug!(messages = messages.len(), "messages listed");
+
+        Ok(Response::new(ListMessagesResponse { messages }))
+    }
+
+    async fn open_receive_stream(
         &self,
         request: Request<ReceiveRequest>,
-    ) -> Result<Response<Self::ReceiveStream>, Status> {
-        let notifications_rx = match UserList::take_receiver(&request, &self.users) {
-            Ok(rx) => rx,
-            Err(err) => return Err(Status::internal(err)),
+    ) -> Result<Response<util::ResponseStream<ReceiveResponse>>, Status> {
+        let span = tracing::Span::current();
+
+        // delays the server shutdown until the notifications of this stream are drained
+        let drain_guard = match self.shutdown.drain_guard() {
+            Ok(guard) => guard,
+            Err(err) => return Err(err.into()),
         };
 
-        let response_stream = util::ResponseStream::new(notifications_rx.map(|notification| {
+        let user = match self.authenticate(&request).await {
+            Ok(user) => user,
+            Err(err) => return Err(err.into()),
+        };
+
+        let receiver = self.start_receiving(&user).await?;
+
+        let notifications = ChatService::notifications(receiver.clone(), drain_guard);
+        let notifications = notifications.map(|notification| {
             Ok(ReceiveResponse {
                 notification: Some(notification),
             })
-        }));
+        });
+
+        let close_span = span.clone();
+        let users = self.users.clone();
+        let node = self.node.clone();
+
+        let mut response_stream = util::ResponseStream::new("receive", notifications)
+            .on_item(move |_| {
+                span.in_scope(|| tracing::debug!("delivering notification"));
+                metrics::NOTIFICATIONS_DELIVERED.inc();
+            })
+            .on_close(move |reason| {
+                close_span.in_scope(|| tracing::info!(%reason, "receive stream closed"));
+
+                // streams dropped while the runtime shuts down can't hand back their receiver,
+                // the server is stopping anyway
+                if let Ok(runtime) = Handle::try_current() {
+                    runtime.spawn(
+                        ChatService::stop_receiving(users, node, user.user(), receiver)
+                            .instrument(close_span),
+                    );
+                }
+            });
+
+        if let Some(timeout) = self.receive_idle_timeout {
+            response_stream = response_stream.idle_timeout(timeout);
+        }
+
+        Ok(Response::new(response_stream))
+    }
+
+    async fn open_connect_stream(
+        &self,
+        request: Request<Streaming<ConnectRequest>>,
+    ) -> Result<Response<util::ResponseStream<ConnectResponse>>, Status> {
+        let span = tracing::Span::current();
+
+        let drain_guard = match self.shutdown.drain_guard() {
+            Ok(guard) => guard,
+            Err(err) => return Err(err.into()),
+        };
+
+        let user = match self.authenticate(&request).await {
+            Ok(user) => user,
+            Err(err) => return Err(err.into()),
+        };
+
+        let receiver = self.start_receiving(&user).await?;
+
+        // requests are handled one after another by a task of their own, their acks are merged
+        // into the notifications
+        let (acks_tx, acks_rx) = mpsc::channel(CONNECT_ACK_CAPACITY);
+        let service = self.clone();
+        let requests_user = user.clone();
+        tokio::spawn(
+            async move {
+                service
+                    .handle_connect_requests(request.into_inner(), requests_user, acks_tx)
+                    .await
+            }
+            .instrument(span.clone()),
+        );
+
+        // the stream ends with the notifications, an end marker stops the merged stream
+        let notifications = ChatService::notifications(receiver.clone(), drain_guard)
+            .map(|notification| Some(connect_response::Types::Notification(notification)))
+            .chain(stream::once(async { None }));
+        let acks = acks_rx.map(|ack| Some(connect_response::Types::Ack(ack)));
+
+        let responses = stream::select(notifications, acks)
+            .take_while(|types| future::ready(types.is_some()))
+            .map(|types| Ok(ConnectResponse { types }));
+
+        let close_span = span.clone();
+        let users = self.users.clone();
+        let node = self.node.clone();
+
+        let mut response_stream = util::ResponseStream::new("connect", responses)
+            .on_item(move |response| {
+                if let Ok(ConnectResponse {
+                    types: Some(connect_response::Types::Notification(_)),
+                }) = response
+                {
+                    span.in_scope(|| tracing::debug!("delivering notification"));
+                    metrics::NOTIFICATIONS_DELIVERED.inc();
+                }
+            })
+            .on_close(move |reason| {
+                close_span.in_scope(|| tracing::info!(%reason, "connect stream closed"));
+
+                // streams dropped while the runtime shuts down can't hand back their receiver,
+                // the server is stopping anyway
+                if let Ok(runtime) = Handle::try_current() {
+                    runtime.spawn(
+                        ChatService::stop_receiving(users, node, user.user(), receiver)
+                            .instrument(close_span),
+                    );
+                }
+            });
+
+        if let Some(timeout) = self.receive_idle_timeout {
+            response_stream = response_stream.idle_timeout(timeout);
+        }
 
         Ok(Response::new(response_stream))
     }
+
+    /// Answers every request of a connect stream with an ack until the client stops sending.
+    async fn handle_connect_requests(
+        &self,
+        mut requests: Streaming<ConnectRequest>,
+        user: UserData,
+        mut acks_tx: mpsc::Sender<connect_response::Ack>,
+    ) {
+        loop {
+            let request = match requests.message().await {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(status) => {
+                    tracing::debug!(%status, "connect requests failed");
+                    break;
+                }
+            };
+
+            let span = tracing::info_span!(
+                "connect_request",
+                correlation_id = request.correlation_id,
+                to_user_id = tracing::field::Empty,
+                message_id = tracing::field::Empty,
+            );
+
+            let correlation_id = request.correlation_id;
+            let result = metrics::timed(
+                "connect_request",
+                logging::traced(span, self.handle_connect_request(&user, request.types)),
+            )
+            .await;
+
+            let ack = match result {
+                Ok(reply) => connect_response::Ack {
+                    correlation_id,
+                    message_id: reply.message_id,
+                    error: None,
+                },
+                Err(status) => connect_response::Ack {
+                    correlation_id,
+                    message_id: None,
+                    error: Some(util::to_rpc_status(&status)),
+                },
+            };
+
+            // the connect stream has been closed
+            if acks_tx.send(ack).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Handles a single request of a connect stream. Every request authenticates the user again,
+    /// which records them as seen and fails once they logged out.
+    async fn handle_connect_request(
+        &self,
+        user: &UserData,
+        request: Option<connect_request::Types>,
+    ) -> Result<SendResponse, Status> {
+        let user = match self.users.authenticate(&user.id(), &user.token()).await {
+            Ok(user) => user,
+            Err(err) => return Err(err.into()),
+        };
+
+        match request {
+            Some(connect_request::Types::Notification(notification)) => {
+                self.deliver_notification(&user, Some(notification)).await
+            }
+            Some(connect_request::Types::Heartbeat(_)) => Ok(SendResponse {
+                message_id: None,
+                sequence_number: 0,
+            }),
+            None => Err(Status::invalid_argument("request.types is invalid")),
+        }
+    }
+
+    /// Hands out the notification queue of the user and marks them online, users are online
+    /// while they are receiving notifications. The receiver is handed back to the user by
+    /// `stop_receiving` once the stream is closed.
+    async fn start_receiving(&self, user: &UserData) -> Result<SharedReceiver, Status> {
+        let receiver = match self.users.take_receiver(&user.id()).await {
+            Ok(receiver) => receiver,
+            Err(err) => return Err(err.into()),
+        };
+
+        if let Err(err) = self.users.set_online(&user.id(), true).await {
+            let _ = self.users.return_receiver(&user.id(), receiver).await;
+            return Err(err.into());
+        }
+        self.node.publish_presence(user.user(), true).await;
+
+        Ok(Arc::new(AsyncMutex::new(Some(receiver))))
+    }
+
+    /// Returns the notifications of the receiver, ending right after the server shutdown or
+    /// disconnected notification has been delivered.
+    fn notifications(
+        receiver: SharedReceiver,
+        drain_guard: DrainGuard,
+    ) -> impl Stream<Item = IncomingNotification> {
+        stream::unfold(
+            (receiver, Some(drain_guard)),
+            |(receiver, drain_guard)| async move {
+                drain_guard.as_ref()?;
+
+                let notification = receiver.lock().await.as_mut()?.recv().await?;
+                let drain_guard = match notification.types {
+                    Some(incoming_notification::Types::ServerShutdown(_))
+                    | Some(incoming_notification::Types::Disconnected(_)) => None,
+                    _ => drain_guard,
+                };
+
+                Some((notification, (receiver, drain_guard)))
+            },
+        )
+    }
+
+    /// Hands the receiver of a closed receive stream back to the user and marks them offline,
+    /// unless a new stream is already receiving. Notifications enqueued in the meantime are
+    /// delivered to the next receive stream.
+    async fn stop_receiving(
+        users: Arc<dyn UserRegistry>,
+        node: Arc<Node>,
+        user: chat::User,
+        receiver: SharedReceiver,
+    ) {
+        let receiver = receiver.lock().await.take();
+
+        // fails if the user has already logged out
+        if let Ok(true) = users.stop_receiving(&user.id, receiver).await {
+            node.publish_presence(user, false).await;
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl chat_service_server::ChatService for ChatService {
+    type ReceiveStream = util::ResponseStream<ReceiveResponse>;
+    type OpenConnectionStream = util::ResponseStream<ConnectResponse>;
+
+    async fn send(&self, request: Request<SendRequest>) -> Result<Response<SendResponse>, Status> {
+        let span = tracing::info_span!(
+            "send",
+            peer = %logging::peer(&request),
+            user_id = %logging::user_id(&request),
+            to_user_id = tracing::field::Empty,
+            message_id = tracing::field::Empty,
+        );
+
+        metrics::timed(
+            "send",
+            logging::traced(span, self.send_notification(request)),
+        )
+        .await
+    }
+
+    async fn heartbeat(
+        &self,
+        request: Request<HeartbeatRequest>,
+    ) -> Result<Response<HeartbeatResponse>, Status> {
+        let span = tracing::info_span!(
+            "heartbeat",
+            peer = %logging::peer(&request),
+            user_id = %logging::user_id(&request),
+        );
+
+        // authenticating records the user as seen
+        metrics::timed(
+            "heartbeat",
+            logging::traced(span, async {
+                self.authenticate(&request).await?;
+
+                Ok(Response::new(HeartbeatResponse {}))
+            }),
+        )
+        .await
+    }
+
+    async fn search(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        let span = tracing::info_span!(
+            "search",
+            peer = %logging::peer(&request),
+            user_id = %logging::user_id(&request),
+        );
+
+        metrics::timed(
+            "search",
+            logging::traced(span, self.search_messages(request)),
+        )
+        .await
+    }
+
+    async fn list_users(
+        &self,
+        request: Request<ListUsersRequest>,
+    ) -> Result<Response<ListUsersResponse>, Status> {
+        let span = tracing::info_span!(
+            "list_users",
+            peer = %logging::peer(&request),
+            user_id = %logging::user_id(&request),
+        );
+
+        metrics::timed(
+            "list_users",
+            logging::traced(span, async {
+                self.authenticate(&request).await?;
+
+                let users = match self.users.online_users().await {
+                    Ok(users) => users,
+                    Err(err) => return Err(err.into()),
+                };
+
+                Ok(Response::new(ListUsersResponse { users }))
+            }),
+        )
+        .await
+    }
+
+    async fn list_messages(
+        &self,
+        request: Request<ListMessagesRequest>,
+    ) -> Result<Response<ListMessagesResponse>, Status> {
+        let span = tracing::info_span!(
+            "list_messages",
+            peer = %logging::peer(&request),
+            user_id = %logging::user_id(&request),
+        );
+
+        metrics::timed(
+            "list_messages",
+            logging::traced(span, self.list_messages_sent(request)),
+        )
+        .await
+    }
+
+    async fn receive(
+        &self,
+        request: Request<ReceiveRequest>,
+    ) -> Result<Response<Self::ReceiveStream>, Status> {
+        let span = tracing::info_span!(
+            "receive",
+            peer = %logging::peer(&request),
+            user_id = %logging::user_id(&request),
+        );
+
+        metrics::timed(
+            "receive",
+            logging::traced(span, self.open_receive_stream(request)),
+        )
+        .await
+    }
+
+    async fn open_connection(
+        &self,
+        request: Request<Streaming<ConnectRequest>>,
+    ) -> Result<Response<Self::OpenConnectionStream>, Status> {
+        let span = tracing::info_span!(
+            "open_connection",
+            peer = %logging::peer(&request),
+            user_id = %logging::user_id(&request),
+        );
+
+        metrics::timed(
+            "open_connection",
+            logging::traced(span, self.open_connect_stream(request)),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backplane::MemoryBackplane;
+    use crate::config::LimitsConfig;
+    use crate::user_list::{MockRegistry, UserList};
+    use tonic::Code;
+
+    fn chat_service(users: Arc<MockRegistry>) -> ChatService {
+        let limits = LimitsConfig::default();
+        let index = Arc::new(Mutex::new(MessageIndex::new(100)));
+        let node = Node::new(
+            Box::new(MemoryBackplane::new()),
+            Arc::new(Mutex::new(UserList::new())),
+            index.clone(),
+        );
+
+        ChatService {
+            users,
+            rate_limiter: Arc::new(RateLimiter::new(limits.send_rate_limit())),
+            message_limits: limits.message_limits(),
+            receive_idle_timeout: None,
+            shutdown: Arc::new(Shutdown::new()),
+            node: Arc::new(node),
+            index,
+        }
+    }
+
+    fn request<T>(message: T, user_id: &str, user_token: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("user_id", user_id.parse().unwrap());
+        request
+            .metadata_mut()
+            .insert("user_token", user_token.parse().unwrap());
+
+        request
+    }
+
+    fn send_request(to: chat::User, content: &str) -> SendRequest {
+        SendRequest {
+            notification: Some(OutgoingNotification {
+                to: Some(to),
+                types: Some(outgoing_notification::Types::Message(MessageContent {
+                    time_sent: None,
+                    content: String::from(content),
+                })),
+            }),
+            idempotency_key: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn send_authenticates_the_sender() {
+        let users = Arc::new(MockRegistry::new());
+        let alice = users.add_user("alice");
+        let bob = users.add_user("bob");
+        let service = chat_service(users.clone());
+
+        let status = service
+            .send_notification(request(
+                send_request(bob.user(), "hello"),
+                &alice.id(),
+                "wrong token",
+            ))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert_eq!(users.calls(), vec!["authenticate alice"]);
+    }
+
+    #[tokio::test]
+    async fn send_enqueues_message_for_local_recipient() {
+        let users = Arc::new(MockRegistry::new());
+        let alice = users.add_user("alice");
+        let bob = users.add_user("bob");
+        let mut bob_rx = users.take_receiver(&bob.id()).await.unwrap();
+        let service = chat_service(users.clone());
+
+        let response = service
+            .send_notification(request(
+                send_request(bob.user(), "hello bob"),
+                &alice.id(),
+                &alice.token(),
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let notification = bob_rx.try_recv().unwrap();
+        assert_eq!(notification.from, Some(alice.user()));
+        match notification.types {
+            Some(incoming_notification::Types::Message(message)) => {
+                assert_eq!(message.message_id, response.message_id);
+                assert_eq!(message.message_content.unwrap().content, "hello bob");
+            }
+            other => panic!("unexpected notification {:?}", other),
+        }
+
+        assert_eq!(
+            users.calls(),
+            vec!["take_receiver bob", "authenticate alice", "lookup bob"]
+        );
+    }
+
+    #[tokio::test]
+    async fn send_to_unknown_user_fails() {
+        let users = Arc::new(MockRegistry::new());
+        let alice = users.add_user("alice");
+        let service = chat_service(users);
+
+        let unknown = chat::User {
+            id: String::from("unknown"),
+            name: String::from("unknown"),
+        };
+
+        let status = service
+            .send_notification(request(
+                send_request(unknown, "hello"),
+                &alice.id(),
+                &alice.token(),
+            ))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn send_to_remote_user_is_published() {
+        let users = Arc::new(MockRegistry::new());
+        let alice = users.add_user("alice");
+        let carol = chat::User {
+            id: String::from("carol-id"),
+            name: String::from("carol"),
+        };
+        users.add_remote_user(carol.clone());
+        let service = chat_service(users);
+
+        let response = service
+            .send_notification(request(
+                send_request(carol, "hello carol"),
+                &alice.id(),
+                &alice.token(),
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(response.message_id.is_some());
+    }
+
+    #[tokio::test]
+    async fn receiver_is_handed_out_once() {
+        let users = Arc::new(MockRegistry::new());
+        let alice = users.add_user("alice");
+        let service = chat_service(users);
+
+        let _stream = service
+            .open_receive_stream(request(ReceiveRequest {}, &alice.id(), &alice.token()))
+            .await
+            .unwrap();
+
+        let status = service
+            .open_receive_stream(request(ReceiveRequest {}, &alice.id(), &alice.token()))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn closed_receive_stream_returns_the_receiver() {
+        let users = Arc::new(MockRegistry::new());
+        let alice = users.add_user("alice");
+        let service = chat_service(users.clone());
+
+        let stream = service
+            .open_receive_stream(request(ReceiveRequest {}, &alice.id(), &alice.token()))
+            .await
+            .unwrap();
+        drop(stream);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while users.user(&alice.id()).unwrap().is_online() {
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("alice did not go offline");
+
+        assert_eq!(
+            users.calls(),
+            vec![
+                "authenticate alice",
+                "take_receiver alice",
+                "set_online alice true",
+                "stop_receiving alice",
+            ]
+        );
+
+        // the next stream receives what has been enqueued in the meantime
+        assert!(users.take_receiver(&alice.id()).await.is_ok());
+    }
 }
@@ -1,5 +1,9 @@
+mod admin_service;
 mod authentication_service;
 mod chat_service;
+#[cfg(test)]
+mod tests;
 
+pub use admin_service::AdminService;
 pub use authentication_service::AuthenticationService;
 pub use chat_service::ChatService;
@@ -0,0 +1,690 @@
+use crate::test_support::{TestClient, TestServer};
+use futures::future;
+use proto::chat::{self, connect_request, connect_response, incoming_notification};
+use std::time::Duration;
+use tonic::{Code, Streaming};
+
+#[tokio::test]
+async fn login_returns_credentials_and_rejects_taken_names() {
+    let server = TestServer::start().await;
+
+    let alice = server.login("alice").await;
+    assert!(!alice.user.id.is_empty());
+
+    let status = server.try_login("alice").await.err().unwrap();
+    assert_eq!(status.code(), Code::AlreadyExists);
+
+    let status = server.try_login("").await.err().unwrap();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn requests_need_valid_credentials() {
+    let server = TestServer::start().await;
+    let alice = server.login("alice").await;
+
+    let mut client = server.chat_client(&alice.user.id, "invalid token");
+    let status = client
+        .receive(proto::chat::ReceiveRequest {})
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn presence_is_fanned_out_to_all_users() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    let mut carol = server.login("carol").await;
+
+    alice.expect_presence(&bob.user, true).await;
+    alice.expect_presence(&carol.user, true).await;
+
+    // users are told about everyone who was online before them
+    bob.expect_presence(&alice.user, true).await;
+    bob.expect_presence(&carol.user, true).await;
+
+    carol.expect_presence(&alice.user, true).await;
+    carol.expect_presence(&bob.user, true).await;
+
+    alice.expect_no_notification().await;
+}
+
+#[tokio::test]
+async fn online_users_are_listed_by_name() {
+    let server = TestServer::start().await;
+
+    let mut carol = server.login("carol").await;
+    let mut alice = server.login("alice").await;
+    carol.expect_presence(&alice.user, true).await;
+    alice.expect_presence(&carol.user, true).await;
+
+    assert_eq!(alice.online_user_names().await, vec!["alice", "carol"]);
+
+    carol.close_receive_stream();
+    alice.expect_presence(&carol.user, false).await;
+
+    assert_eq!(alice.online_user_names().await, vec!["alice"]);
+}
+
+#[tokio::test]
+async fn messages_are_delivered_to_the_recipient_only() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    let mut carol = server.login("carol").await;
+
+    alice.expect_presence(&bob.user, true).await;
+    alice.expect_presence(&carol.user, true).await;
+    bob.expect_presence(&alice.user, true).await;
+    bob.expect_presence(&carol.user, true).await;
+    carol.expect_presence(&alice.user, true).await;
+    carol.expect_presence(&bob.user, true).await;
+
+    let message_id = alice.send_message(&bob.user, "hello bob").await.unwrap();
+    assert_eq!(
+        bob.expect_message(&alice.user, "hello bob").await,
+        message_id
+    );
+
+    alice.expect_no_notification().await;
+    carol.expect_no_notification().await;
+}
+
+#[tokio::test]
+async fn retried_sends_are_delivered_once() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.expect_presence(&bob.user, true).await;
+
+    let first = alice
+        .send_with_key(&bob.user, "hello bob", "key-1")
+        .await
+        .unwrap();
+    let retry = alice
+        .send_with_key(&bob.user, "hello bob", "key-1")
+        .await
+        .unwrap();
+    assert_eq!(retry, first);
+    assert_eq!(first.sequence_number, 1);
+
+    bob.expect_presence(&alice.user, true).await;
+    bob.expect_message(&alice.user, "hello bob").await;
+    bob.expect_no_notification().await;
+
+    let second = alice
+        .send_with_key(&bob.user, "again", "key-2")
+        .await
+        .unwrap();
+    assert_eq!(second.sequence_number, 2);
+    bob.expect_message(&alice.user, "again").await;
+
+    let status = alice
+        .send_with_key(&bob.user, "hello", &"k".repeat(129))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn concurrent_messages_are_delivered_in_the_order_of_their_numbers() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.expect_presence(&bob.user, true).await;
+    bob.expect_presence(&alice.user, true).await;
+
+    // fewer messages than fit into the queue of the recipient
+    let sends = (0..4).map(|index| {
+        let mut client = alice.chat_client();
+        let request = chat::SendRequest {
+            notification: Some(chat::OutgoingNotification {
+                to: Some(bob.user.clone()),
+                types: Some(chat::outgoing_notification::Types::Message(
+                    chat::MessageContent {
+                        time_sent: None,
+                        content: format!("message {}", index),
+                    },
+                )),
+            }),
+            idempotency_key: String::new(),
+        };
+
+        async move { client.send(request).await.unwrap().into_inner() }
+    });
+    let mut sequence_numbers: Vec<u64> = future::join_all(sends)
+        .await
+        .into_iter()
+        .map(|response| response.sequence_number)
+        .collect();
+    sequence_numbers.sort_unstable();
+    assert_eq!(sequence_numbers, vec![1, 2, 3, 4]);
+
+    for expected in 1..=4 {
+        match bob.next_notification().await.types {
+            Some(incoming_notification::Types::Message(message)) => {
+                assert_eq!(message.sequence_number, expected)
+            }
+            types => panic!("expected a message, got {:?}", types),
+        }
+    }
+}
+
+#[tokio::test]
+async fn missed_messages_are_listed_by_sequence_number() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    let mut carol = server.login("carol").await;
+    alice.expect_presence(&bob.user, true).await;
+
+    for content in &["one", "two", "three"] {
+        alice.send_message(&bob.user, content).await.unwrap();
+    }
+    // every conversation is numbered on its own
+    let to_carol = alice.send_with_key(&carol.user, "hi", "").await.unwrap();
+    assert_eq!(to_carol.sequence_number, 1);
+
+    assert_eq!(
+        bob.list_messages(&alice.user, 1).await,
+        vec![(2, String::from("two")), (3, String::from("three"))]
+    );
+    assert!(bob.list_messages(&alice.user, 3).await.is_empty());
+
+    // users only see the messages sent to them
+    assert_eq!(
+        carol.list_messages(&alice.user, 0).await,
+        vec![(1, String::from("hi"))]
+    );
+}
+
+#[tokio::test]
+async fn search_pages_continue_with_the_page_token() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let bob = server.login("bob").await;
+    for index in 0..5 {
+        let content = format!("hello {}", index);
+        alice.send_message(&bob.user, &content).await.unwrap();
+    }
+
+    let mut client = alice.chat_client();
+    let mut snippets = vec![];
+    let mut page_token = String::new();
+    loop {
+        let response = client
+            .search(chat::SearchRequest {
+                query: String::from("hello"),
+                page_size: 2,
+                page_token,
+                ..chat::SearchRequest::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.total_hits, 5);
+        snippets.extend(response.hits.into_iter().map(|hit| hit.snippet));
+
+        page_token = response.next_page_token;
+        if page_token.is_empty() {
+            break;
+        }
+    }
+
+    // equally good matches, newer messages first
+    assert_eq!(
+        snippets,
+        vec!["hello 4", "hello 3", "hello 2", "hello 1", "hello 0"]
+    );
+}
+
+#[tokio::test]
+async fn typing_and_read_notifications_are_forwarded() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.expect_presence(&bob.user, true).await;
+    bob.expect_presence(&alice.user, true).await;
+
+    let message_id = bob.send_message(&alice.user, "hello alice").await.unwrap();
+    alice.expect_message(&bob.user, "hello alice").await;
+
+    let to_bob = bob.user.clone();
+    let notify = |types| chat::SendRequest {
+        notification: Some(chat::OutgoingNotification {
+            to: Some(to_bob.clone()),
+            types: Some(types),
+        }),
+        idempotency_key: String::new(),
+    };
+    let typing = chat::outgoing_notification::Types::Typing(chat::outgoing_notification::Typing {
+        expiration: None,
+    });
+    let read = chat::outgoing_notification::Types::Read(chat::outgoing_notification::Read {
+        message_id: Some(message_id.clone()),
+        time_read: None,
+    });
+
+    let mut chat_client = alice.chat_client();
+    chat_client.send(notify(typing)).await.unwrap();
+    chat_client.send(notify(read)).await.unwrap();
+
+    match bob.next_notification().await.types {
+        Some(incoming_notification::Types::Typing(typing)) => assert!(typing.is_typing),
+        types => panic!("expected typing, got {:?}", types),
+    }
+    match bob.next_notification().await.types {
+        Some(incoming_notification::Types::Read(read)) => {
+            assert_eq!(read.message_id, Some(message_id));
+            assert!(read.time_read.is_some());
+        }
+        types => panic!("expected read, got {:?}", types),
+    }
+
+    // a read notification has to name the message
+    let unnamed = chat::outgoing_notification::Types::Read(chat::outgoing_notification::Read {
+        message_id: None,
+        time_read: None,
+    });
+    let status = chat_client.send(notify(unnamed)).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn messages_to_unknown_users_are_rejected() {
+    let server = TestServer::start().await;
+    let mut alice = server.login("alice").await;
+
+    let unknown = proto::chat::User {
+        id: String::from("unknown"),
+        name: String::from("unknown"),
+    };
+
+    let status = alice.send_message(&unknown, "hello").await.err().unwrap();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn logout_notifies_other_users_and_removes_the_user() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let bob = server.login("bob").await;
+
+    alice.expect_presence(&bob.user, true).await;
+
+    let bob = bob.logout();
+    alice.expect_presence(&bob, false).await;
+
+    let status = alice
+        .send_message(&bob, "are you there?")
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(status.code(), Code::NotFound);
+
+    // the name can be taken again
+    server.login("bob").await;
+}
+
+#[tokio::test]
+async fn closed_receive_stream_keeps_undelivered_messages() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice.expect_presence(&bob.user, true).await;
+    bob.expect_presence(&alice.user, true).await;
+
+    // users are offline while they do not receive notifications, but stay logged in
+    bob.close_receive_stream();
+    alice.expect_presence(&bob.user, false).await;
+
+    let message_id = alice.send_message(&bob.user, "catch up").await.unwrap();
+
+    bob.reopen_receive_stream().await;
+    alice.expect_presence(&bob.user, true).await;
+    assert_eq!(
+        bob.expect_message(&alice.user, "catch up").await,
+        message_id
+    );
+}
+
+#[tokio::test]
+async fn connection_sends_and_receives_over_one_stream() {
+    let server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice.expect_presence(&bob.user, true).await;
+    bob.expect_presence(&alice.user, true).await;
+
+    let (mut requests, mut responses) = bob.open_connection().await;
+    alice.expect_presence(&bob.user, false).await;
+    alice.expect_presence(&bob.user, true).await;
+
+    // like every user coming online, bob is told who is online
+    match next_response(&mut responses).await {
+        connect_response::Types::Notification(notification) => {
+            assert_eq!(notification.from, Some(alice.user.clone()))
+        }
+        types => panic!("expected a notification, got {:?}", types),
+    }
+
+    // notifications arrive over the connection
+    let message_id = alice.send_message(&bob.user, "hello bob").await.unwrap();
+    match next_response(&mut responses).await {
+        connect_response::Types::Notification(notification) => match notification.types {
+            Some(incoming_notification::Types::Message(message)) => {
+                assert_eq!(message.message_id, Some(message_id))
+            }
+            types => panic!("expected a message, got {:?}", types),
+        },
+        types => panic!("expected a notification, got {:?}", types),
+    }
+
+    // every request is acked with its correlation id
+    requests
+        .send(connect_request(1, send_message(&alice.user, "hello alice")))
+        .await
+        .unwrap();
+    let message_id = match next_response(&mut responses).await {
+        connect_response::Types::Ack(ack) => {
+            assert_eq!(ack.correlation_id, 1);
+            assert_eq!(ack.error, None);
+            ack.message_id.unwrap()
+        }
+        types => panic!("expected an ack, got {:?}", types),
+    };
+    assert_eq!(
+        alice.expect_message(&bob.user, "hello alice").await,
+        message_id
+    );
+
+    // failed requests are acked with an error and the stream stays open
+    let unknown = chat::User {
+        id: String::from("unknown"),
+        name: String::from("unknown"),
+    };
+    requests
+        .send(connect_request(2, send_message(&unknown, "hello?")))
+        .await
+        .unwrap();
+    requests
+        .send(connect_request(
+            3,
+            connect_request::Types::Heartbeat(chat::HeartbeatRequest {}),
+        ))
+        .await
+        .unwrap();
+
+    match next_response(&mut responses).await {
+        connect_response::Types::Ack(ack) => {
+            assert_eq!(ack.correlation_id, 2);
+            assert_eq!(ack.error.unwrap().code, Code::NotFound as i32);
+        }
+        types => panic!("expected an ack, got {:?}", types),
+    }
+    match next_response(&mut responses).await {
+        connect_response::Types::Ack(ack) => {
+            assert_eq!(ack.correlation_id, 3);
+            assert_eq!(ack.error, None);
+        }
+        types => panic!("expected an ack, got {:?}", types),
+    }
+
+    // closing the connection takes the user offline
+    drop(requests);
+    drop(responses);
+    alice.expect_presence(&bob.user, false).await;
+}
+
+#[tokio::test]
+async fn admin_requests_need_the_admin_token() {
+    let server = TestServer::start().await;
+
+    for admin_token in &[None, Some("wrong token"), Some("test admin token ")] {
+        let status = server
+            .admin_client_with_token(*admin_token)
+            .list_sessions(chat::ListSessionsRequest {})
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::Unauthenticated, "{:?}", admin_token);
+    }
+
+    assert!(server
+        .admin_client()
+        .list_sessions(chat::ListSessionsRequest {})
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn admins_list_sessions_and_queue_depths() {
+    let server = TestServer::start().await;
+    let mut admin = server.admin_client();
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice.expect_presence(&bob.user, true).await;
+    bob.expect_presence(&alice.user, true).await;
+
+    let mut sessions = admin
+        .list_sessions(chat::ListSessionsRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .sessions;
+    sessions.sort_by_key(|v| v.user.as_ref().map(|user| user.name.clone()));
+    assert_eq!(
+        sessions,
+        vec![
+            chat::Session {
+                user: Some(alice.user.clone()),
+                is_receiving: true,
+            },
+            chat::Session {
+                user: Some(bob.user.clone()),
+                is_receiving: true,
+            },
+        ]
+    );
+
+    // the messages wait in the queue of bob while his stream is closed
+    bob.close_receive_stream();
+    alice.send_message(&bob.user, "one").await.unwrap();
+    alice.send_message(&bob.user, "two").await.unwrap();
+
+    let queues = admin
+        .get_queue_depths(chat::GetQueueDepthsRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .queues;
+    let depth = |user: &chat::User| {
+        let queue = queues.iter().find(|v| v.user.as_ref() == Some(user));
+        queue.map(|v| v.depth).expect("no queue of user")
+    };
+    assert_eq!(depth(&alice.user), 0);
+    assert_eq!(depth(&bob.user), 2);
+    assert!(queues.iter().all(|v| v.capacity > 2));
+}
+
+#[tokio::test]
+async fn admins_disconnect_users() {
+    let server = TestServer::start().await;
+    let mut admin = server.admin_client();
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice.expect_presence(&bob.user, true).await;
+    bob.expect_presence(&alice.user, true).await;
+
+    admin
+        .disconnect_user(chat::DisconnectUserRequest {
+            user_id: bob.user.id.clone(),
+            reason: String::from("maintenance"),
+        })
+        .await
+        .unwrap();
+
+    match bob.next_notification().await.types {
+        Some(incoming_notification::Types::Disconnected(disconnected)) => {
+            assert_eq!(disconnected.reason, "maintenance")
+        }
+        types => panic!("expected to be disconnected, got {:?}", types),
+    }
+    alice.expect_presence(&bob.user, false).await;
+
+    let status = admin
+        .disconnect_user(chat::DisconnectUserRequest {
+            user_id: String::from("unknown"),
+            reason: String::new(),
+        })
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn admins_broadcast_to_some_or_all_users() {
+    let server = TestServer::start().await;
+    let mut admin = server.admin_client();
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice.expect_presence(&bob.user, true).await;
+    bob.expect_presence(&alice.user, true).await;
+
+    let broadcast = |content: &str, user_ids: Vec<String>| chat::BroadcastRequest {
+        message: Some(chat::MessageContent {
+            time_sent: None,
+            content: String::from(content),
+        }),
+        user_ids,
+    };
+
+    let response = admin
+        .broadcast(broadcast("to bob", vec![bob.user.id.clone()]))
+        .await
+        .unwrap();
+    assert_eq!(response.into_inner().recipients, 1);
+    bob.expect_system_message("to bob").await;
+    alice.expect_no_notification().await;
+
+    let response = admin.broadcast(broadcast("to all", vec![])).await.unwrap();
+    assert_eq!(response.into_inner().recipients, 2);
+    alice.expect_system_message("to all").await;
+    bob.expect_system_message("to all").await;
+
+    // nobody is sent the message if one of the users is unknown
+    let user_ids = vec![alice.user.id.clone(), String::from("unknown")];
+    let status = admin
+        .broadcast(broadcast("to some", user_ids))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let status = admin
+        .broadcast(chat::BroadcastRequest {
+            message: None,
+            user_ids: vec![],
+        })
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    alice.expect_no_notification().await;
+    bob.expect_no_notification().await;
+}
+
+#[tokio::test]
+async fn shutdown_is_announced_before_the_streams_end() {
+    let mut server = TestServer::start().await;
+
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice.expect_presence(&bob.user, true).await;
+    bob.expect_presence(&alice.user, true).await;
+
+    // the sequence waits for the streams to end, so they are read while it runs
+    let (alice_user, bob_user) = (alice.user.clone(), bob.user.clone());
+    future::join3(
+        server.shut_down(),
+        expect_shutdown(&mut alice, &bob_user),
+        expect_shutdown(&mut bob, &alice_user),
+    )
+    .await;
+
+    assert!(server.try_login("carol").await.is_err());
+}
+
+fn connect_request(correlation_id: u64, types: connect_request::Types) -> chat::ConnectRequest {
+    chat::ConnectRequest {
+        correlation_id,
+        types: Some(types),
+    }
+}
+
+fn send_message(to: &chat::User, content: &str) -> connect_request::Types {
+    connect_request::Types::Notification(chat::OutgoingNotification {
+        to: Some(to.clone()),
+        types: Some(chat::outgoing_notification::Types::Message(
+            chat::MessageContent {
+                time_sent: None,
+                content: String::from(content),
+            },
+        )),
+    })
+}
+
+async fn next_response(
+    responses: &mut Streaming<chat::ConnectResponse>,
+) -> connect_response::Types {
+    let response = tokio::time::timeout(Duration::from_secs(5), responses.message())
+        .await
+        .expect("no connect response");
+
+    match response {
+        Ok(Some(response)) => response.types.expect("empty connect response"),
+        Ok(None) => panic!("connection ended"),
+        Err(status) => panic!("connection failed: {}", status),
+    }
+}
+
+/// Expects the other user to go offline and the server shutdown to be announced before the
+/// receive stream ends.
+async fn expect_shutdown(client: &mut TestClient, other: &chat::User) {
+    client.expect_presence(other, false).await;
+
+    match client.next_notification().await.types {
+        Some(incoming_notification::Types::ServerShutdown(shutdown)) => {
+            assert_eq!(shutdown.reason, "server is shutting down")
+        }
+        types => panic!("expected the server shutdown, got {:?}", types),
+    }
+
+    client.expect_end_of_stream().await;
+}
@@ -0,0 +1,163 @@
+use crate::backplane::Node;
+use crate::error::ChatError;
+use crate::store::Store;
+use crate::UserList;
+use futures::future;
+use server_common::health::{HealthReporter, ServingStatus};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, watch};
+
+/// Held by every open `Receive` stream, the shutdown sequence waits until all guards are dropped.
+pub struct DrainGuard {
+    _drain_tx: mpsc::Sender<()>,
+}
+
+/// Coordinates the graceful shutdown of the chat services.
+///
+/// Once the shutdown sequence has started, no new sessions and receive streams are accepted.
+/// Connected users are notified that the server is going away, their pending notifications are
+/// drained and the state is persisted before the remaining sessions are closed.
+pub struct Shutdown {
+    drain_tx: Mutex<Option<mpsc::Sender<()>>>,
+    drain_rx: Mutex<Option<mpsc::Receiver<()>>>,
+    sessions_tx: watch::Sender<bool>,
+    sessions_rx: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (drain_tx, drain_rx) = mpsc::channel(1);
+        let (sessions_tx, sessions_rx) = watch::channel(false);
+
+        Shutdown {
+            drain_tx: Mutex::new(Some(drain_tx)),
+            drain_rx: Mutex::new(Some(drain_rx)),
+            sessions_tx,
+            sessions_rx,
+        }
+    }
+
+    /// Fails once the shutdown sequence has started.
+    pub fn check(&self) -> Result<(), ChatError> {
+        self.drain_guard().map(|_| ())
+    }
+
+    /// Returns a guard which delays the shutdown until it is dropped, or fails once the shutdown
+    /// sequence has started.
+    pub fn drain_guard(&self) -> Result<DrainGuard, ChatError> {
+        let drain_tx = match self.drain_tx.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(ChatError::LockPoisoned),
+        };
+
+        match &*drain_tx {
+            Some(drain_tx) => Ok(DrainGuard {
+                _drain_tx: drain_tx.clone(),
+            }),
+            None => Err(ChatError::ShuttingDown),
+        }
+    }
+
+    /// Resolves when the sessions have to be closed, i.e. at the very end of the shutdown sequence.
+    pub async fn sessions_closed(&self) {
+        let mut sessions_rx = self.sessions_rx.clone();
+
+        while let Some(closed) = sessions_rx.recv().await {
+            if closed {
+                return;
+            }
+        }
+    }
+
+    /// Runs the shutdown sequence.
+    pub async fn run(
+        &self,
+        users: &Arc<Mutex<UserList>>,
+        node: &Node,
+        health_reporter: &HealthReporter,
+        store: Option<&dyn Store>,
+        timeout: Duration,
+    ) {
+        health_reporter.set_all(ServingStatus::NotServing);
+
+        // stop accepting new sessions and receive streams
+        let drain_rx = match self.drain_tx.lock() {
+            Ok(mut drain_tx) => {
+                drain_tx.take();
+                self.drain_rx
+                    .lock()
+                    .ok()
+                    .and_then(|mut drain_rx| drain_rx.take())
+            }
+            Err(_) => None,
+        };
+
+        let notifications = match users.lock() {
+            Ok(mut users) => users.shutdown_notifications("server is shutting down"),
+            Err(_) => {
+                tracing::error!("unable to acquire lock, users will not be notified");
+                vec![]
+            }
+        };
+
+        tracing::info!(
+            users = notifications.len(),
+            "notifying users and draining notifications"
+        );
+
+        let drain = async {
+            future::join_all(notifications.into_iter().map(
+                |(mut sender, notifications)| async move {
+                    for notification in notifications {
+                        if sender.send(notification).await.is_err() {
+                            break;
+                        }
+                    }
+                },
+            ))
+            .await;
+
+            // every receive stream ends after delivering the shutdown notification
+            if let Some(mut drain_rx) = drain_rx {
+                drain_rx.recv().await;
+            }
+        };
+
+        if tokio::time::timeout(timeout, drain).await.is_err() {
+            tracing::warn!(
+                "notifications were not drained within {} seconds",
+                timeout.as_secs()
+            );
+        }
+
+        // users of this node are offline for the rest of the cluster
+        node.leave().await;
+
+        if let Some(store) = store {
+            self.persist(users, store).await;
+        }
+
+        let _ = self.sessions_tx.broadcast(true);
+    }
+
+    async fn persist(&self, users: &Arc<Mutex<UserList>>, store: &dyn Store) {
+        let user_states = match users.lock() {
+            Ok(mut users) => users.state(),
+            Err(_) => {
+                tracing::error!("unable to acquire lock, state will not be persisted");
+                return;
+            }
+        };
+
+        let state = proto::chat::ServerState {
+            saved_at: Some(SystemTime::now().into()),
+            users: user_states,
+        };
+
+        match store.save(&state).await {
+            Ok(()) => tracing::info!(users = state.users.len(), "state persisted"),
+            Err(err) => tracing::error!("could not persist state: {}", err),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use super::Store;
+use prost::Message;
+use proto::chat;
+use std::io;
+use std::path::PathBuf;
+use tokio::fs;
+
+/// Stores the server state as an encoded `chat.ServerState` message in a single file.
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(path: PathBuf) -> FileStore {
+        FileStore { path }
+    }
+}
+
+#[tonic::async_trait]
+impl Store for FileStore {
+    async fn save(&self, state: &chat::ServerState) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(state.encoded_len());
+        if let Err(err) = state.encode(&mut buf) {
+            return Err(io::Error::other(err));
+        }
+
+        // write to a temporary file first so a crash never leaves a truncated state behind
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, buf).await?;
+        fs::rename(&tmp_path, &self.path).await
+    }
+}
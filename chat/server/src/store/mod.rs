@@ -0,0 +1,13 @@
+mod file_store;
+
+pub use file_store::FileStore;
+
+use proto::chat;
+use std::io;
+
+/// Persistent storage for the server state.
+#[tonic::async_trait]
+pub trait Store: Send + Sync {
+    /// Persists the given state, replacing any previously saved state.
+    async fn save(&self, state: &chat::ServerState) -> io::Result<()>;
+}
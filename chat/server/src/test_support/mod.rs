@@ -0,0 +1,595 @@
+//! Runs the authentication and chat services in-process on an ephemeral port and connects
+//! authenticated clients to them.
+
+use crate::backplane::{MemoryBackplane, Node};
+use crate::config::{LimitsConfig, WebConfig, WebhookConfig};
+use crate::gateway::{self, Gateway};
+use crate::rate_limiter::RateLimiter;
+use crate::search::MessageIndex;
+use crate::services::{AdminService, AuthenticationService, ChatService};
+use crate::shutdown::Shutdown;
+use crate::user_list::UserList;
+use crate::webhook::Webhooks;
+use futures::channel::oneshot;
+use hyper::server::conn::AddrIncoming;
+use proto::chat;
+use proto::chat::admin_service_client::AdminServiceClient;
+use proto::chat::authentication_service_client::AuthenticationServiceClient;
+use proto::chat::chat_service_client::ChatServiceClient;
+use server_common::health::{self, HealthReporter};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Channel, Endpoint, Server};
+use tonic::{Code, Request, Status, Streaming};
+
+/// Token of the admin service.
+pub const ADMIN_TOKEN: &str = "test admin token";
+
+/// Attempts to deliver a notification to a bot, retried quickly.
+pub const WEBHOOK_ATTEMPTS: u32 = 3;
+
+/// Origin of the web pages allowed to call the web gateway.
+pub const WEB_ORIGIN: &str = "https://chat.example.com";
+
+/// Time to wait for a notification before an assertion fails.
+const NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time in which no notification may arrive for `expect_no_notification` to pass.
+const QUIET_PERIOD: Duration = Duration::from_millis(200);
+
+/// Time to wait for the server to take back the receiver of a closed receive stream.
+const REOPEN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time the shutdown sequence waits for pending notifications to be delivered.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A chat server listening on a local ephemeral port, stopped when dropped.
+pub struct TestServer {
+    channel: Channel,
+    web_addr: SocketAddr,
+    dead_letter_file: PathBuf,
+    users: Arc<Mutex<UserList>>,
+    node: Arc<Node>,
+    shutdown: Arc<Shutdown>,
+    health_reporter: HealthReporter,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl TestServer {
+    pub async fn start() -> TestServer {
+        let limits = LimitsConfig::default();
+
+        let users = Arc::new(Mutex::new(UserList::new()));
+        let shutdown = Arc::new(Shutdown::new());
+        let index = Arc::new(Mutex::new(MessageIndex::new(1000)));
+
+        let node = Arc::new(Node::new(
+            Box::new(MemoryBackplane::new()),
+            users.clone(),
+            index.clone(),
+        ));
+        Node::join(&node).await.expect("could not join backplane");
+
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .expect("could not bind ephemeral port");
+        let addr = listener.local_addr().expect("listener without address");
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+        let auth_rate_limiter = Arc::new(RateLimiter::new(limits.auth_rate_limit()));
+        let authentication_service = AuthenticationService::new(
+            users.clone(),
+            auth_rate_limiter.clone(),
+            shutdown.clone(),
+            node.clone(),
+        );
+        // bots are posted their notifications with little delay between attempts
+        let dead_letter_file = std::env::temp_dir().join(format!(
+            "chat_dead_letters_{}.jsonl",
+            uuid::Uuid::new_v4().to_simple()
+        ));
+        let webhooks = Webhooks::new(
+            WebhookConfig {
+                max_attempts: WEBHOOK_ATTEMPTS,
+                initial_backoff: 10,
+                max_backoff: 50,
+                timeout: 2,
+                dead_letter_file: Some(dead_letter_file.clone()),
+            },
+            users.clone(),
+            node.clone(),
+            shutdown.clone(),
+        );
+        let admin_service = AdminService::new(
+            users.clone(),
+            node.clone(),
+            limits.message_limits(),
+            Arc::new(webhooks),
+            String::from(ADMIN_TOKEN),
+        );
+
+        let chat_service = ChatService::new(
+            users.clone(),
+            Arc::new(RateLimiter::new(limits.send_rate_limit())),
+            limits.message_limits(),
+            limits.receive_idle_timeout(),
+            shutdown.clone(),
+            node.clone(),
+            index,
+        );
+
+        // browser clients are served on a port of their own
+        let web_config = WebConfig {
+            allowed_origins: vec![String::from(WEB_ORIGIN)],
+            ..WebConfig::default()
+        };
+        let gateway = Gateway::new(
+            &web_config,
+            authentication_service.clone(),
+            chat_service.clone(),
+            auth_rate_limiter,
+        );
+        let web_incoming = AddrIncoming::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .expect("could not bind ephemeral port");
+        let web_addr = web_incoming.local_addr();
+        let gateway_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let signal = gateway_shutdown.sessions_closed();
+            gateway::serve(gateway, web_incoming, signal).await
+        });
+
+        let router = Server::builder()
+            .add_service(authentication_service)
+            .add_service(chat_service)
+            .add_service(admin_service);
+
+        tokio::spawn(async move {
+            let result = router
+                .serve_with_incoming_shutdown(listener, async {
+                    let _ = shutdown_rx.await;
+                })
+                .await;
+
+            if let Err(err) = result {
+                panic!("test server failed: {}", err);
+            }
+        });
+
+        let channel = Endpoint::from_shared(format!("http://{}", addr))
+            .expect("invalid server address")
+            .connect()
+            .await
+            .expect("could not connect to test server");
+
+        TestServer {
+            channel,
+            web_addr,
+            dead_letter_file,
+            users,
+            node,
+            shutdown,
+            health_reporter: health::health_reporter().0,
+            shutdown_tx: Some(shutdown_tx),
+        }
+    }
+
+    /// Runs the shutdown sequence and stops the server afterwards, like an interrupt does.
+    pub async fn shut_down(&mut self) {
+        self.shutdown
+            .run(
+                &self.users,
+                &self.node,
+                &self.health_reporter,
+                None,
+                SHUTDOWN_TIMEOUT,
+            )
+            .await;
+
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+    }
+
+    /// Returns the URL of the web gateway, e.g. `http://127.0.0.1:1234`.
+    pub fn web_url(&self) -> String {
+        format!("http://{}", self.web_addr)
+    }
+
+    /// Logs in a user and opens its receive stream.
+    pub async fn login(&self, name: &str) -> TestClient {
+        match self.try_login(name).await {
+            Ok(client) => client,
+            Err(status) => panic!("could not log in {}: {}", name, status),
+        }
+    }
+
+    pub async fn try_login(&self, name: &str) -> Result<TestClient, Status> {
+        let mut authentication = AuthenticationServiceClient::new(self.channel.clone());
+
+        let mut session = authentication
+            .authenticate(chat::AuthenticateRequest {
+                name: String::from(name),
+            })
+            .await?
+            .into_inner();
+
+        let credentials = match session.message().await? {
+            Some(credentials) => credentials,
+            None => {
+                return Err(Status::internal(
+                    "session ended before credentials were sent",
+                ))
+            }
+        };
+
+        let mut chat_client = self.chat_client(&credentials.id, &credentials.token);
+        let notifications = chat_client
+            .receive(chat::ReceiveRequest {})
+            .await?
+            .into_inner();
+
+        Ok(TestClient {
+            user: chat::User {
+                id: credentials.id,
+                name: String::from(name),
+            },
+            chat_client,
+            session,
+            notifications: Some(notifications),
+        })
+    }
+
+    /// Returns an admin client sending the admin token with every request.
+    pub fn admin_client(&self) -> AdminServiceClient<Channel> {
+        self.admin_client_with_token(Some(ADMIN_TOKEN))
+    }
+
+    /// Returns an admin client sending the given token, or none, with every request.
+    pub fn admin_client_with_token(
+        &self,
+        admin_token: Option<&str>,
+    ) -> AdminServiceClient<Channel> {
+        let admin_token =
+            admin_token.map(|token| MetadataValue::from_str(token).expect("invalid admin token"));
+
+        AdminServiceClient::with_interceptor(
+            self.channel.clone(),
+            move |mut request: Request<()>| {
+                if let Some(admin_token) = &admin_token {
+                    request
+                        .metadata_mut()
+                        .insert("admin_token", admin_token.clone());
+                }
+                Ok(request)
+            },
+        )
+    }
+
+    /// Returns the entries of the dead-letter log of the webhooks.
+    pub async fn dead_letters(&self) -> Vec<serde_json::Value> {
+        match tokio::fs::read_to_string(&self.dead_letter_file).await {
+            Ok(log) => log
+                .lines()
+                .map(|line| serde_json::from_str(line).expect("invalid dead letter"))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Returns a chat client sending the given credentials with every request.
+    pub fn chat_client(&self, user_id: &str, user_token: &str) -> ChatServiceClient<Channel> {
+        let user_id = MetadataValue::from_str(user_id).expect("invalid user id");
+        let user_token = MetadataValue::from_str(user_token).expect("invalid user token");
+
+        ChatServiceClient::with_interceptor(self.channel.clone(), move |mut request: Request<()>| {
+            request.metadata_mut().insert("user_id", user_id.clone());
+            request
+                .metadata_mut()
+                .insert("user_token", user_token.clone());
+            Ok(request)
+        })
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+
+        let _ = std::fs::remove_file(&self.dead_letter_file);
+    }
+}
+
+/// A logged in user with an open receive stream.
+pub struct TestClient {
+    pub user: chat::User,
+    chat_client: ChatServiceClient<Channel>,
+    session: Streaming<chat::AuthenticateResponse>,
+    notifications: Option<Streaming<chat::ReceiveResponse>>,
+}
+
+impl TestClient {
+    /// Returns the names of the users who are online.
+    pub async fn online_user_names(&mut self) -> Vec<String> {
+        let response = self
+            .chat_client
+            .list_users(chat::ListUsersRequest {})
+            .await
+            .expect("could not list users");
+
+        response
+            .into_inner()
+            .users
+            .into_iter()
+            .map(|v| v.name)
+            .collect()
+    }
+
+    pub async fn send_message(
+        &mut self,
+        to: &chat::User,
+        content: &str,
+    ) -> Result<chat::MessageId, Status> {
+        let response = self.send_with_key(to, content, "").await?;
+
+        match response.message_id {
+            Some(message_id) => Ok(message_id),
+            None => Err(Status::internal("response without message id")),
+        }
+    }
+
+    /// Sends a message with the given idempotency key, none if empty.
+    pub async fn send_with_key(
+        &mut self,
+        to: &chat::User,
+        content: &str,
+        idempotency_key: &str,
+    ) -> Result<chat::SendResponse, Status> {
+        let response = self
+            .chat_client
+            .send(chat::SendRequest {
+                notification: Some(chat::OutgoingNotification {
+                    to: Some(to.clone()),
+                    types: Some(chat::outgoing_notification::Types::Message(
+                        chat::MessageContent {
+                            time_sent: None,
+                            content: String::from(content),
+                        },
+                    )),
+                }),
+                idempotency_key: String::from(idempotency_key),
+            })
+            .await?;
+
+        Ok(response.into_inner())
+    }
+
+    /// Returns the sequence numbers and contents of the messages `from` sent to the user after
+    /// the given sequence number.
+    pub async fn list_messages(&mut self, from: &chat::User, after: u64) -> Vec<(u64, String)> {
+        let response = self
+            .chat_client
+            .list_messages(chat::ListMessagesRequest {
+                from_user_id: from.id.clone(),
+                after_sequence_number: after,
+                page_size: 0,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        response
+            .messages
+            .into_iter()
+            .map(|notification| match notification.types {
+                Some(chat::incoming_notification::Types::Message(message)) => (
+                    message.sequence_number,
+                    message.message_content.unwrap_or_default().content,
+                ),
+                types => panic!("expected a message, got {:?}", types),
+            })
+            .collect()
+    }
+
+    /// Returns a client sending requests as the user, e.g. to send concurrently.
+    pub fn chat_client(&self) -> ChatServiceClient<Channel> {
+        self.chat_client.clone()
+    }
+
+    /// Closes the receive stream while staying logged in.
+    pub fn close_receive_stream(&mut self) {
+        self.notifications = None;
+    }
+
+    /// Opens a new receive stream, waiting until the server has taken back the receiver of the
+    /// previous one.
+    pub async fn reopen_receive_stream(&mut self) {
+        self.close_receive_stream();
+
+        let chat_client = self.chat_client.clone();
+        let notifications = self
+            .retry_until_receiver_returned(move || {
+                let mut chat_client = chat_client.clone();
+                async move {
+                    let response = chat_client.receive(chat::ReceiveRequest {}).await?;
+                    Ok(response.into_inner())
+                }
+            })
+            .await;
+
+        self.notifications = Some(notifications);
+    }
+
+    /// Replaces the receive stream with a connection, over which requests are sent by the
+    /// returned sender.
+    pub async fn open_connection(
+        &mut self,
+    ) -> (
+        mpsc::Sender<chat::ConnectRequest>,
+        Streaming<chat::ConnectResponse>,
+    ) {
+        self.close_receive_stream();
+
+        let chat_client = self.chat_client.clone();
+        self.retry_until_receiver_returned(move || {
+            let mut chat_client = chat_client.clone();
+            let (requests_tx, requests_rx) = mpsc::channel(16);
+
+            async move {
+                let response = chat_client.open_connection(requests_rx).await?;
+                Ok((requests_tx, response.into_inner()))
+            }
+        })
+        .await
+    }
+
+    /// Opens a stream receiving the notifications of the user, retrying while the server has not
+    /// yet taken back the receiver of the previous stream.
+    async fn retry_until_receiver_returned<F, R, T>(&self, mut open: F) -> T
+    where
+        F: FnMut() -> R,
+        R: Future<Output = Result<T, Status>>,
+    {
+        let result = tokio::time::timeout(REOPEN_TIMEOUT, async {
+            loop {
+                match open().await {
+                    Ok(stream) => return stream,
+                    Err(status) if status.code() == Code::FailedPrecondition => {
+                        tokio::time::delay_for(Duration::from_millis(10)).await
+                    }
+                    Err(status) => panic!("could not open stream: {}", status),
+                }
+            }
+        })
+        .await;
+
+        result.unwrap_or_else(|_| panic!("receiver of {} was not returned", self.user.name))
+    }
+
+    fn notifications(&mut self) -> &mut Streaming<chat::ReceiveResponse> {
+        match &mut self.notifications {
+            Some(notifications) => notifications,
+            None => panic!("receive stream of {} is closed", self.user.name),
+        }
+    }
+
+    /// Waits for the next notification, panics if none arrives in time or the stream ends.
+    pub async fn next_notification(&mut self) -> chat::IncomingNotification {
+        let response = tokio::time::timeout(NOTIFICATION_TIMEOUT, self.notifications().message())
+            .await
+            .unwrap_or_else(|_| panic!("{} received no notification", self.user.name));
+
+        match response {
+            Ok(Some(response)) => response
+                .notification
+                .unwrap_or_else(|| panic!("{} received an empty response", self.user.name)),
+            Ok(None) => panic!("receive stream of {} ended", self.user.name),
+            Err(status) => panic!("receive stream of {} failed: {}", self.user.name, status),
+        }
+    }
+
+    /// Asserts that the next notification announces `user` going online or offline.
+    pub async fn expect_presence(&mut self, user: &chat::User, is_online: bool) {
+        let notification = self.next_notification().await;
+
+        match &notification.types {
+            Some(chat::incoming_notification::Types::Online(online)) => {
+                assert_eq!(
+                    notification.from.as_ref(),
+                    Some(user),
+                    "presence of wrong user"
+                );
+                assert_eq!(
+                    online.is_online, is_online,
+                    "wrong presence of {}",
+                    user.name
+                );
+            }
+            _ => panic!(
+                "{} expected presence of {}, got {:?}",
+                self.user.name, user.name, notification
+            ),
+        }
+    }
+
+    /// Asserts that the next notification is a message from `from` and returns its id.
+    pub async fn expect_message(&mut self, from: &chat::User, content: &str) -> chat::MessageId {
+        let notification = self.next_notification().await;
+
+        match notification.types {
+            Some(chat::incoming_notification::Types::Message(message)) => {
+                assert_eq!(
+                    notification.from.as_ref(),
+                    Some(from),
+                    "message from wrong user"
+                );
+                assert_eq!(
+                    message.message_content.map(|content| content.content),
+                    Some(String::from(content))
+                );
+
+                message.message_id.expect("message without id")
+            }
+            types => panic!(
+                "{} expected a message from {}, got {:?}",
+                self.user.name, from.name, types
+            ),
+        }
+    }
+
+    /// Asserts that the next notification is a system message with the given content.
+    pub async fn expect_system_message(&mut self, content: &str) {
+        let notification = self.next_notification().await;
+
+        match notification.types {
+            Some(chat::incoming_notification::Types::SystemMessage(message)) => {
+                assert_eq!(notification.from, None, "system message from a user");
+                assert_eq!(
+                    message.message_content.map(|content| content.content),
+                    Some(String::from(content))
+                );
+            }
+            types => panic!(
+                "{} expected a system message, got {:?}",
+                self.user.name, types
+            ),
+        }
+    }
+
+    /// Asserts that the receive stream ends without another notification.
+    pub async fn expect_end_of_stream(&mut self) {
+        let response = tokio::time::timeout(NOTIFICATION_TIMEOUT, self.notifications().message())
+            .await
+            .unwrap_or_else(|_| panic!("receive stream of {} did not end", self.user.name));
+
+        match response {
+            Ok(None) => {}
+            Ok(Some(response)) => panic!("{} received unexpected {:?}", self.user.name, response),
+            Err(status) => panic!("receive stream of {} failed: {}", self.user.name, status),
+        }
+    }
+
+    /// Asserts that no notification arrives within a short period.
+    pub async fn expect_no_notification(&mut self) {
+        if let Ok(response) =
+            tokio::time::timeout(QUIET_PERIOD, self.notifications().message()).await
+        {
+            panic!("{} received unexpected {:?}", self.user.name, response);
+        }
+    }
+
+    /// Ends the session, which logs out the user.
+    pub fn logout(self) -> chat::User {
+        drop(self.session);
+
+        self.user
+    }
+}
@@ -0,0 +1,209 @@
+use super::registry::{Recipient, UserRegistry};
+use super::user::User;
+use super::{NotificationReceiver, UserData};
+use crate::error::ChatError;
+use proto::chat;
+use std::sync::Mutex;
+
+/// A `UserRegistry` for unit tests of the services. It records every call and keeps the users
+/// without notifying anyone of their presence.
+pub struct MockRegistry {
+    state: Mutex<MockState>,
+}
+
+#[derive(Default)]
+struct MockState {
+    users: Vec<User>,
+    remote_users: Vec<chat::User>,
+    calls: Vec<String>,
+}
+
+impl MockRegistry {
+    pub fn new() -> MockRegistry {
+        MockRegistry {
+            state: Mutex::new(MockState::default()),
+        }
+    }
+
+    /// Adds a user as if they had logged in and were receiving notifications.
+    pub fn add_user(&self, name: &str) -> UserData {
+        let mut user = User::new(name);
+        user.user_data.set_online(true);
+
+        let user_data = user.user_data.clone();
+        self.state.lock().unwrap().users.push(user);
+
+        user_data
+    }
+
+    /// Adds a user connected to another node.
+    pub fn add_remote_user(&self, user: chat::User) {
+        self.state.lock().unwrap().remote_users.push(user);
+    }
+
+    /// Returns the registered user, `None` if there is none or they have been removed.
+    pub fn user(&self, user_id: &str) -> Option<UserData> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .users
+            .iter()
+            .find(|user| user.id() == user_id)
+            .map(|user| user.user_data.clone())
+    }
+
+    /// Returns all calls made so far, e.g. `set_online alice true`, users are named by name.
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    fn record(state: &mut MockState, call: &str, user_id: &str, args: &[&str]) {
+        let name = match state.users.iter().find(|user| user.id() == user_id) {
+            Some(user) => user.user_data.name(),
+            None => String::from(user_id),
+        };
+
+        let mut call = vec![call, name.as_str()];
+        call.extend(args);
+
+        state.calls.push(call.join(" "));
+    }
+
+    fn user_mut<'a>(state: &'a mut MockState, user_id: &str) -> Result<&'a mut User, ChatError> {
+        match state.users.iter_mut().find(|user| user.id() == user_id) {
+            Some(user) => Ok(user),
+            None => Err(ChatError::UserNotFound(String::from(user_id))),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl UserRegistry for MockRegistry {
+    async fn create_user(&self, name: &str) -> Result<UserData, ChatError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("create_user {}", name));
+
+        if state.users.iter().any(|user| user.user_data.name() == name) {
+            return Err(ChatError::UserAlreadyExists(String::from(name)));
+        }
+
+        let user = User::new(name);
+        let user_data = user.user_data.clone();
+        state.users.push(user);
+
+        Ok(user_data)
+    }
+
+    async fn remove_user(&self, user_id: &str) -> Result<(), ChatError> {
+        let mut state = self.state.lock().unwrap();
+        MockRegistry::record(&mut state, "remove_user", user_id, &[]);
+
+        match state.users.iter().position(|user| user.id() == user_id) {
+            Some(index) => {
+                state.users.remove(index);
+                Ok(())
+            }
+            None => Err(ChatError::UserNotFound(String::from(user_id))),
+        }
+    }
+
+    async fn set_online(&self, user_id: &str, is_online: bool) -> Result<(), ChatError> {
+        let mut state = self.state.lock().unwrap();
+        let is_online_arg = is_online.to_string();
+        MockRegistry::record(&mut state, "set_online", user_id, &[&is_online_arg]);
+
+        MockRegistry::user_mut(&mut state, user_id)?
+            .user_data
+            .set_online(is_online);
+
+        Ok(())
+    }
+
+    async fn authenticate(&self, user_id: &str, user_token: &str) -> Result<UserData, ChatError> {
+        let mut state = self.state.lock().unwrap();
+        MockRegistry::record(&mut state, "authenticate", user_id, &[]);
+
+        match state
+            .users
+            .iter()
+            .find(|user| user.id() == user_id && user.user_data.token() == user_token)
+        {
+            Some(user) => Ok(user.user_data.clone()),
+            None => Err(ChatError::InvalidCredentials),
+        }
+    }
+
+    async fn lookup(&self, user_id: &str) -> Result<Recipient, ChatError> {
+        let mut state = self.state.lock().unwrap();
+        MockRegistry::record(&mut state, "lookup", user_id, &[]);
+
+        if let Some(user) = state.users.iter().find(|user| user.id() == user_id) {
+            return Ok(Recipient::Local(user.user_data.clone()));
+        }
+
+        match state.remote_users.iter().find(|user| user.id == user_id) {
+            Some(user) => Ok(Recipient::Remote(user.clone())),
+            None => Err(ChatError::UserNotFound(String::from(user_id))),
+        }
+    }
+
+    async fn online_users(&self) -> Result<Vec<chat::User>, ChatError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(String::from("online_users"));
+
+        let mut users: Vec<chat::User> = state
+            .users
+            .iter()
+            .filter(|user| user.user_data.is_online())
+            .map(|user| user.user_data.user())
+            .chain(state.remote_users.iter().cloned())
+            .collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(users)
+    }
+
+    async fn take_receiver(&self, user_id: &str) -> Result<NotificationReceiver, ChatError> {
+        let mut state = self.state.lock().unwrap();
+        MockRegistry::record(&mut state, "take_receiver", user_id, &[]);
+
+        match MockRegistry::user_mut(&mut state, user_id)?.take_receiver() {
+            Some(receiver) => Ok(receiver),
+            None => Err(ChatError::ReceiverTaken(String::from(user_id))),
+        }
+    }
+
+    async fn return_receiver(
+        &self,
+        user_id: &str,
+        receiver: NotificationReceiver,
+    ) -> Result<(), ChatError> {
+        let mut state = self.state.lock().unwrap();
+        MockRegistry::record(&mut state, "return_receiver", user_id, &[]);
+
+        MockRegistry::user_mut(&mut state, user_id)?.return_receiver(receiver);
+
+        Ok(())
+    }
+
+    async fn stop_receiving(
+        &self,
+        user_id: &str,
+        receiver: Option<NotificationReceiver>,
+    ) -> Result<bool, ChatError> {
+        let mut state = self.state.lock().unwrap();
+        MockRegistry::record(&mut state, "stop_receiving", user_id, &[]);
+
+        let user = MockRegistry::user_mut(&mut state, user_id)?;
+        if let Some(receiver) = receiver {
+            user.return_receiver(receiver);
+        }
+
+        if user.is_receiving() {
+            return Ok(false);
+        }
+        user.user_data.set_online(false);
+
+        Ok(true)
+    }
+}
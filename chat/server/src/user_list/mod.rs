@@ -1,229 +1,606 @@
-mod user;
-mod user_data;
-
-use proto::chat;
-use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
-use tonic::{Request, Status};
-use user::User;
-use user_data::UserData;
-
-pub struct UserList {
-    users: Vec<User>,
-}
-
-pub trait UserManagement {
-    fn create_user(&mut self, user_id: &str) -> Result<UserData, &str>;
-    fn remove_user(&mut self, user_id: &str) -> Result<(), String>;
-}
-
-impl UserManagement for UserList {
-    fn create_user(&mut self, user_id: &str) -> Result<UserData, &str> {
-        // check if user exists
-        if self.users.iter().position(|v| v.id() == user_id).is_some() {
-            return Err("User already exists");
-        }
-
-        let user = User::new(user_id);
-
-        for other_user in &mut self.users {
-            // notify other users that this user is online
-            let send_result = other_user
-                .user_data
-                .sender()
-                .try_send(chat::IncomingNotification {
-                    from: Some(user.user_data.user()),
-                    types: Some(chat::incoming_notification::Types::Online(
-                        chat::incoming_notification::Online { is_online: true },
-                    )),
-                });
-
-            if send_result.is_err() {
-                println!("Could not send online notification to user {}", user.id());
-            }
-
-            // notify the new user of all currently active users
-            let send_result = user
-                .user_data
-                .sender()
-                .try_send(chat::IncomingNotification {
-                    from: Some(other_user.user_data.user()),
-                    types: Some(chat::incoming_notification::Types::Online(
-                        chat::incoming_notification::Online { is_online: true },
-                    )),
-                });
-
-            if send_result.is_err() {
-                println!("Could not send online notification to user {}", user.id());
-            }
-        }
-
-        let user_data = user.user_data.clone();
-
-        self.users.push(user);
-
-        Ok(user_data)
-    }
-
-    fn remove_user(&mut self, user_id: &str) -> Result<(), String> {
-        let mut user = match self.users.iter().position(|v| v.id() == user_id) {
-            Some(index) => self.users.remove(index),
-            None => return Err(String::from("user id not found")),
-        };
-
-        // set user as offline and notify other users
-        self.set_user_online(&mut user, false);
-
-        Ok(())
-    }
-}
-
-impl UserList {
-    pub fn new() -> UserList {
-        UserList { users: vec![] }
-    }
-
-    fn set_user_online(&mut self, user: &mut User, is_online: bool) {
-        user.user_data.set_online(is_online);
-
-        let user_data = user.user_data.clone();
-
-        for other_user in &mut self.users {
-            // don't send this notification to the current user
-            if other_user.id() == user.id() {
-                continue;
-            }
-
-            let send_result = other_user
-                .user_data
-                .sender()
-                .try_send(chat::IncomingNotification {
-                    from: Some(user_data.user()),
-                    types: Some(chat::incoming_notification::Types::Online(
-                        chat::incoming_notification::Online {
-                            is_online: is_online,
-                        },
-                    )),
-                });
-
-            if send_result.is_err() {
-                eprintln!(
-                    "Could not send offline notification to user {}",
-                    user_data.id()
-                );
-            }
-        }
-    }
-
-    pub fn get_user(&self, user_id: &str) -> Result<&User, String> {
-        let user = match self.users.iter().position(|v| v.id() == user_id) {
-            Some(index) => &self.users[index],
-            None => return Err(String::from("user id not found")),
-        };
-
-        Ok(user)
-    }
-
-    fn get_user_mut(&mut self, user_id: &str) -> Result<&mut User, String> {
-        let user = match self.users.iter().position(|v| v.id() == user_id) {
-            Some(index) => &mut self.users[index],
-            None => return Err(String::from("user id not found")),
-        };
-
-        Ok(user)
-    }
-
-    fn get_user_id_and_token_from_request<T>(
-        request: &Request<T>,
-    ) -> Result<(String, String), String> {
-        let user_id = match request.metadata().get("user_id") {
-            Some(user_id) => user_id.to_str(),
-            None => return Err(String::from("no user id in metadata")),
-        };
-
-        let user_id = match user_id {
-            Ok(user_id) => user_id,
-            Err(err) => return Err(err.to_string()),
-        };
-
-        let user_token = match request.metadata().get("user_token") {
-            Some(user_token) => user_token.to_str(),
-            None => return Err(String::from("no user id in metadata")),
-        };
-
-        let user_token = match user_token {
-            Ok(user_token) => user_token,
-            Err(err) => return Err(err.to_string()),
-        };
-
-        Ok((String::from(user_id), String::from(user_token)))
-    }
-
-    pub fn get_user_from_request<T>(
-        request: &Request<T>,
-        users: &Arc<Mutex<UserList>>,
-    ) -> Result<UserData, String> {
-        let (user_id, _user_token) = match UserList::get_user_id_and_token_from_request(&request) {
-            Ok((user_id, user_token)) => (user_id, user_token),
-            Err(err) => return Err(err),
-        };
-
-        let users = match users.lock() {
-            Ok(guard) => guard,
-            Err(_) => return Err(String::from("unable to acquire lock")),
-        };
-
-        let user = users.get_user(&user_id)?;
-        Ok(user.user_data.clone())
-    }
-
-    fn is_user_authenticated(&self, user_id: &str, user_token: &str) -> bool {
-        match self
-            .users
-            .iter()
-            .position(|v| v.id() == user_id && v.user_data.token() == user_token)
-        {
-            Some(_index) => true,
-            None => false,
-        }
-    }
-
-    pub fn authenticate(
-        request: Request<()>,
-        users: &Arc<Mutex<UserList>>,
-    ) -> Result<Request<()>, Status> {
-        let (user_id, user_token) = match UserList::get_user_id_and_token_from_request(&request) {
-            Ok((user_id, user_token)) => (user_id, user_token),
-            Err(_err) => return Err(Status::unauthenticated("could not authenticate")),
-        };
-
-        let users = match users.lock() {
-            Ok(guard) => guard,
-            Err(_) => return Err(Status::unauthenticated("unable to acquire lock")),
-        };
-
-        // check if user is authenticated
-        match users.is_user_authenticated(&user_id, &user_token) {
-            true => Ok(request),
-            false => Err(Status::unauthenticated("could not authenticate")),
-        }
-    }
-
-    pub fn take_receiver<T>(
-        request: &Request<T>,
-        users: &Arc<Mutex<UserList>>,
-    ) -> Result<mpsc::Receiver<chat::IncomingNotification>, String> {
-        let (user_id, _user_token) = match UserList::get_user_id_and_token_from_request(&request) {
-            Ok((user_id, user_token)) => (user_id, user_token),
-            Err(err) => return Err(err),
-        };
-
-        let mut users = match users.lock() {
-            Ok(guard) => guard,
-            Err(_) => return Err(String::from("unable to acquire lock")),
-        };
-
-        let user = users.get_user_mut(&user_id)?;
-        Ok(user.take_receiver())
-    }
-}
+#[cfg(test)]
+mod mock;
+mod notification_queue;
+mod outbox;
+mod registry;
+mod user;
+mod user_data;
+
+use crate::error::ChatError;
+use crate::metrics;
+use proto::chat;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use user::User;
+
+#[cfg(test)]
+pub use mock::MockRegistry;
+pub use notification_queue::{NotificationReceiver, NotificationSender, QUEUE_CAPACITY};
+pub use outbox::Idempotency;
+pub use registry::{credentials, Recipient, UserRegistry};
+pub use user_data::UserData;
+
+pub struct UserList {
+    users: Vec<User>,
+    remote_users: HashMap<String, RemoteUser>,
+}
+
+/// A user connected to another node of the backplane.
+struct RemoteUser {
+    user: chat::User,
+    node_id: String,
+}
+
+impl UserList {
+    pub fn new() -> UserList {
+        UserList {
+            users: vec![],
+            remote_users: HashMap::new(),
+        }
+    }
+
+    /// Registers a new user, see `UserRegistry::create_user`.
+    pub fn create_user(&mut self, name: &str) -> Result<UserData, ChatError> {
+        // check if user exists
+        if self.users.iter().any(|v| v.user_data.name() == name)
+            || self.remote_users.values().any(|v| v.user.name == name)
+        {
+            return Err(ChatError::UserAlreadyExists(String::from(name)));
+        }
+
+        let user = User::new(name);
+        let user_data = user.user_data.clone();
+
+        self.users.push(user);
+        metrics::CONNECTED_USERS.set(self.users.len() as i64);
+
+        Ok(user_data)
+    }
+
+    /// Registers a bot whose notifications are delivered to a webhook, so its receiver is handed
+    /// out right away. The bot comes online once it is set online like a receiving user.
+    pub fn create_bot(
+        &mut self,
+        name: &str,
+    ) -> Result<(UserData, NotificationReceiver), ChatError> {
+        let user_data = self.create_user(name)?;
+
+        let user = self.get_user_mut(&user_data.id())?;
+        user.set_bot();
+
+        match user.take_receiver() {
+            Some(receiver) => Ok((user_data, receiver)),
+            None => Err(ChatError::ReceiverTaken(user_data.id())),
+        }
+    }
+
+    /// Removes a user, see `UserRegistry::remove_user`.
+    pub fn remove_user(&mut self, user_id: &str) -> Result<(), ChatError> {
+        let user = match self.users.iter().position(|v| v.id() == user_id) {
+            Some(index) => self.users.remove(index),
+            None => return Err(ChatError::UserNotFound(String::from(user_id))),
+        };
+        metrics::CONNECTED_USERS.set(self.users.len() as i64);
+
+        // notify other users that this user is offline
+        if user.user_data.is_online() {
+            for other_user in self.users.iter().filter(|v| v.user_data.is_online()) {
+                UserList::send_presence(other_user, user.user_data.user(), false);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns all users connected to this node.
+    pub fn local_users(&self) -> Vec<chat::User> {
+        self.users.iter().map(|v| v.user_data.user()).collect()
+    }
+
+    /// Returns the users who are online on this or on any other node, sorted by name.
+    pub fn online_users(&self) -> Vec<chat::User> {
+        let mut users: Vec<chat::User> = self
+            .users
+            .iter()
+            .filter(|v| v.user_data.is_online())
+            .map(|v| v.user_data.user())
+            .chain(self.remote_users.values().map(|v| v.user.clone()))
+            .collect();
+
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        users
+    }
+
+    /// Returns the ids of all users connected to other nodes of the backplane.
+    pub fn remote_user_ids(&self) -> Vec<String> {
+        self.remote_users.keys().cloned().collect()
+    }
+
+    /// Returns the user with the given id if it is connected to another node of the backplane.
+    pub fn remote_user(&self, user_id: &str) -> Option<chat::User> {
+        self.remote_users.get(user_id).map(|v| v.user.clone())
+    }
+
+    /// Returns true if the user is connected to another node of the backplane.
+    pub fn is_remote_user(&self, user_id: &str) -> bool {
+        self.remote_users.contains_key(user_id)
+    }
+
+    /// Tracks the presence of a user connected to another node and notifies all local users.
+    pub fn set_remote_user_online(&mut self, node_id: &str, user: chat::User, is_online: bool) {
+        if is_online {
+            // ignore users which are already known
+            if self.get_user(&user.id).is_ok() || self.remote_users.contains_key(&user.id) {
+                return;
+            }
+
+            self.remote_users.insert(
+                user.id.clone(),
+                RemoteUser {
+                    user: user.clone(),
+                    node_id: String::from(node_id),
+                },
+            );
+        } else if self.remote_users.remove(&user.id).is_none() {
+            return;
+        }
+
+        for local_user in self.users.iter().filter(|v| v.user_data.is_online()) {
+            UserList::send_presence(local_user, user.clone(), is_online);
+        }
+    }
+
+    /// Marks all users connected to the given node as offline.
+    pub fn remove_node(&mut self, node_id: &str) {
+        let users: Vec<chat::User> = self
+            .remote_users
+            .values()
+            .filter(|v| v.node_id == node_id)
+            .map(|v| v.user.clone())
+            .collect();
+
+        for user in users {
+            self.set_remote_user_online(node_id, user, false);
+        }
+    }
+
+    fn send_presence(to_user: &User, from_user: chat::User, is_online: bool) {
+        let from_user_id = from_user.id.clone();
+
+        let send_result = to_user
+            .user_data
+            .sender()
+            .try_send(chat::IncomingNotification {
+                from: Some(from_user),
+                types: Some(chat::incoming_notification::Types::Online(
+                    chat::incoming_notification::Online { is_online },
+                )),
+            });
+
+        if send_result.is_err() {
+            metrics::NOTIFICATIONS_DROPPED
+                .with_label_values(&["online"])
+                .inc();
+            tracing::warn!(
+                user_id = %to_user.id(),
+                online_user_id = %from_user_id,
+                is_online,
+                "could not send presence notification"
+            );
+        }
+    }
+
+    /// Updates the presence of a user, see `UserRegistry::set_online`.
+    pub fn set_user_online(&mut self, user_id: &str, is_online: bool) -> Result<(), ChatError> {
+        let user = self.get_user_mut(user_id)?;
+        if user.user_data.is_online() == is_online {
+            return Ok(());
+        }
+        user.user_data.set_online(is_online);
+
+        let user = self.get_user(user_id)?;
+        let other_users = self
+            .users
+            .iter()
+            .filter(|v| v.id() != user_id && v.user_data.is_online());
+
+        for other_user in other_users {
+            // notify other users of the new state of this user
+            UserList::send_presence(other_user, user.user_data.user(), is_online);
+
+            // notify the new user of all currently active users
+            if is_online {
+                UserList::send_presence(user, other_user.user_data.user(), true);
+            }
+        }
+
+        // notify the new user of all users connected to other nodes
+        if is_online {
+            for remote_user in self.remote_users.values() {
+                UserList::send_presence(user, remote_user.user.clone(), true);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_user(&self, user_id: &str) -> Result<&User, ChatError> {
+        let user = match self.users.iter().position(|v| v.id() == user_id) {
+            Some(index) => &self.users[index],
+            None => return Err(ChatError::UserNotFound(String::from(user_id))),
+        };
+
+        Ok(user)
+    }
+
+    fn get_user_mut(&mut self, user_id: &str) -> Result<&mut User, ChatError> {
+        let user = match self.users.iter().position(|v| v.id() == user_id) {
+            Some(index) => &mut self.users[index],
+            None => return Err(ChatError::UserNotFound(String::from(user_id))),
+        };
+
+        Ok(user)
+    }
+
+    /// Returns the sessions of all users connected to this node.
+    pub fn sessions(&self) -> Vec<chat::Session> {
+        self.users
+            .iter()
+            .map(|user| chat::Session {
+                user: Some(user.user_data.user()),
+                is_receiving: user.is_receiving(),
+            })
+            .collect()
+    }
+
+    /// Returns the number of queued notifications of all users connected to this node.
+    pub fn queue_depths(&self) -> Vec<chat::QueueDepth> {
+        self.users
+            .iter()
+            .map(|user| chat::QueueDepth {
+                user: Some(user.user_data.user()),
+                depth: user.user_data.sender().depth() as u32,
+                capacity: QUEUE_CAPACITY as u32,
+            })
+            .collect()
+    }
+
+    /// Sends a notification to the given users connected to this node, or to all of them if no
+    /// user ids are given. Returns the number of users it was enqueued for.
+    pub fn broadcast(
+        &self,
+        notification: &chat::IncomingNotification,
+        user_ids: &[String],
+    ) -> usize {
+        let mut recipients = 0;
+
+        let users = self
+            .users
+            .iter()
+            .filter(|user| user_ids.is_empty() || user_ids.contains(&user.id()));
+
+        for user in users {
+            match user.user_data.sender().try_send(notification.clone()) {
+                Ok(()) => recipients += 1,
+                Err(_) => {
+                    metrics::NOTIFICATIONS_DROPPED
+                        .with_label_values(&["broadcast"])
+                        .inc();
+                    tracing::warn!(user_id = %user.id(), "could not send broadcast notification");
+                }
+            }
+        }
+
+        recipients
+    }
+
+    /// Notifies the user that they are being disconnected and ends their session. The user is
+    /// removed once the session has ended.
+    pub fn disconnect_user(&mut self, user_id: &str, reason: &str) -> Result<(), ChatError> {
+        let user = self.get_user_mut(user_id)?;
+
+        // the session is already ending
+        if user.is_disconnected() {
+            return Ok(());
+        }
+
+        let send_result = user
+            .user_data
+            .sender()
+            .try_send(chat::IncomingNotification {
+                from: None,
+                types: Some(chat::incoming_notification::Types::Disconnected(
+                    chat::incoming_notification::Disconnected {
+                        reason: String::from(reason),
+                    },
+                )),
+            });
+
+        if send_result.is_err() {
+            metrics::NOTIFICATIONS_DROPPED
+                .with_label_values(&["disconnected"])
+                .inc();
+            tracing::warn!(%user_id, "could not send disconnected notification");
+        }
+
+        user.disconnect(reason);
+
+        Ok(())
+    }
+
+    /// Sends a heartbeat notification to every user with an open receive stream and an empty
+    /// queue. Bots are skipped, their webhooks can't answer heartbeats and bots never time out.
+    pub fn send_heartbeats(&self) {
+        let notification = chat::IncomingNotification {
+            from: None,
+            types: Some(chat::incoming_notification::Types::Heartbeat(
+                chat::incoming_notification::Heartbeat {
+                    time_sent: Some(SystemTime::now().into()),
+                },
+            )),
+        };
+
+        let receiving_users = self
+            .users
+            .iter()
+            .filter(|user| user.is_receiving() && !user.is_bot());
+
+        for user in receiving_users {
+            let mut sender = user.user_data.sender();
+
+            // queued notifications keep the stream busy, a heartbeat would only take the slot of
+            // a message
+            if sender.depth() > 0 || sender.try_send(notification.clone()).is_err() {
+                tracing::debug!(user_id = %user.id(), "heartbeat skipped");
+            }
+        }
+    }
+
+    /// Returns the ids of all users who have not made a request within `timeout`, except bots.
+    pub fn expired_users(&self, timeout: Duration) -> Vec<String> {
+        self.users
+            .iter()
+            .filter(|user| {
+                !user.is_disconnected() && !user.is_bot() && user.last_seen().elapsed() > timeout
+            })
+            .map(|user| user.id())
+            .collect()
+    }
+
+    /// Marks all users as offline and returns the notifications every receiving user has to be
+    /// sent before the server shuts down: the offline state of all other users, followed by a
+    /// server shutdown notification.
+    pub fn shutdown_notifications(
+        &mut self,
+        reason: &str,
+    ) -> Vec<(NotificationSender, Vec<chat::IncomingNotification>)> {
+        for user in &mut self.users {
+            user.user_data.set_online(false);
+        }
+
+        let mut notifications = vec![];
+
+        for user in &self.users {
+            // users without a receive stream get nothing delivered, their queue is persisted instead
+            if !user.is_receiving() {
+                continue;
+            }
+
+            let mut user_notifications: Vec<chat::IncomingNotification> = self
+                .users
+                .iter()
+                .filter(|other_user| other_user.id() != user.id())
+                .map(|other_user| chat::IncomingNotification {
+                    from: Some(other_user.user_data.user()),
+                    types: Some(chat::incoming_notification::Types::Online(
+                        chat::incoming_notification::Online { is_online: false },
+                    )),
+                })
+                .collect();
+
+            user_notifications.push(chat::IncomingNotification {
+                from: None,
+                types: Some(chat::incoming_notification::Types::ServerShutdown(
+                    chat::incoming_notification::ServerShutdown {
+                        reason: String::from(reason),
+                    },
+                )),
+            });
+
+            notifications.push((user.user_data.sender(), user_notifications));
+        }
+
+        notifications
+    }
+
+    /// Returns the state of all users, including the notifications they have not received yet.
+    pub fn state(&mut self) -> Vec<chat::UserState> {
+        self.users
+            .iter_mut()
+            .map(|user| chat::UserState {
+                user: Some(user.user_data.user()),
+                // heartbeats are meaningless once the server has restarted
+                pending_notifications: user
+                    .pending_notifications()
+                    .into_iter()
+                    .filter(|notification| {
+                        !matches!(
+                            notification.types,
+                            Some(chat::incoming_notification::Types::Heartbeat(_))
+                        )
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Checks the credentials and records that the user has just made a request.
+    pub fn authenticate_user(
+        &mut self,
+        user_id: &str,
+        user_token: &str,
+    ) -> Result<UserData, ChatError> {
+        match self
+            .users
+            .iter_mut()
+            .find(|v| v.id() == user_id && v.user_data.token() == user_token)
+        {
+            Some(user) => {
+                user.touch();
+                Ok(user.user_data.clone())
+            }
+            None => Err(ChatError::InvalidCredentials),
+        }
+    }
+
+    pub fn return_user_receiver(
+        &mut self,
+        user_id: &str,
+        receiver: NotificationReceiver,
+    ) -> Result<(), ChatError> {
+        self.get_user_mut(user_id)?.return_receiver(receiver);
+
+        Ok(())
+    }
+
+    /// Returns the receiver of a closed stream and marks the user offline, see
+    /// `UserRegistry::stop_receiving`.
+    pub fn stop_user_receiving(
+        &mut self,
+        user_id: &str,
+        receiver: Option<NotificationReceiver>,
+    ) -> Result<bool, ChatError> {
+        let user = self.get_user_mut(user_id)?;
+        if let Some(receiver) = receiver {
+            user.return_receiver(receiver);
+        }
+
+        // another stream is receiving the notifications now
+        if user.is_receiving() {
+            return Ok(false);
+        }
+
+        self.set_user_online(user_id, false)?;
+
+        Ok(true)
+    }
+
+    pub fn take_user_receiver(&mut self, user_id: &str) -> Result<NotificationReceiver, ChatError> {
+        let user = self.get_user_mut(user_id)?;
+        match user.take_receiver() {
+            Some(receiver) => Ok(receiver),
+            None => Err(ChatError::ReceiverTaken(String::from(user_id))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeats_are_not_sent_to_bots() {
+        let mut users = UserList::new();
+
+        let (_, mut bot_receiver) = users.create_bot("bot").unwrap();
+        let user_data = users.create_user("alice").unwrap();
+        let mut user_receiver = users.take_user_receiver(&user_data.id()).unwrap();
+
+        users.send_heartbeats();
+
+        assert!(bot_receiver.try_recv().is_err());
+        assert!(matches!(
+            user_receiver.try_recv().unwrap().types,
+            Some(chat::incoming_notification::Types::Heartbeat(_))
+        ));
+    }
+
+    #[test]
+    fn heartbeats_are_not_queued_behind_notifications() {
+        let mut users = UserList::new();
+        let user_data = users.create_user("alice").unwrap();
+        let mut receiver = users.take_user_receiver(&user_data.id()).unwrap();
+
+        users.send_heartbeats();
+        users.send_heartbeats();
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_err());
+
+        users.disconnect_user(&user_data.id(), "bye").unwrap();
+        users.send_heartbeats();
+        assert!(matches!(
+            receiver.try_recv().unwrap().types,
+            Some(chat::incoming_notification::Types::Disconnected(_))
+        ));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn closed_streams_do_not_take_users_offline_who_receive_again() {
+        let mut users = UserList::new();
+        let user_data = users.create_user("alice").unwrap();
+
+        let receiver = users.take_user_receiver(&user_data.id()).unwrap();
+        users.set_user_online(&user_data.id(), true).unwrap();
+
+        assert!(users
+            .stop_user_receiving(&user_data.id(), Some(receiver))
+            .unwrap());
+        let is_online = |users: &UserList| {
+            users
+                .get_user(&user_data.id())
+                .unwrap()
+                .user_data
+                .is_online()
+        };
+        assert!(!is_online(&users));
+
+        // a new stream takes the receiver before the old one is cleaned up
+        let _receiver = users.take_user_receiver(&user_data.id()).unwrap();
+        users.set_user_online(&user_data.id(), true).unwrap();
+
+        assert!(!users.stop_user_receiving(&user_data.id(), None).unwrap());
+        assert!(is_online(&users));
+    }
+
+    #[test]
+    fn broadcasts_are_sent_to_the_given_users_or_all() {
+        let mut users = UserList::new();
+
+        let mut receivers = vec![];
+        let mut user_ids = vec![];
+        for name in &["alice", "bob", "carol"] {
+            let user_data = users.create_user(name).unwrap();
+            receivers.push(users.take_user_receiver(&user_data.id()).unwrap());
+            user_ids.push(user_data.id());
+        }
+
+        let system_message = |content: &str| chat::IncomingNotification {
+            from: None,
+            types: Some(chat::incoming_notification::Types::SystemMessage(
+                chat::incoming_notification::SystemMessage {
+                    message_id: None,
+                    message_content: Some(chat::MessageContent {
+                        time_sent: None,
+                        content: String::from(content),
+                    }),
+                },
+            )),
+        };
+
+        // users not connected to this node are left to the caller
+        let targeted = system_message("to bob");
+        let user_ids_of_bob = vec![user_ids[1].clone(), String::from("unknown")];
+        assert_eq!(users.broadcast(&targeted, &user_ids_of_bob), 1);
+        assert!(receivers[0].try_recv().is_err());
+        assert_eq!(receivers[1].try_recv().unwrap(), targeted);
+        assert!(receivers[2].try_recv().is_err());
+
+        let to_all = system_message("to all");
+        assert_eq!(users.broadcast(&to_all, &[]), 3);
+        for receiver in &mut receivers {
+            assert_eq!(receiver.try_recv().unwrap(), to_all);
+            assert!(receiver.try_recv().is_err());
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use proto::chat;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{SendError, TryRecvError, TrySendError};
+
+/// Number of notifications which can be queued for a single user.
+pub const QUEUE_CAPACITY: usize = 4;
+
+/// Creates the notification queue of a user.
+pub fn channel() -> (NotificationSender, NotificationReceiver) {
+    let (notifications_tx, notifications_rx) = mpsc::channel(QUEUE_CAPACITY);
+    let depth = Arc::new(AtomicUsize::new(0));
+
+    (
+        NotificationSender {
+            notifications_tx,
+            depth: depth.clone(),
+        },
+        NotificationReceiver {
+            notifications_rx,
+            depth,
+        },
+    )
+}
+
+/// Sending half of a user's notification queue, keeps track of the number of queued notifications.
+#[derive(Clone)]
+pub struct NotificationSender {
+    notifications_tx: mpsc::Sender<chat::IncomingNotification>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl NotificationSender {
+    pub fn try_send(
+        &mut self,
+        notification: chat::IncomingNotification,
+    ) -> Result<(), TrySendError<chat::IncomingNotification>> {
+        // counted up front, the notification may be received before try_send returns
+        self.depth.fetch_add(1, Ordering::SeqCst);
+
+        let result = self.notifications_tx.try_send(notification);
+        if result.is_err() {
+            self.depth.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        result
+    }
+
+    pub async fn send(
+        &mut self,
+        notification: chat::IncomingNotification,
+    ) -> Result<(), SendError<chat::IncomingNotification>> {
+        self.depth.fetch_add(1, Ordering::SeqCst);
+
+        let result = self.notifications_tx.send(notification).await;
+        if result.is_err() {
+            self.depth.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        result
+    }
+
+    /// Returns the number of notifications which have not been received yet.
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+}
+
+/// Receiving half of a user's notification queue.
+pub struct NotificationReceiver {
+    notifications_rx: mpsc::Receiver<chat::IncomingNotification>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl NotificationReceiver {
+    pub async fn recv(&mut self) -> Option<chat::IncomingNotification> {
+        let notification = self.notifications_rx.recv().await;
+        if notification.is_some() {
+            self.depth.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        notification
+    }
+
+    pub fn try_recv(&mut self) -> Result<chat::IncomingNotification, TryRecvError> {
+        let notification = self.notifications_rx.try_recv();
+        if notification.is_ok() {
+            self.depth.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        notification
+    }
+}
@@ -0,0 +1,151 @@
+use crate::error::ChatError;
+use proto::chat;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Time for which the response to a request with an idempotency key is remembered.
+pub const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(600);
+
+/// What to do with a request carrying an idempotency key, see `Outbox::begin`.
+#[derive(Debug, PartialEq)]
+pub enum Idempotency {
+    /// The key is new and has been reserved, the request is sent.
+    Send,
+    /// A request with the key has succeeded, its response is returned again.
+    Sent(chat::SendResponse),
+    /// A request with the key is still being sent.
+    InProgress,
+}
+
+#[derive(Default)]
+struct State {
+    /// Sequence number of the last message sent to a user, by user id.
+    sequence_numbers: HashMap<String, Arc<AsyncMutex<u64>>>,
+    /// The responses of the requests with an idempotency key, `None` while they are sent.
+    responses: HashMap<String, Option<chat::SendResponse>>,
+    /// The idempotency keys in the order they were used, to forget them once they expire.
+    keys: VecDeque<(Instant, String)>,
+}
+
+/// Numbers the messages a user sends and remembers the requests sent with an idempotency key.
+#[derive(Default)]
+pub struct Outbox {
+    state: Mutex<State>,
+}
+
+impl Outbox {
+    /// Returns the sequence number of the last message delivered to the given user, 0 before the
+    /// first one. Senders hold the lock while they deliver a message and only count it once it
+    /// has been delivered, so messages are delivered in the order of their numbers and failed
+    /// deliveries don't use up a number.
+    pub fn sequence_number(&self, to_user_id: &str) -> Result<Arc<AsyncMutex<u64>>, ChatError> {
+        let mut state = match self.state.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(ChatError::LockPoisoned),
+        };
+
+        let sequence_number = state
+            .sequence_numbers
+            .entry(String::from(to_user_id))
+            .or_default();
+
+        Ok(sequence_number.clone())
+    }
+
+    /// Reserves a new idempotency key, or returns what became of the request which used it.
+    pub fn begin(&self, idempotency_key: &str) -> Result<Idempotency, ChatError> {
+        let mut state = match self.state.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(ChatError::LockPoisoned),
+        };
+        let now = Instant::now();
+
+        while let Some((time, _)) = state.keys.front() {
+            if now.duration_since(*time) < IDEMPOTENCY_KEY_TTL {
+                break;
+            }
+
+            if let Some((_, key)) = state.keys.pop_front() {
+                state.responses.remove(&key);
+            }
+        }
+
+        let idempotency = match state.responses.get(idempotency_key) {
+            Some(Some(response)) => Idempotency::Sent(response.clone()),
+            Some(None) => Idempotency::InProgress,
+            None => {
+                let key = String::from(idempotency_key);
+                state.responses.insert(key.clone(), None);
+                state.keys.push_back((now, key));
+
+                Idempotency::Send
+            }
+        };
+
+        Ok(idempotency)
+    }
+
+    /// Remembers the response to the request with the key, or releases the key if the request
+    /// failed, so it can be retried.
+    pub fn finish(&self, idempotency_key: &str, response: Option<&chat::SendResponse>) {
+        let mut state = match self.state.lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                tracing::error!("unable to acquire lock, idempotency key not finished");
+                return;
+            }
+        };
+
+        match response {
+            Some(response) => {
+                if let Some(entry) = state.responses.get_mut(idempotency_key) {
+                    *entry = Some(response.clone());
+                }
+            }
+            None => {
+                state.responses.remove(idempotency_key);
+                state.keys.retain(|(_, key)| key != idempotency_key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn messages_are_numbered_per_recipient() {
+        let outbox = Outbox::default();
+
+        *outbox.sequence_number("bob").unwrap().lock().await += 1;
+        *outbox.sequence_number("bob").unwrap().lock().await += 1;
+
+        assert_eq!(*outbox.sequence_number("bob").unwrap().lock().await, 2);
+        assert_eq!(*outbox.sequence_number("carol").unwrap().lock().await, 0);
+    }
+
+    #[test]
+    fn idempotency_keys_return_the_first_response() {
+        let outbox = Outbox::default();
+        let response = chat::SendResponse {
+            message_id: Some(chat::MessageId {
+                id: String::from("message-id"),
+            }),
+            sequence_number: 1,
+        };
+
+        assert_eq!(outbox.begin("key").unwrap(), Idempotency::Send);
+        assert_eq!(outbox.begin("key").unwrap(), Idempotency::InProgress);
+
+        outbox.finish("key", Some(&response));
+        assert_eq!(outbox.begin("key").unwrap(), Idempotency::Sent(response));
+
+        // failed requests can be retried
+        assert_eq!(outbox.begin("other key").unwrap(), Idempotency::Send);
+        outbox.finish("other key", None);
+        assert_eq!(outbox.begin("other key").unwrap(), Idempotency::Send);
+    }
+}
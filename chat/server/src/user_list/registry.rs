@@ -0,0 +1,145 @@
+use super::{NotificationReceiver, UserData, UserList};
+use crate::error::ChatError;
+use proto::chat;
+use std::sync::{Mutex, MutexGuard};
+use tonic::Request;
+
+/// A user a notification can be sent to.
+pub enum Recipient {
+    /// Connected to this node, notifications are enqueued directly.
+    Local(UserData),
+    /// Connected to another node, notifications are routed via the backplane.
+    Remote(chat::User),
+}
+
+/// Keeps track of the users, their sessions and their presence, shared by the services.
+#[tonic::async_trait]
+pub trait UserRegistry: Send + Sync {
+    /// Registers a new user, who is offline until they start receiving notifications. The id is
+    /// generated, so names identify users: a name can only be registered once across all nodes
+    /// and fails with `UserAlreadyExists` until the user has been removed.
+    async fn create_user(&self, name: &str) -> Result<UserData, ChatError>;
+
+    /// Removes the user and notifies the other users that they went offline.
+    async fn remove_user(&self, user_id: &str) -> Result<(), ChatError>;
+
+    /// Updates the presence of the user and notifies the other online users. A user who comes
+    /// online is also told about everyone who is already online.
+    async fn set_online(&self, user_id: &str, is_online: bool) -> Result<(), ChatError>;
+
+    /// Checks the credentials and records that the user has just made a request.
+    async fn authenticate(&self, user_id: &str, user_token: &str) -> Result<UserData, ChatError>;
+
+    /// Finds the user with the given id on this or on any other node.
+    async fn lookup(&self, user_id: &str) -> Result<Recipient, ChatError>;
+
+    /// Returns the users who are online on this or on any other node, sorted by name.
+    async fn online_users(&self) -> Result<Vec<chat::User>, ChatError>;
+
+    /// Hands out the notification queue of the user, which can only be received by one stream.
+    async fn take_receiver(&self, user_id: &str) -> Result<NotificationReceiver, ChatError>;
+
+    /// Takes back the notification queue of a closed stream, so the next stream receives the
+    /// notifications which have not been delivered yet.
+    async fn return_receiver(
+        &self,
+        user_id: &str,
+        receiver: NotificationReceiver,
+    ) -> Result<(), ChatError>;
+
+    /// Hands back the receiver of a closed stream, if it still has one, and marks the user
+    /// offline in one step, so a new stream can't take the receiver in between. Returns whether
+    /// the user went offline, which they don't if a new stream has already taken the receiver.
+    async fn stop_receiving(
+        &self,
+        user_id: &str,
+        receiver: Option<NotificationReceiver>,
+    ) -> Result<bool, ChatError>;
+}
+
+/// Returns the user id and token from the request metadata.
+pub fn credentials<T>(request: &Request<T>) -> Result<(String, String), ChatError> {
+    let user_id = match request.metadata().get("user_id") {
+        Some(user_id) => user_id.to_str(),
+        None => return Err(ChatError::MissingCredentials("user_id")),
+    };
+
+    let user_id = match user_id {
+        Ok(user_id) => user_id,
+        Err(_) => return Err(ChatError::MissingCredentials("user_id")),
+    };
+
+    let user_token = match request.metadata().get("user_token") {
+        Some(user_token) => user_token.to_str(),
+        None => return Err(ChatError::MissingCredentials("user_token")),
+    };
+
+    let user_token = match user_token {
+        Ok(user_token) => user_token,
+        Err(_) => return Err(ChatError::MissingCredentials("user_token")),
+    };
+
+    Ok((String::from(user_id), String::from(user_token)))
+}
+
+fn lock(users: &Mutex<UserList>) -> Result<MutexGuard<'_, UserList>, ChatError> {
+    match users.lock() {
+        Ok(guard) => Ok(guard),
+        Err(_) => Err(ChatError::LockPoisoned),
+    }
+}
+
+#[tonic::async_trait]
+impl UserRegistry for Mutex<UserList> {
+    async fn create_user(&self, name: &str) -> Result<UserData, ChatError> {
+        lock(self)?.create_user(name)
+    }
+
+    async fn remove_user(&self, user_id: &str) -> Result<(), ChatError> {
+        lock(self)?.remove_user(user_id)
+    }
+
+    async fn set_online(&self, user_id: &str, is_online: bool) -> Result<(), ChatError> {
+        lock(self)?.set_user_online(user_id, is_online)
+    }
+
+    async fn authenticate(&self, user_id: &str, user_token: &str) -> Result<UserData, ChatError> {
+        lock(self)?.authenticate_user(user_id, user_token)
+    }
+
+    async fn lookup(&self, user_id: &str) -> Result<Recipient, ChatError> {
+        let users = lock(self)?;
+
+        match users.get_user(user_id) {
+            Ok(user) => Ok(Recipient::Local(user.user_data.clone())),
+            Err(err) => match users.remote_user(user_id) {
+                Some(user) => Ok(Recipient::Remote(user)),
+                None => Err(err),
+            },
+        }
+    }
+
+    async fn online_users(&self) -> Result<Vec<chat::User>, ChatError> {
+        Ok(lock(self)?.online_users())
+    }
+
+    async fn take_receiver(&self, user_id: &str) -> Result<NotificationReceiver, ChatError> {
+        lock(self)?.take_user_receiver(user_id)
+    }
+
+    async fn return_receiver(
+        &self,
+        user_id: &str,
+        receiver: NotificationReceiver,
+    ) -> Result<(), ChatError> {
+        lock(self)?.return_user_receiver(user_id, receiver)
+    }
+
+    async fn stop_receiving(
+        &self,
+        user_id: &str,
+        receiver: Option<NotificationReceiver>,
+    ) -> Result<bool, ChatError> {
+        lock(self)?.stop_user_receiving(user_id, receiver)
+    }
+}
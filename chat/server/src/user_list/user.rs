@@ -1,24 +1,86 @@
+use super::notification_queue::{self, NotificationReceiver};
 use super::UserData;
 use proto::chat;
-use tokio::sync::mpsc;
+use std::time::Instant;
+use tokio::sync::watch;
 
 pub struct User {
     pub user_data: UserData,
-    notifications_rx: Option<mpsc::Receiver<chat::IncomingNotification>>,
+    notifications_rx: Option<NotificationReceiver>,
+    disconnect_tx: watch::Sender<Option<String>>,
+    is_disconnected: bool,
+    is_bot: bool,
+    last_seen: Instant,
 }
 
 impl User {
     pub fn new(name: &str) -> User {
-        let (notifications_tx, notifications_rx) = mpsc::channel(4);
+        let (notifications_tx, notifications_rx) = notification_queue::channel();
+        let (disconnect_tx, disconnect_rx) = watch::channel(None);
 
         User {
-            user_data: UserData::new(String::from(name), notifications_tx),
+            user_data: UserData::new(String::from(name), notifications_tx, disconnect_rx),
             notifications_rx: Some(notifications_rx),
+            disconnect_tx,
+            is_disconnected: false,
+            is_bot: false,
+            last_seen: Instant::now(),
         }
     }
 
-    pub fn take_receiver(&mut self) -> mpsc::Receiver<chat::IncomingNotification> {
-        self.notifications_rx.take().unwrap()
+    pub fn take_receiver(&mut self) -> Option<NotificationReceiver> {
+        self.notifications_rx.take()
+    }
+
+    /// Puts back the receiver of a closed stream, queued notifications are kept for the next one.
+    pub fn return_receiver(&mut self, receiver: NotificationReceiver) {
+        self.notifications_rx = Some(receiver);
+    }
+
+    /// Ends the session of this user, see `UserData::disconnected`.
+    pub fn disconnect(&mut self, reason: &str) {
+        self.is_disconnected = true;
+        let _ = self.disconnect_tx.broadcast(Some(String::from(reason)));
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.is_disconnected
+    }
+
+    /// Marks the user as a bot whose notifications are delivered to a webhook, bots don't time out.
+    pub fn set_bot(&mut self) {
+        self.is_bot = true;
+    }
+
+    pub fn is_bot(&self) -> bool {
+        self.is_bot
+    }
+
+    /// Records that the user has just made a request.
+    pub fn touch(&mut self) {
+        self.last_seen = Instant::now();
+    }
+
+    pub fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+
+    /// Returns true if a stream has taken the receiver and is delivering notifications.
+    pub fn is_receiving(&self) -> bool {
+        self.notifications_rx.is_none()
+    }
+
+    /// Removes and returns all notifications which are queued but have not been received.
+    pub fn pending_notifications(&mut self) -> Vec<chat::IncomingNotification> {
+        let mut notifications = vec![];
+
+        if let Some(notifications_rx) = &mut self.notifications_rx {
+            while let Ok(notification) = notifications_rx.try_recv() {
+                notifications.push(notification);
+            }
+        }
+
+        notifications
     }
 
     pub fn id(&self) -> String {
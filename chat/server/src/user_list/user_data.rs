@@ -1,5 +1,8 @@
+use super::notification_queue::NotificationSender;
+use super::outbox::Outbox;
 use proto::chat;
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use tokio::sync::watch;
 use uuid::Uuid;
 
 #[derive(Clone)]
@@ -7,22 +10,30 @@ pub struct UserData {
     user: chat::User,
     token: String,
     is_online: bool,
-    notifications_tx: mpsc::Sender<chat::IncomingNotification>,
+    notifications_tx: NotificationSender,
+    disconnect_rx: watch::Receiver<Option<String>>,
+    outbox: Arc<Outbox>,
 }
 
 impl UserData {
-    pub fn new(name: String, sender: mpsc::Sender<chat::IncomingNotification>) -> UserData {
+    pub fn new(
+        name: String,
+        sender: NotificationSender,
+        disconnect_rx: watch::Receiver<Option<String>>,
+    ) -> UserData {
         let id = Uuid::new_v4();
         let token = Uuid::new_v4();
 
         UserData {
             user: chat::User {
                 id: id.to_hyphenated().to_string(),
-                name: name,
+                name,
             },
             token: token.to_hyphenated().to_string(),
             is_online: false,
             notifications_tx: sender,
+            disconnect_rx,
+            outbox: Arc::new(Outbox::default()),
         }
     }
 
@@ -50,7 +61,26 @@ impl UserData {
         self.is_online = is_online;
     }
 
-    pub fn sender(&self) -> mpsc::Sender<chat::IncomingNotification> {
+    pub fn sender(&self) -> NotificationSender {
         self.notifications_tx.clone()
     }
+
+    /// The messages sent by the user, shared by all copies of the user's data.
+    pub fn outbox(&self) -> &Outbox {
+        &self.outbox
+    }
+
+    /// Resolves with the reason once the server disconnected the user, or with `None` once
+    /// the user was removed.
+    pub async fn disconnected(&self) -> Option<String> {
+        let mut disconnect_rx = self.disconnect_rx.clone();
+
+        while let Some(reason) = disconnect_rx.recv().await {
+            if reason.is_some() {
+                return reason;
+            }
+        }
+
+        None
+    }
 }
@@ -1,3 +1,5 @@
 mod response_stream;
+mod status_details;
 
 pub use response_stream::ResponseStream;
+pub use status_details::{status_with_details, to_any, to_rpc_status};
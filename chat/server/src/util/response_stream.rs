@@ -1,51 +1,262 @@
-use futures::channel::oneshot;
+use crate::metrics;
+use std::fmt;
+use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tokio::stream::Stream;
+use tokio::time::{Delay, Instant};
+use tonic::Status;
 
+/// Why a response stream ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CloseReason {
+    /// The server ended the stream after its last item.
+    Finished,
+    /// The server ended the stream with an error status.
+    Error,
+    /// The stream was dropped before it ended, the client cancelled the call or the connection
+    /// was lost.
+    Cancelled,
+    /// No item was sent within the idle timeout.
+    IdleTimeout,
+}
+
+impl CloseReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloseReason::Finished => "finished",
+            CloseReason::Error => "error",
+            CloseReason::Cancelled => "cancelled",
+            CloseReason::IdleTimeout => "idle_timeout",
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+type InnerStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync>>;
+type CloseCallback = Box<dyn FnOnce(CloseReason) + Send + Sync>;
+type ItemHook<T> = Box<dyn FnMut(&Result<T, Status>) + Send + Sync>;
+
+/// Response stream of a server streaming RPC which tracks its lifecycle: it reports why it
+/// ended to all close callbacks, counts open and closed streams and can end itself once idle.
 pub struct ResponseStream<T> {
-    inner: Pin<Box<dyn Stream<Item = T> + Send + Sync>>,
-    chan: Option<oneshot::Sender<bool>>,
+    name: &'static str,
+    /// Dropped as soon as the stream ends, so close callbacks can reclaim what it holds.
+    inner: Option<InnerStream<T>>,
+    on_close: Vec<CloseCallback>,
+    on_item: Vec<ItemHook<T>>,
+    idle_timeout: Option<(Duration, Delay)>,
+    close_reason: Option<CloseReason>,
 }
 
 impl<T> ResponseStream<T> {
-    pub fn new<S>(stream: S) -> ResponseStream<T>
+    pub fn new<S>(name: &'static str, stream: S) -> ResponseStream<T>
     where
-        S: Stream<Item = T> + Send + Sync + 'static,
+        S: Stream<Item = Result<T, Status>> + Send + Sync + 'static,
     {
+        metrics::OPEN_STREAMS.with_label_values(&[name]).inc();
+
         ResponseStream {
-            inner: Box::pin(stream),
-            chan: None,
+            name,
+            inner: Some(Box::pin(stream)),
+            on_close: vec![],
+            on_item: vec![],
+            idle_timeout: None,
+            close_reason: None,
         }
     }
 
-    pub fn new_with_close_notification<S>(
-        chan: oneshot::Sender<bool>,
-        stream: S,
-    ) -> ResponseStream<T>
+    /// Adds a callback which is called once with the reason the stream ended, either when the
+    /// stream ends or when it is dropped.
+    pub fn on_close<F>(mut self, callback: F) -> ResponseStream<T>
     where
-        S: Stream<Item = T> + Send + Sync + 'static,
+        F: FnOnce(CloseReason) + Send + Sync + 'static,
     {
-        ResponseStream {
-            inner: Box::pin(stream),
-            chan: Some(chan),
+        self.on_close.push(Box::new(callback));
+        self
+    }
+
+    /// Adds a hook which sees every item before it is sent.
+    pub fn on_item<F>(mut self, hook: F) -> ResponseStream<T>
+    where
+        F: FnMut(&Result<T, Status>) + Send + Sync + 'static,
+    {
+        self.on_item.push(Box::new(hook));
+        self
+    }
+
+    /// Ends the stream with a deadline exceeded status if no item is sent within `timeout`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> ResponseStream<T> {
+        self.idle_timeout = Some((timeout, tokio::time::delay_for(timeout)));
+        self
+    }
+
+    fn close(&mut self, reason: CloseReason) {
+        if self.close_reason.is_some() {
+            return;
+        }
+        self.close_reason = Some(reason);
+        self.inner = None;
+
+        metrics::STREAMS_CLOSED
+            .with_label_values(&[self.name, reason.as_str()])
+            .inc();
+
+        for callback in self.on_close.drain(..) {
+            callback(reason);
         }
     }
 }
 
 impl<T> Stream for ResponseStream<T> {
-    type Item = T;
+    type Item = Result<T, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let inner = match &mut self.inner {
+            Some(inner) => inner,
+            None => return Poll::Ready(None),
+        };
+
+        match inner.as_mut().poll_next(cx) {
+            Poll::Ready(Some(item)) => {
+                if let Some((timeout, delay)) = &mut self.idle_timeout {
+                    delay.reset(Instant::now() + *timeout);
+                }
+
+                for hook in &mut self.on_item {
+                    hook(&item);
+                }
 
-    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
-        self.inner.as_mut().as_mut().poll_next(cx)
+                // nothing is sent after an error status
+                if item.is_err() {
+                    self.close(CloseReason::Error);
+                }
+
+                Poll::Ready(Some(item))
+            }
+            Poll::Ready(None) => {
+                self.close(CloseReason::Finished);
+                Poll::Ready(None)
+            }
+            Poll::Pending => {
+                let is_idle = match &mut self.idle_timeout {
+                    Some((_, delay)) => Pin::new(delay).poll(cx).is_ready(),
+                    None => false,
+                };
+
+                if !is_idle {
+                    return Poll::Pending;
+                }
+
+                self.close(CloseReason::IdleTimeout);
+                Poll::Ready(Some(Err(Status::deadline_exceeded(format!(
+                    "{} stream was idle for too long",
+                    self.name
+                )))))
+            }
+        }
     }
 }
 
 impl<T> Drop for ResponseStream<T> {
     fn drop(&mut self) {
-        let chan = self.chan.take();
-        if let Some(chan) = chan {
-            chan.send(true).unwrap();
+        metrics::OPEN_STREAMS.with_label_values(&[self.name]).dec();
+
+        self.close(CloseReason::Cancelled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::oneshot;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn closed(
+        stream: ResponseStream<u32>,
+    ) -> (ResponseStream<u32>, oneshot::Receiver<CloseReason>) {
+        let (close_tx, close_rx) = oneshot::channel();
+        let stream = stream.on_close(move |reason| {
+            let _ = close_tx.send(reason);
+        });
+
+        (stream, close_rx)
+    }
+
+    #[tokio::test]
+    async fn reports_finished_streams() {
+        let (mut stream, close_rx) = closed(ResponseStream::new(
+            "test",
+            tokio::stream::iter(vec![Ok(1), Ok(2)]),
+        ));
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 2);
+        assert!(stream.next().await.is_none());
+        assert_eq!(close_rx.await, Ok(CloseReason::Finished));
+    }
+
+    #[tokio::test]
+    async fn ends_after_an_error() {
+        let (mut stream, close_rx) = closed(ResponseStream::new(
+            "test",
+            tokio::stream::iter(vec![Err(Status::internal("failed")), Ok(1)]),
+        ));
+
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+        assert_eq!(close_rx.await, Ok(CloseReason::Error));
+    }
+
+    #[tokio::test]
+    async fn calls_all_callbacks_when_dropped() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut stream = ResponseStream::new("test", tokio::stream::pending::<Result<u32, _>>());
+
+        for _ in 0..2 {
+            let calls = calls.clone();
+            stream = stream.on_close(move |reason| {
+                assert_eq!(reason, CloseReason::Cancelled);
+                calls.fetch_add(1, Ordering::SeqCst);
+            });
         }
+
+        drop(stream);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn ends_idle_streams() {
+        let (mut stream, close_rx) = closed(
+            ResponseStream::new("test", tokio::stream::pending::<Result<u32, _>>())
+                .idle_timeout(Duration::from_millis(10)),
+        );
+
+        let status = stream.next().await.unwrap().err().unwrap();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        assert!(stream.next().await.is_none());
+        assert_eq!(close_rx.await, Ok(CloseReason::IdleTimeout));
+    }
+
+    #[tokio::test]
+    async fn hooks_see_every_item() {
+        let items = Arc::new(AtomicUsize::new(0));
+        let hook_items = items.clone();
+        let stream = ResponseStream::new("test", tokio::stream::iter(vec![Ok(1), Ok(2)])).on_item(
+            move |_| {
+                hook_items.fetch_add(1, Ordering::SeqCst);
+            },
+        );
+
+        assert_eq!(stream.collect::<Vec<_>>().await.len(), 2);
+        assert_eq!(items.load(Ordering::SeqCst), 2);
     }
 }
@@ -0,0 +1,59 @@
+use bytes::Bytes;
+use prost::Message;
+use proto::google::rpc;
+use tonic::{Code, Status};
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+
+/// Packs a message into a `google.protobuf.Any`, `type_name` being the fully
+/// qualified protobuf name of the message (e.g. `google.rpc.BadRequest`).
+pub fn to_any<M: Message>(type_name: &str, message: &M) -> prost_types::Any {
+    let mut value = Vec::with_capacity(message.encoded_len());
+    message
+        .encode(&mut value)
+        .expect("Vec<u8> provides sufficient capacity");
+
+    prost_types::Any {
+        type_url: format!("{}{}", TYPE_URL_PREFIX, type_name),
+        value,
+    }
+}
+
+/// Creates a status carrying a `google.rpc.Status` with the given details in the
+/// `grpc-status-details-bin` trailer.
+pub fn status_with_details(
+    code: Code,
+    message: impl Into<String>,
+    details: Vec<prost_types::Any>,
+) -> Status {
+    let message = message.into();
+
+    let status = rpc::Status {
+        code: code as i32,
+        message: message.clone(),
+        details,
+    };
+
+    let mut encoded = Vec::with_capacity(status.encoded_len());
+    status
+        .encode(&mut encoded)
+        .expect("Vec<u8> provides sufficient capacity");
+
+    Status::with_details(code, message, Bytes::from(encoded))
+}
+
+/// Converts a status into a `google.rpc.Status`, keeping the details of statuses created by
+/// `status_with_details`.
+pub fn to_rpc_status(status: &Status) -> rpc::Status {
+    if !status.details().is_empty() {
+        if let Ok(rpc_status) = rpc::Status::decode(status.details()) {
+            return rpc_status;
+        }
+    }
+
+    rpc::Status {
+        code: status.code() as i32,
+        message: String::from(status.message()),
+        details: vec![],
+    }
+}
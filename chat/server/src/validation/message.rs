@@ -0,0 +1,195 @@
+use super::search::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use super::ValidationError;
+use proto::chat;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const NANOS_PER_SECOND: i32 = 1_000_000_000;
+
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 128;
+
+/// Limits applied to the content of messages sent by users.
+#[derive(Clone, Copy, Debug)]
+pub struct MessageLimits {
+    /// Maximum size of a message in bytes (UTF-8 encoded).
+    pub max_content_length: usize,
+    /// How far `time_sent` may lie in the future, to account for clock differences
+    /// between client and server.
+    pub max_clock_skew: Duration,
+}
+
+pub fn validate_message_content(
+    field: &str,
+    message: &chat::MessageContent,
+    limits: &MessageLimits,
+) -> Result<(), ValidationError> {
+    let mut error = ValidationError::new();
+
+    let content_field = format!("{}.content", field);
+    if message.content.trim().is_empty() {
+        error.add(&content_field, "must not be empty");
+    } else if message.content.len() > limits.max_content_length {
+        error.add(
+            &content_field,
+            format!(
+                "must not be larger than {} bytes, got {}",
+                limits.max_content_length,
+                message.content.len()
+            ),
+        );
+    }
+
+    if let Some(time_sent) = &message.time_sent {
+        let time_sent_field = format!("{}.time_sent", field);
+
+        if time_sent.nanos < 0 || time_sent.nanos >= NANOS_PER_SECOND {
+            error.add(&time_sent_field, "nanos must be in the range [0, 1e9)");
+        } else if time_sent.seconds < 0 {
+            error.add(&time_sent_field, "must not lie before the unix epoch");
+        } else {
+            let time_sent = UNIX_EPOCH.checked_add(Duration::new(
+                time_sent.seconds as u64,
+                time_sent.nanos as u32,
+            ));
+            let latest = SystemTime::now().checked_add(limits.max_clock_skew);
+
+            match (time_sent, latest) {
+                (Some(time_sent), Some(latest)) if time_sent <= latest => {}
+                // a skew beyond the range of the clock allows every time
+                (Some(_), None) => {}
+                _ => error.add(&time_sent_field, "must not lie in the future"),
+            }
+        }
+    }
+
+    error.into_result(())
+}
+
+/// Validates the optional idempotency key of a send request.
+pub fn validate_idempotency_key(field: &str, key: &str) -> Result<(), ValidationError> {
+    let mut error = ValidationError::new();
+
+    if key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        error.add(
+            field,
+            format!(
+                "must not be larger than {} bytes, got {}",
+                MAX_IDEMPOTENCY_KEY_LENGTH,
+                key.len()
+            ),
+        );
+    }
+
+    error.into_result(())
+}
+
+/// Validates a request for the messages of a user and returns the number of messages to return.
+/// Page sizes above the maximum are lowered to it.
+pub fn validate_list_messages_request(
+    request: &chat::ListMessagesRequest,
+) -> Result<usize, ValidationError> {
+    let mut error = ValidationError::new();
+
+    if request.from_user_id.is_empty() {
+        error.add("from_user_id", "must not be empty");
+    }
+
+    let limit = match request.page_size as usize {
+        0 => DEFAULT_PAGE_SIZE,
+        page_size => page_size.min(MAX_PAGE_SIZE),
+    };
+
+    error.into_result(limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: MessageLimits = MessageLimits {
+        max_content_length: 16,
+        max_clock_skew: Duration::from_secs(60),
+    };
+
+    fn message(content: &str, seconds_from_now: i64) -> chat::MessageContent {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        chat::MessageContent {
+            content: String::from(content),
+            time_sent: Some(prost_types::Timestamp {
+                seconds: now.as_secs() as i64 + seconds_from_now,
+                nanos: 0,
+            }),
+        }
+    }
+
+    fn fields(message: &chat::MessageContent, limits: &MessageLimits) -> Vec<String> {
+        match validate_message_content("message", message, limits) {
+            Ok(()) => vec![],
+            Err(error) => error
+                .violations
+                .into_iter()
+                .map(|violation| violation.field)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn content_must_fit_the_limit() {
+        assert!(fields(&message("hello", 0), &LIMITS).is_empty());
+        assert!(fields(&message(&"a".repeat(16), 0), &LIMITS).is_empty());
+
+        for content in &["", "  \n", &"a".repeat(17), "ääääääääää"] {
+            assert_eq!(
+                fields(&message(content, 0), &LIMITS),
+                vec!["message.content"],
+                "{:?}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn time_sent_must_not_lie_in_the_future() {
+        assert!(fields(&message("hello", -3600), &LIMITS).is_empty());
+        assert!(fields(&message("hello", 30), &LIMITS).is_empty());
+        assert_eq!(
+            fields(&message("hello", 120), &LIMITS),
+            vec!["message.time_sent"]
+        );
+
+        let mut invalid = message("hello", 0);
+        invalid.time_sent = Some(prost_types::Timestamp {
+            seconds: 0,
+            nanos: -1,
+        });
+        assert_eq!(fields(&invalid, &LIMITS), vec!["message.time_sent"]);
+
+        invalid.time_sent = Some(prost_types::Timestamp {
+            seconds: -1,
+            nanos: 0,
+        });
+        assert_eq!(fields(&invalid, &LIMITS), vec!["message.time_sent"]);
+
+        // times and skews beyond the range of the clock don't overflow
+        invalid.time_sent = Some(prost_types::Timestamp {
+            seconds: i64::MAX,
+            nanos: 0,
+        });
+        assert_eq!(fields(&invalid, &LIMITS), vec!["message.time_sent"]);
+
+        let unlimited = MessageLimits {
+            max_content_length: 16,
+            max_clock_skew: Duration::MAX,
+        };
+        assert!(fields(&message("hello", 3600), &unlimited).is_empty());
+    }
+
+    #[test]
+    fn idempotency_keys_are_limited() {
+        assert!(validate_idempotency_key("key", "").is_ok());
+        assert!(validate_idempotency_key("key", &"k".repeat(MAX_IDEMPOTENCY_KEY_LENGTH)).is_ok());
+        assert!(
+            validate_idempotency_key("key", &"k".repeat(MAX_IDEMPOTENCY_KEY_LENGTH + 1)).is_err()
+        );
+    }
+}
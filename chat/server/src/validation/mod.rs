@@ -0,0 +1,69 @@
+mod message;
+mod search;
+mod user_name;
+mod webhook_url;
+
+use crate::util;
+use proto::google::rpc;
+use tonic::{Code, Status};
+
+pub use message::{
+    validate_idempotency_key, validate_list_messages_request, validate_message_content,
+    MessageLimits,
+};
+pub use search::validate_search_request;
+pub use user_name::validate_user_name;
+pub use webhook_url::validate_webhook_url;
+
+/// Collects all problems found in a request, each one attributed to the path of
+/// the offending field (e.g. `notification.message.content`).
+#[derive(Debug, Default)]
+pub struct ValidationError {
+    violations: Vec<rpc::bad_request::FieldViolation>,
+}
+
+impl ValidationError {
+    pub fn new() -> ValidationError {
+        ValidationError::default()
+    }
+
+    pub fn add(&mut self, field: &str, description: impl Into<String>) {
+        self.violations.push(rpc::bad_request::FieldViolation {
+            field: String::from(field),
+            description: description.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Returns `Ok(value)` if no violations were recorded, otherwise the error itself.
+    pub fn into_result<T>(self, value: T) -> Result<T, ValidationError> {
+        match self.is_empty() {
+            true => Ok(value),
+            false => Err(self),
+        }
+    }
+}
+
+impl From<ValidationError> for Status {
+    fn from(error: ValidationError) -> Status {
+        let message = error
+            .violations
+            .iter()
+            .map(|violation| format!("{}: {}", violation.field, violation.description))
+            .collect::<Vec<String>>()
+            .join("; ");
+
+        let bad_request = rpc::BadRequest {
+            field_violations: error.violations,
+        };
+
+        util::status_with_details(
+            Code::InvalidArgument,
+            message,
+            vec![util::to_any("google.rpc.BadRequest", &bad_request)],
+        )
+    }
+}
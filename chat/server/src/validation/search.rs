@@ -0,0 +1,203 @@
+use super::ValidationError;
+use crate::search::{self, SearchQuery};
+use proto::chat;
+use std::convert::TryFrom;
+use std::time::SystemTime;
+
+pub const MAX_QUERY_LENGTH: usize = 1024;
+pub const MAX_QUERY_TERMS: usize = 16;
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Validates a search request and parses it into a query. The query may only be empty if the
+/// search is limited to a conversation, whose history is listed then. Page sizes above the
+/// maximum are lowered to it.
+pub fn validate_search_request(
+    request: &chat::SearchRequest,
+) -> Result<SearchQuery, ValidationError> {
+    let mut error = ValidationError::new();
+
+    let terms = search::parse_query(&request.query);
+    if request.query.len() > MAX_QUERY_LENGTH {
+        error.add(
+            "query",
+            format!(
+                "must not be larger than {} bytes, got {}",
+                MAX_QUERY_LENGTH,
+                request.query.len()
+            ),
+        );
+    } else if terms.is_empty() && request.conversation_id.is_empty() {
+        error.add(
+            "query",
+            "must contain at least one word unless conversation_id is set",
+        );
+    } else if terms.len() > MAX_QUERY_TERMS {
+        error.add(
+            "query",
+            format!(
+                "must not contain more than {} words, got {}",
+                MAX_QUERY_TERMS,
+                terms.len()
+            ),
+        );
+    }
+
+    let sent_after = timestamp(&mut error, "sent_after", &request.sent_after);
+    let sent_before = timestamp(&mut error, "sent_before", &request.sent_before);
+
+    if let (Some(sent_after), Some(sent_before)) = (sent_after, sent_before) {
+        if sent_after > sent_before {
+            error.add("sent_before", "must not lie before sent_after");
+        }
+    }
+
+    let offset = match request.page_token.as_str() {
+        "" => 0,
+        page_token => match page_token.parse() {
+            Ok(offset) => offset,
+            Err(_) => {
+                error.add("page_token", "is invalid");
+                0
+            }
+        },
+    };
+
+    let limit = match request.page_size as usize {
+        0 => DEFAULT_PAGE_SIZE,
+        page_size => page_size.min(MAX_PAGE_SIZE),
+    };
+
+    error.into_result(SearchQuery {
+        terms,
+        from_user_id: non_empty(&request.from_user_id),
+        conversation_id: non_empty(&request.conversation_id),
+        sent_after,
+        sent_before,
+        offset,
+        limit,
+    })
+}
+
+fn timestamp(
+    error: &mut ValidationError,
+    field: &str,
+    timestamp: &Option<prost_types::Timestamp>,
+) -> Option<SystemTime> {
+    let timestamp = timestamp.clone()?;
+
+    match SystemTime::try_from(timestamp) {
+        Ok(time) => Some(time),
+        Err(_) => {
+            error.add(field, "is not a valid timestamp");
+            None
+        }
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    match value.is_empty() {
+        true => None,
+        false => Some(String::from(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(query: &str) -> chat::SearchRequest {
+        chat::SearchRequest {
+            query: String::from(query),
+            ..chat::SearchRequest::default()
+        }
+    }
+
+    fn fields(request: &chat::SearchRequest) -> Vec<String> {
+        match validate_search_request(request) {
+            Ok(_) => vec![],
+            Err(error) => error
+                .violations
+                .into_iter()
+                .map(|violation| violation.field)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn valid_requests_are_parsed_into_queries() {
+        let mut request = request("hello wor*");
+        request.from_user_id = String::from("alice-id");
+        request.page_token = String::from("40");
+        request.sent_after = Some(prost_types::Timestamp {
+            seconds: 10,
+            nanos: 0,
+        });
+
+        let query = validate_search_request(&request).unwrap();
+        assert_eq!(query.terms.len(), 2);
+        assert!(!query.terms[0].is_prefix);
+        assert!(query.terms[1].is_prefix);
+        assert_eq!(query.from_user_id.as_deref(), Some("alice-id"));
+        assert_eq!(query.conversation_id, None);
+        assert!(query.sent_after.is_some());
+        assert_eq!(query.offset, 40);
+        assert_eq!(query.limit, DEFAULT_PAGE_SIZE);
+    }
+
+    #[test]
+    fn page_sizes_are_limited() {
+        let mut request = request("hello");
+        request.page_size = 1000;
+        assert_eq!(
+            validate_search_request(&request).unwrap().limit,
+            MAX_PAGE_SIZE
+        );
+
+        request.page_size = 5;
+        assert_eq!(validate_search_request(&request).unwrap().limit, 5);
+    }
+
+    #[test]
+    fn conversations_are_listed_without_a_query() {
+        let mut request = request("");
+        request.conversation_id = String::from("alice-id:bob-id");
+
+        let query = validate_search_request(&request).unwrap();
+        assert!(query.terms.is_empty());
+        assert_eq!(query.conversation_id.as_deref(), Some("alice-id:bob-id"));
+    }
+
+    #[test]
+    fn invalid_requests_name_the_fields() {
+        assert_eq!(fields(&request("")), vec!["query"]);
+        assert_eq!(fields(&request("...")), vec!["query"]);
+        assert_eq!(
+            fields(&request(&"a".repeat(MAX_QUERY_LENGTH + 1))),
+            vec!["query"]
+        );
+        assert_eq!(
+            fields(&request(&vec!["word"; MAX_QUERY_TERMS + 1].join(" "))),
+            vec!["query"]
+        );
+
+        let mut invalid = request("hello");
+        invalid.page_token = String::from("next");
+        invalid.sent_after = Some(prost_types::Timestamp {
+            seconds: 0,
+            nanos: -1,
+        });
+        assert_eq!(fields(&invalid), vec!["sent_after", "page_token"]);
+
+        let mut reversed = request("hello");
+        reversed.sent_after = Some(prost_types::Timestamp {
+            seconds: 20,
+            nanos: 0,
+        });
+        reversed.sent_before = Some(prost_types::Timestamp {
+            seconds: 10,
+            nanos: 0,
+        });
+        assert_eq!(fields(&reversed), vec!["sent_before"]);
+    }
+}
@@ -0,0 +1,89 @@
+use super::ValidationError;
+use unicode_normalization::UnicodeNormalization;
+
+pub const MAX_USER_NAME_LENGTH: usize = 32;
+
+/// Names which could be mistaken for messages originating from the server itself.
+const RESERVED_USER_NAMES: &[&str] = &["admin", "administrator", "root", "server", "system"];
+
+/// Characters allowed in user names in addition to alphanumeric ones.
+const ALLOWED_PUNCTUATION: &[char] = &[' ', '-', '_', '.'];
+
+/// Validates a user name and returns it in Unicode normalization form C, so that names
+/// which differ only in how their characters are encoded (e.g. an accent composed with its
+/// letter or following it) compare equal. Look-alike characters from different scripts stay
+/// distinct.
+pub fn validate_user_name(field: &str, name: &str) -> Result<String, ValidationError> {
+    let name: String = name.nfc().collect();
+    let mut error = ValidationError::new();
+
+    if name.is_empty() {
+        error.add(field, "must not be empty");
+        return Err(error);
+    }
+
+    let length = name.chars().count();
+    if length > MAX_USER_NAME_LENGTH {
+        error.add(
+            field,
+            format!(
+                "must not be longer than {} characters, got {}",
+                MAX_USER_NAME_LENGTH, length
+            ),
+        );
+    }
+
+    if let Some(invalid) = name
+        .chars()
+        .find(|c| !c.is_alphanumeric() && !ALLOWED_PUNCTUATION.contains(c))
+    {
+        error.add(field, format!("contains invalid character {:?}", invalid));
+    }
+
+    if name.starts_with(char::is_whitespace) || name.ends_with(char::is_whitespace) {
+        error.add(field, "must not start or end with whitespace");
+    }
+
+    let lowercase = name.to_lowercase();
+    if RESERVED_USER_NAMES.contains(&lowercase.as_str()) {
+        error.add(field, format!("'{}' is a reserved name", name));
+    }
+
+    error.into_result(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_valid(name: &str) -> bool {
+        validate_user_name("name", name).is_ok()
+    }
+
+    #[test]
+    fn names_are_normalized() {
+        let composed = validate_user_name("name", "Ren\u{e9}").unwrap();
+        let decomposed = validate_user_name("name", "Rene\u{301}").unwrap();
+        assert_eq!(composed, decomposed);
+
+        // look-alikes from other scripts are different names
+        let cyrillic = validate_user_name("name", "\u{430}lice").unwrap();
+        assert_ne!(cyrillic, "alice");
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        assert!(is_valid("alice"));
+        assert!(is_valid("Alice Smith-Jones_2.0"));
+        assert!(is_valid(&"ä".repeat(MAX_USER_NAME_LENGTH)));
+
+        assert!(!is_valid(""));
+        assert!(!is_valid(&"a".repeat(MAX_USER_NAME_LENGTH + 1)));
+        assert!(!is_valid("alice!"));
+        assert!(!is_valid("alice\n"));
+        assert!(!is_valid(" alice"));
+        assert!(!is_valid("alice "));
+        assert!(!is_valid("Admin"));
+        assert!(!is_valid("SYSTEM"));
+    }
+}
@@ -0,0 +1,39 @@
+use super::ValidationError;
+use hyper::Uri;
+
+pub const MAX_WEBHOOK_URL_LENGTH: usize = 2048;
+
+/// Validates the URL of a webhook and parses it. Only plain http is supported, bots reachable
+/// over https need a proxy terminating TLS.
+pub fn validate_webhook_url(field: &str, url: &str) -> Result<Uri, ValidationError> {
+    let mut error = ValidationError::new();
+
+    if url.len() > MAX_WEBHOOK_URL_LENGTH {
+        error.add(
+            field,
+            format!(
+                "must not be longer than {} bytes, got {}",
+                MAX_WEBHOOK_URL_LENGTH,
+                url.len()
+            ),
+        );
+        return Err(error);
+    }
+
+    let uri: Uri = match url.parse() {
+        Ok(uri) => uri,
+        Err(err) => {
+            error.add(field, format!("is not a valid URL: {}", err));
+            return Err(error);
+        }
+    };
+
+    if uri.scheme_str() != Some("http") {
+        error.add(field, "must be an http URL");
+    }
+    if uri.host().is_none() {
+        error.add(field, "must contain a host");
+    }
+
+    error.into_result(uri)
+}
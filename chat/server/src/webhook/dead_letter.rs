@@ -0,0 +1,96 @@
+use proto::chat;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// A notification which could not be delivered to a bot.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter<'a> {
+    pub bot_id: &'a str,
+    pub url: String,
+    pub delivery_id: &'a str,
+    pub attempts: u32,
+    pub error: String,
+    pub notification: &'a chat::IncomingNotification,
+}
+
+/// Appends undeliverable notifications to a file as JSON lines, or only logs them if no file is
+/// configured. Entries carry the time they were recorded in seconds since the Unix epoch.
+pub struct DeadLetterLog {
+    path: Option<PathBuf>,
+    // serializes the appends of the bots' delivery tasks
+    lock: Mutex<()>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry<'a> {
+    recorded_at: u64,
+    #[serde(flatten)]
+    dead_letter: &'a DeadLetter<'a>,
+}
+
+impl DeadLetterLog {
+    pub fn new(path: Option<PathBuf>) -> DeadLetterLog {
+        DeadLetterLog {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn record(&self, dead_letter: &DeadLetter<'_>) {
+        let recorded_at = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(time) => time.as_secs(),
+            Err(_) => 0,
+        };
+
+        let mut line = match serde_json::to_string(&Entry {
+            recorded_at,
+            dead_letter,
+        }) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::error!("could not serialize dead letter: {}", err);
+                return;
+            }
+        };
+
+        tracing::warn!(
+            bot_id = %dead_letter.bot_id,
+            delivery_id = %dead_letter.delivery_id,
+            attempts = dead_letter.attempts,
+            error = %dead_letter.error,
+            "notification dead-lettered"
+        );
+
+        let path = match &self.path {
+            Some(path) => path,
+            None => {
+                tracing::info!(dead_letter = %line, "no dead letter file configured");
+                return;
+            }
+        };
+
+        line.push('\n');
+
+        let _guard = self.lock.lock().await;
+        let result = async {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await
+        }
+        .await;
+
+        if let Err(err) = result {
+            tracing::error!(path = %path.display(), "could not write dead letter: {}", err);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Delivers the notifications of bot users to webhooks, so bots react to messages without holding
+//! a stream open. Every notification is posted as the JSON of its `IncomingNotification`, signed
+//! with the bot's secret, see `signature`. The notifications of a bot are delivered one at a time
+//! in order. Failed deliveries are retried with exponential backoff and are written to the
+//! dead-letter log once all attempts have failed.
+
+mod dead_letter;
+mod signature;
+#[cfg(test)]
+mod tests;
+
+use crate::backplane::Node;
+use crate::config::WebhookConfig;
+use crate::error::ChatError;
+use crate::metrics;
+use crate::shutdown::{DrainGuard, Shutdown};
+use crate::user_list::{NotificationReceiver, UserData};
+use crate::UserList;
+use dead_letter::{DeadLetter, DeadLetterLog};
+use hyper::client::HttpConnector;
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use proto::chat::{self, incoming_notification};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tonic::Status;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// Header carrying the signature of a delivery, see `signature::sign`.
+pub const SIGNATURE_HEADER: &str = "x-chat-signature";
+/// Header carrying the time a delivery was signed at, in seconds since the Unix epoch.
+pub const TIMESTAMP_HEADER: &str = "x-chat-timestamp";
+/// Header identifying a notification, all attempts to deliver it carry the same id so bots can
+/// recognize retries of deliveries they have already handled.
+pub const DELIVERY_HEADER: &str = "x-chat-delivery";
+/// Header carrying the number of the attempt, starting at 1.
+pub const ATTEMPT_HEADER: &str = "x-chat-attempt";
+
+/// A registered bot, see `Webhooks::register`.
+pub struct Registration {
+    pub user: UserData,
+    pub signing_secret: String,
+}
+
+/// Registers bots and starts delivering their notifications.
+pub struct Webhooks {
+    config: WebhookConfig,
+    users: Arc<Mutex<UserList>>,
+    node: Arc<Node>,
+    shutdown: Arc<Shutdown>,
+    client: Client<HttpConnector>,
+    dead_letters: Arc<DeadLetterLog>,
+}
+
+impl Webhooks {
+    pub fn new(
+        config: WebhookConfig,
+        users: Arc<Mutex<UserList>>,
+        node: Arc<Node>,
+        shutdown: Arc<Shutdown>,
+    ) -> Webhooks {
+        let dead_letters = Arc::new(DeadLetterLog::new(config.dead_letter_file.clone()));
+
+        Webhooks {
+            config,
+            users,
+            node,
+            shutdown,
+            client: Client::new(),
+            dead_letters,
+        }
+    }
+
+    /// Creates a bot user, who is online right away, and posts its notifications to the URL until
+    /// it is disconnected or the server shuts down.
+    pub async fn register(&self, name: &str, url: Uri) -> Result<Registration, Status> {
+        let drain_guard = self.shutdown.drain_guard()?;
+        let signing_secret = signature::generate_secret()?;
+
+        let (user, receiver) = {
+            let mut users = match self.users.lock() {
+                Ok(guard) => guard,
+                Err(_) => return Err(ChatError::LockPoisoned.into()),
+            };
+
+            let (user, receiver) = users.create_bot(name)?;
+            users.set_user_online(&user.id(), true)?;
+
+            (user, receiver)
+        };
+        self.node.publish_presence(user.user(), true).await;
+
+        let webhook = Webhook {
+            user: user.clone(),
+            url,
+            signing_secret: signing_secret.clone(),
+            config: self.config.clone(),
+            client: self.client.clone(),
+            dead_letters: self.dead_letters.clone(),
+        };
+
+        let span = tracing::info_span!("webhook", bot_id = %user.id(), url = %webhook.url);
+        tokio::spawn(
+            webhook
+                .run(receiver, self.users.clone(), self.node.clone(), drain_guard)
+                .instrument(span),
+        );
+
+        Ok(Registration {
+            user,
+            signing_secret,
+        })
+    }
+}
+
+/// Why an attempt to deliver a notification failed.
+#[derive(Debug)]
+enum DeliveryError {
+    /// The bot could not be reached.
+    Request(String),
+    /// The bot didn't answer in time.
+    Timeout,
+    /// The bot answered with a status other than success.
+    Status(StatusCode),
+}
+
+impl DeliveryError {
+    /// Returns whether a later attempt may succeed, bots reject requests they can't handle with a
+    /// client error other than too many requests.
+    fn is_transient(&self) -> bool {
+        match self {
+            DeliveryError::Request(_) | DeliveryError::Timeout => true,
+            DeliveryError::Status(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeliveryError::Request(err) => write!(f, "request failed: {}", err),
+            DeliveryError::Timeout => write!(f, "request timed out"),
+            DeliveryError::Status(status) => write!(f, "bot answered with {}", status),
+        }
+    }
+}
+
+/// Delivers the notifications of a single bot.
+struct Webhook {
+    user: UserData,
+    url: Uri,
+    signing_secret: String,
+    config: WebhookConfig,
+    client: Client<HttpConnector>,
+    dead_letters: Arc<DeadLetterLog>,
+}
+
+impl Webhook {
+    /// Delivers notifications until the bot is disconnected or the server shuts down, delaying
+    /// the shutdown until the server shutdown notification has been delivered.
+    async fn run(
+        self,
+        mut receiver: NotificationReceiver,
+        users: Arc<Mutex<UserList>>,
+        node: Arc<Node>,
+        drain_guard: DrainGuard,
+    ) {
+        tracing::info!("webhook registered");
+
+        let is_disconnected = loop {
+            let notification = tokio::select! {
+                notification = receiver.recv() => notification,
+                _ = self.user.disconnected() => {
+                    // deliver what has been enqueued up to the disconnected notification
+                    while let Ok(notification) = receiver.try_recv() {
+                        self.deliver(&notification).await;
+                    }
+                    break true;
+                }
+            };
+
+            let notification = match notification {
+                Some(notification) => notification,
+                // the bot has been removed
+                None => break false,
+            };
+
+            self.deliver(&notification).await;
+
+            match notification.types {
+                Some(incoming_notification::Types::Disconnected(_)) => break true,
+                Some(incoming_notification::Types::ServerShutdown(_)) => break false,
+                _ => {}
+            }
+        };
+
+        drop(drain_guard);
+
+        if is_disconnected {
+            let result = match users.lock() {
+                Ok(mut users) => users.remove_user(&self.user.id()),
+                Err(_) => Err(ChatError::LockPoisoned),
+            };
+
+            match result {
+                Ok(()) => node.publish_presence(self.user.user(), false).await,
+                Err(err) => tracing::error!("error removing bot: {}", err),
+            }
+        }
+
+        tracing::info!("webhook removed");
+    }
+
+    /// Posts the notification until the bot accepts it or all attempts have failed.
+    async fn deliver(&self, notification: &chat::IncomingNotification) {
+        let body = match serde_json::to_vec(notification) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::error!("could not serialize notification: {}", err);
+                return;
+            }
+        };
+
+        let delivery_id = Uuid::new_v4().to_hyphenated().to_string();
+
+        let mut attempt = 1;
+        let error = loop {
+            let error = match self.post(&body, &delivery_id, attempt).await {
+                Ok(()) => {
+                    metrics::WEBHOOK_DELIVERIES
+                        .with_label_values(&["delivered"])
+                        .inc();
+                    tracing::debug!(%delivery_id, attempt, "notification delivered");
+                    return;
+                }
+                Err(error) => error,
+            };
+
+            if !error.is_transient() || attempt >= self.config.max_attempts {
+                break error;
+            }
+
+            metrics::WEBHOOK_DELIVERIES
+                .with_label_values(&["retried"])
+                .inc();
+            tracing::debug!(%delivery_id, attempt, %error, "delivery failed, retrying");
+
+            tokio::time::delay_for(self.config.backoff(attempt)).await;
+            attempt += 1;
+        };
+
+        metrics::WEBHOOK_DELIVERIES
+            .with_label_values(&["dead_lettered"])
+            .inc();
+
+        self.dead_letters
+            .record(&DeadLetter {
+                bot_id: &self.user.id(),
+                url: self.url.to_string(),
+                delivery_id: &delivery_id,
+                attempts: attempt,
+                error: error.to_string(),
+                notification,
+            })
+            .await;
+    }
+
+    async fn post(
+        &self,
+        body: &[u8],
+        delivery_id: &str,
+        attempt: u32,
+    ) -> Result<(), DeliveryError> {
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(time) => time.as_secs(),
+            Err(_) => 0,
+        };
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )
+            .header(
+                SIGNATURE_HEADER,
+                signature::sign(&self.signing_secret, timestamp, body),
+            )
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(DELIVERY_HEADER, delivery_id)
+            .header(ATTEMPT_HEADER, attempt)
+            .body(Body::from(body.to_vec()));
+
+        let request = match request {
+            Ok(request) => request,
+            Err(err) => return Err(DeliveryError::Request(err.to_string())),
+        };
+
+        match tokio::time::timeout(self.config.timeout(), self.client.request(request)).await {
+            Ok(Ok(response)) if response.status().is_success() => Ok(()),
+            Ok(Ok(response)) => Err(DeliveryError::Status(response.status())),
+            Ok(Err(err)) => Err(DeliveryError::Request(err.to_string())),
+            Err(_) => Err(DeliveryError::Timeout),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! Signs deliveries so bots can check that they were sent by this server. The signature is the
+//! hex encoded HMAC-SHA256 of `<timestamp>.<body>` keyed with the bot's signing secret, bots should
+//! also reject old timestamps to prevent replays.
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use tonic::Status;
+
+/// Number of random bytes of a signing secret.
+const SECRET_LENGTH: usize = 32;
+
+/// Returns the value of the signature header, e.g. `sha256=5bdcc1...`.
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+
+    let mut context = hmac::Context::with_key(&key);
+    context.update(timestamp.to_string().as_bytes());
+    context.update(b".");
+    context.update(body);
+
+    format!("sha256={}", hex(context.sign().as_ref()))
+}
+
+/// Returns a new random signing secret.
+pub fn generate_secret() -> Result<String, Status> {
+    let mut secret = [0; SECRET_LENGTH];
+
+    match SystemRandom::new().fill(&mut secret) {
+        Ok(()) => Ok(hex(&secret)),
+        Err(_) => Err(Status::internal("could not generate a signing secret")),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        assert_eq!(
+            sign("Jefe", 1234, b"what do ya want for nothing?"),
+            "sha256=154f07c6e9ad786e2586347b51e84859e53243b0f97c8e62de76b4eccb597f00"
+        );
+    }
+
+    #[test]
+    fn secrets_are_random() {
+        let secret = generate_secret().unwrap();
+
+        assert_eq!(secret.len(), 2 * SECRET_LENGTH);
+        assert_ne!(secret, generate_secret().unwrap());
+    }
+}
@@ -0,0 +1,256 @@
+use super::{signature, ATTEMPT_HEADER, DELIVERY_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::test_support::{TestServer, WEBHOOK_ATTEMPTS};
+use hyper::header::HeaderMap;
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server, StatusCode};
+use proto::chat;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tonic::Code;
+
+/// A request posted to the stand-in.
+struct Delivery {
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl Delivery {
+    fn header(&self, name: &str) -> &str {
+        self.headers[name].to_str().unwrap()
+    }
+
+    fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+/// Stands in for the HTTP endpoint of a bot. It answers with the given statuses in order, then
+/// with 200.
+struct StandIn {
+    addr: SocketAddr,
+    deliveries: mpsc::UnboundedReceiver<Delivery>,
+}
+
+impl StandIn {
+    fn start(statuses: Vec<u16>) -> StandIn {
+        let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+        let (deliveries_tx, deliveries) = mpsc::unbounded_channel();
+
+        let make_service = make_service_fn(move |_| {
+            let statuses = statuses.clone();
+            let deliveries_tx = deliveries_tx.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let statuses = statuses.clone();
+                    let deliveries_tx = deliveries_tx.clone();
+
+                    async move {
+                        let (parts, body) = hyper::Request::into_parts(request);
+                        let body = hyper::body::to_bytes(body).await.unwrap();
+                        let _ = deliveries_tx.send(Delivery {
+                            headers: parts.headers,
+                            body: body.to_vec(),
+                        });
+
+                        let status = statuses.lock().unwrap().next().unwrap_or(200);
+                        let mut response = Response::new(Body::empty());
+                        *response.status_mut() = StatusCode::from_u16(status).unwrap();
+
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+
+        let incoming = AddrIncoming::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = incoming.local_addr();
+        tokio::spawn(Server::builder(incoming).serve(make_service));
+
+        StandIn { addr, deliveries }
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}/hooks/chat", self.addr)
+    }
+
+    async fn next(&mut self) -> Delivery {
+        tokio::time::timeout(Duration::from_secs(5), self.deliveries.recv())
+            .await
+            .expect("no delivery received")
+            .unwrap()
+    }
+}
+
+async fn register_bot(server: &TestServer, url: &str) -> chat::RegisterWebhookResponse {
+    server
+        .admin_client()
+        .register_webhook(chat::RegisterWebhookRequest {
+            name: String::from("echo-bot"),
+            url: String::from(url),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+}
+
+#[tokio::test]
+async fn bots_receive_signed_notifications_and_reply() {
+    let server = TestServer::start().await;
+    let mut stand_in = StandIn::start(vec![]);
+
+    let bot = register_bot(&server, &stand_in.url()).await;
+    let bot_user = bot.user.clone().unwrap();
+
+    let mut alice = server.login("alice").await;
+    alice.expect_presence(&bot_user, true).await;
+
+    let delivery = stand_in.next().await;
+    assert_eq!(delivery.json()["online"]["isOnline"], true);
+    assert_eq!(delivery.json()["from"]["name"], "alice");
+
+    // bots check the signature with their secret
+    let timestamp = delivery.header(TIMESTAMP_HEADER).parse().unwrap();
+    assert_eq!(
+        delivery.header(SIGNATURE_HEADER),
+        signature::sign(&bot.signing_secret, timestamp, &delivery.body)
+    );
+
+    let message_id = alice.send_message(&bot_user, "hello bot").await.unwrap();
+    let delivery = stand_in.next().await;
+    assert_eq!(delivery.json()["message"]["messageId"]["id"], message_id.id);
+    assert_eq!(
+        delivery.json()["message"]["messageContent"]["content"],
+        "hello bot"
+    );
+
+    // and reply like any other user
+    let mut bot_client = server.chat_client(&bot_user.id, &bot.token);
+    bot_client
+        .send(chat::SendRequest {
+            notification: Some(chat::OutgoingNotification {
+                to: Some(alice.user.clone()),
+                types: Some(chat::outgoing_notification::Types::Message(
+                    chat::MessageContent {
+                        time_sent: None,
+                        content: String::from("hello alice"),
+                    },
+                )),
+            }),
+            idempotency_key: String::new(),
+        })
+        .await
+        .unwrap();
+    alice.expect_message(&bot_user, "hello alice").await;
+}
+
+#[tokio::test]
+async fn only_http_webhooks_can_be_registered() {
+    let server = TestServer::start().await;
+
+    let status = server
+        .admin_client()
+        .register_webhook(chat::RegisterWebhookRequest {
+            name: String::from("echo-bot"),
+            url: String::from("https://bot.example.com/hooks/chat"),
+        })
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let server = TestServer::start().await;
+    let mut stand_in = StandIn::start(vec![503, 429]);
+
+    register_bot(&server, &stand_in.url()).await;
+    let _alice = server.login("alice").await;
+
+    let first = stand_in.next().await;
+    assert_eq!(first.header(ATTEMPT_HEADER), "1");
+
+    for attempt in &["2", "3"] {
+        let retry = stand_in.next().await;
+        assert_eq!(retry.header(ATTEMPT_HEADER), *attempt);
+        assert_eq!(retry.header(DELIVERY_HEADER), first.header(DELIVERY_HEADER));
+        assert_eq!(retry.body, first.body);
+    }
+
+    assert!(server.dead_letters().await.is_empty());
+}
+
+#[tokio::test]
+async fn undeliverable_notifications_are_dead_lettered() {
+    let server = TestServer::start().await;
+
+    // the first notification is rejected, the second fails on every attempt
+    let mut statuses = vec![400];
+    statuses.extend(vec![500; WEBHOOK_ATTEMPTS as usize]);
+    let mut stand_in = StandIn::start(statuses);
+
+    let bot = register_bot(&server, &stand_in.url()).await;
+    let bot_user = bot.user.unwrap();
+
+    let mut alice = server.login("alice").await;
+    stand_in.next().await;
+    alice.send_message(&bot_user, "hello bot").await.unwrap();
+    for _ in 0..WEBHOOK_ATTEMPTS {
+        stand_in.next().await;
+    }
+
+    let mut dead_letters = vec![];
+    for _ in 0..50 {
+        dead_letters = server.dead_letters().await;
+        if dead_letters.len() == 2 {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+    }
+    assert_eq!(dead_letters.len(), 2);
+
+    assert_eq!(dead_letters[0]["botId"], bot_user.id.as_str());
+    assert_eq!(dead_letters[0]["attempts"], 1);
+    assert_eq!(dead_letters[0]["notification"]["online"]["isOnline"], true);
+
+    assert_eq!(dead_letters[1]["attempts"], WEBHOOK_ATTEMPTS);
+    assert_eq!(
+        dead_letters[1]["notification"]["message"]["messageContent"]["content"],
+        "hello bot"
+    );
+    assert!(dead_letters[1]["error"].as_str().unwrap().contains("500"));
+}
+
+#[tokio::test]
+async fn disconnected_bots_are_removed() {
+    let server = TestServer::start().await;
+    let mut stand_in = StandIn::start(vec![]);
+
+    let bot = register_bot(&server, &stand_in.url()).await;
+    let bot_user = bot.user.unwrap();
+
+    let mut alice = server.login("alice").await;
+    alice.expect_presence(&bot_user, true).await;
+    stand_in.next().await;
+
+    server
+        .admin_client()
+        .disconnect_user(chat::DisconnectUserRequest {
+            user_id: bot_user.id.clone(),
+            reason: String::from("bot retired"),
+        })
+        .await
+        .unwrap();
+
+    let delivery = stand_in.next().await;
+    assert_eq!(delivery.json()["disconnected"]["reason"], "bot retired");
+
+    alice.expect_presence(&bot_user, false).await;
+    assert_eq!(alice.online_user_names().await, vec!["alice"]);
+}
@@ -1,15 +1,35 @@
 use proto::hello;
 use hello::greeter_client::GreeterClient;
 use hello::HelloRequest;
+use server_common::uds;
+use std::path::PathBuf;
+use structopt::StructOpt;
 use tonic::{transport::Endpoint};
 
+#[derive(StructOpt)]
+#[structopt(about = "A gRPC test client")]
+struct Cli {
+    #[structopt(long, default_value = "http://[::1]:50051", help = "URI of the gRPC server")]
+    server: String,
+
+    #[structopt(long, parse(from_os_str), help = "Unix domain socket of the gRPC server, used instead of --server")]
+    unix_socket: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let endpoint = Endpoint::from_static("http://[::1]:50051");
+    let args = Cli::from_args();
+
+    let endpoint = Endpoint::from_shared(args.server)?;
 
     let channel: tonic::transport::Channel;
     loop {
-        channel = match endpoint.connect().await {
+        let result = match &args.unix_socket {
+            Some(path) => uds::connect(path).await,
+            None => endpoint.connect().await,
+        };
+
+        channel = match result {
             Ok(channel) => channel,
             Err(error) => {
                 println!("An error occurred while connection, retrying: {:?}", error);
@@ -20,7 +40,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         break;
     }
 
-    println!("Connected to {:?}", endpoint.uri());
+    match &args.unix_socket {
+        Some(path) => println!("Connected to {:?}", path),
+        None => println!("Connected to {:?}", endpoint.uri()),
+    }
 
     let mut client = GreeterClient::new(channel);
 
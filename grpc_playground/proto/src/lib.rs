@@ -5,3 +5,7 @@ pub mod hello {
 pub mod services {
     tonic::include_proto!("services");
 }
+
+/// Encoded `FileDescriptorSet` of all playground protos, including their imports.
+pub const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/playground_descriptor.bin"));
@@ -16,7 +16,7 @@ impl Greeter {
 #[tonic::async_trait]
 impl greeter_server::Greeter for Greeter {
     async fn say_hello(&self, request: Request<HelloRequest>) -> Result<Response<HelloReply>, Status> {
-        println!("Got a request from {:?}", request.remote_addr());
+        tracing::info!("got a request from {:?}", request.remote_addr());
 
         let reply = hello::HelloReply {
             message: format!("Hello {}!", request.into_inner().name),
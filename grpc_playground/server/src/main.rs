@@ -1,43 +1,98 @@
-mod hello;
-mod services;
-
-use structopt::StructOpt;
-use tonic::transport::Server;
-use futures::prelude::*;
-
-use hello::Greeter;
-use services::UnaryService;
-use services::ServerStreamingService;
-use services::ClientStreamingService;
-use services::BidirectionalStreamingService;
-
-#[derive(StructOpt)]
-#[structopt(about = "A gRPC test server")]
-struct Cli {
-    #[structopt(short, help = "The port on which the gRPC server will be opened")]
-    port: u32,
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Cli::from_args();
-
-    let addr = format!("127.0.0.1:{}", args.port).parse().unwrap();
-
-    println!("Server listening on {}", addr);
-
-    let shutdown_signal = tokio::signal::ctrl_c().map(|_| ());
-
-    Server::builder()
-        .add_service(Greeter::new())
-        .add_service(UnaryService::new())
-        .add_service(ServerStreamingService::new())
-        .add_service(ClientStreamingService::new())
-        .add_service(BidirectionalStreamingService::new())
-        .serve_with_shutdown(addr, shutdown_signal)
-        .await?;
-
-    println!("Server finished");
-
-    Ok(())
-}
+mod hello;
+mod services;
+
+use structopt::StructOpt;
+use std::time::Duration;
+use server_common::config::{CommonArgs, CommonConfig};
+use server_common::health::{self, ServingStatus};
+use server_common::reflection;
+use server_common::uds::UnixIncoming;
+use proto::hello::greeter_server::GreeterServer;
+use proto::services::unary_service_server::UnaryServiceServer;
+use proto::services::server_streaming_service_server::ServerStreamingServiceServer;
+use proto::services::client_streaming_service_server::ClientStreamingServiceServer;
+use proto::services::bidirectional_streaming_service_server::BidirectionalStreamingServiceServer;
+
+use hello::Greeter;
+use services::UnaryService;
+use services::ServerStreamingService;
+use services::ClientStreamingService;
+use services::BidirectionalStreamingService;
+
+#[derive(StructOpt)]
+#[structopt(about = "A gRPC test server")]
+struct Cli {
+    #[structopt(flatten)]
+    common: CommonArgs,
+
+    #[structopt(
+        long,
+        default_value = "5",
+        help = "Number of seconds the server reports NOT_SERVING before it stops on shutdown"
+    )]
+    drain_delay: u64,
+}
+
+/// Prefix of the environment variables overriding the configuration, e.g. `PLAYGROUND__LISTEN__PORT`.
+const ENV_PREFIX: &str = "PLAYGROUND";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Cli::from_args();
+
+    let mut config: CommonConfig = args.common.load(ENV_PREFIX)?;
+    args.common.apply(&mut config)?;
+
+    config.logging.init()?;
+
+    let (health_reporter, health_service) = health::health_reporter();
+    health_reporter.set_serving::<GreeterServer<Greeter>>();
+    health_reporter.set_serving::<UnaryServiceServer<UnaryService>>();
+    health_reporter.set_serving::<ServerStreamingServiceServer<ServerStreamingService>>();
+    health_reporter.set_serving::<ClientStreamingServiceServer<ClientStreamingService>>();
+    health_reporter.set_serving::<BidirectionalStreamingServiceServer<BidirectionalStreamingService>>();
+
+    let reflection_service = reflection::Builder::new()
+        .register_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    let drain_delay = Duration::from_secs(args.drain_delay);
+    let shutdown_signal = async move {
+        let _ = tokio::signal::ctrl_c().await;
+        tracing::info!("server shutting down");
+
+        // health watchers and load balancers have to see the status before connections are refused
+        health_reporter.set_all(ServingStatus::NotServing);
+        tokio::time::delay_for(drain_delay).await;
+    };
+
+    let router = config
+        .server_builder()
+        .await?
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(Greeter::new())
+        .add_service(UnaryService::new())
+        .add_service(ServerStreamingService::new())
+        .add_service(ClientStreamingService::new())
+        .add_service(BidirectionalStreamingService::new());
+
+    match &config.listen.unix_socket {
+        Some(path) => {
+            let incoming = UnixIncoming::bind(path)?;
+            tracing::info!("server listening on {}", path.display());
+
+            router.serve_with_incoming_shutdown(incoming, shutdown_signal).await?;
+        }
+        None => {
+            let addr = config.listen.socket_addr();
+            tracing::info!("server listening on {}", addr);
+
+            router.serve_with_shutdown(addr, shutdown_signal).await?;
+        }
+    }
+
+    tracing::info!("server finished");
+
+    Ok(())
+}
@@ -24,7 +24,7 @@ impl bidirectional_streaming_service_server::BidirectionalStreamingService for B
         let mut stream = request.into_inner();
 
         while let Some(request) = stream.message().await? {
-            println!("received request");
+            tracing::debug!("received request");
 
             let response = services::BidirectionalStreamingResponse {
                 greeting: format!("Hello {}!", request.name),
@@ -32,7 +32,7 @@ impl bidirectional_streaming_service_server::BidirectionalStreamingService for B
 
             tx.send(Ok(response)).await.unwrap();
 
-            println!(" /// done sending");
+            tracing::debug!("done sending");
         }
 
         Ok(Response::new(rx))
@@ -32,7 +32,7 @@ impl server_streaming_service_server::ServerStreamingService for ServerStreaming
                 tx.send(Ok(response)).await.unwrap();
             }
 
-            println!(" /// done sending");
+            tracing::debug!("done sending");
         });
 
         Ok(Response::new(rx))
@@ -16,7 +16,7 @@ impl UnaryService {
 #[tonic::async_trait]
 impl unary_service_server::UnaryService for UnaryService {
     async fn unary_call(&self, request: Request<UnaryCallRequest>) -> Result<Response<UnaryCallResponse>, Status> {
-        println!("Got a request from {:?}", request.remote_addr());
+        tracing::info!("got a request from {:?}", request.remote_addr());
 
         let reply = services::UnaryCallResponse {
             greeting: format!("Hello {}!", request.into_inner().name),
@@ -0,0 +1,34 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+const PROTOS: &[&str] = &[
+    "proto/grpc/health/v1/health.proto",
+    "proto/grpc/reflection/v1alpha/reflection.proto",
+];
+
+fn main() {
+    tonic_build::configure()
+        .compile(PROTOS, &["proto"])
+        .expect("gRPC protobuf compilation failed");
+
+    // the descriptor set is served by the reflection service
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let descriptor_set = out_dir.join("server_common_descriptor.bin");
+
+    let status = Command::new(prost_build::protoc())
+        .arg("--include_imports")
+        .arg("-I")
+        .arg("proto")
+        .arg("-I")
+        .arg(prost_build::protoc_include())
+        .arg(format!("--descriptor_set_out={}", descriptor_set.display()))
+        .args(PROTOS)
+        .status()
+        .expect("failed to run protoc");
+
+    assert!(
+        status.success(),
+        "protobuf descriptor set generation failed"
+    );
+}
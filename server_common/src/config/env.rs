@@ -0,0 +1,161 @@
+use super::ConfigError;
+use std::env;
+use toml::value::{Table, Value};
+
+/// Separates the prefix, sections and keys in the names of environment variables.
+const SEPARATOR: &str = "__";
+
+/// Applies all environment variables named `<PREFIX>__<SECTION>__<KEY>` to `config`, e.g.
+/// `CHAT__LISTEN__PORT=50002` sets `port` in the `[listen]` section.
+pub fn apply_overrides(config: &mut Table, prefix: &str) -> Result<(), ConfigError> {
+    let vars = env::vars_os().filter_map(|(name, value)| {
+        match (name.into_string(), value.into_string()) {
+            (Ok(name), Ok(value)) => Some((name, value)),
+            _ => None,
+        }
+    });
+
+    apply_vars(config, prefix, vars)
+}
+
+/// Applies the variables of `vars` like `apply_overrides` applies those of the environment.
+fn apply_vars(
+    config: &mut Table,
+    prefix: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<(), ConfigError> {
+    let prefix = format!("{}{}", prefix, SEPARATOR);
+
+    for (name, value) in vars {
+        let path = match name.strip_prefix(&prefix) {
+            Some(path) => path.to_lowercase(),
+            None => continue,
+        };
+
+        let keys: Vec<&str> = path.split(SEPARATOR).collect();
+        if keys.iter().any(|key| key.is_empty()) {
+            return Err(ConfigError::Env(name, String::from("empty section or key")));
+        }
+
+        if let Err(reason) = insert(config, &keys, parse_value(&value)) {
+            return Err(ConfigError::Env(name, reason));
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses `raw` as a TOML value, e.g. a number or boolean, and treats it as a string otherwise.
+fn parse_value(raw: &str) -> Value {
+    match toml::from_str::<Table>(&format!("value = {}", raw)) {
+        Ok(mut table) if table.len() == 1 => match table.remove("value") {
+            Some(value) => value,
+            None => Value::String(String::from(raw)),
+        },
+        _ => Value::String(String::from(raw)),
+    }
+}
+
+fn insert(table: &mut Table, keys: &[&str], value: Value) -> Result<(), String> {
+    let (key, sections) = match keys.split_last() {
+        Some(split) => split,
+        None => return Err(String::from("no key given")),
+    };
+
+    let mut table = table;
+    for section in sections {
+        let entry = table
+            .entry(String::from(*section))
+            .or_insert_with(|| Value::Table(Table::new()));
+
+        table = match entry {
+            Value::Table(table) => table,
+            _ => return Err(format!("'{}' is not a section", section)),
+        };
+    }
+
+    table.insert(String::from(*key), value);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_parsed_as_toml() {
+        assert_eq!(parse_value("50002"), Value::Integer(50002));
+        assert_eq!(parse_value("2.5"), Value::Float(2.5));
+        assert_eq!(parse_value("true"), Value::Boolean(true));
+        assert_eq!(
+            parse_value("\"quoted\""),
+            Value::String(String::from("quoted"))
+        );
+        assert_eq!(parse_value("::1"), Value::String(String::from("::1")));
+        assert_eq!(
+            parse_value("info,h2=warn"),
+            Value::String(String::from("info,h2=warn"))
+        );
+        assert_eq!(
+            parse_value("1\nport = 2"),
+            Value::String(String::from("1\nport = 2"))
+        );
+    }
+
+    #[test]
+    fn values_are_inserted_into_nested_sections() {
+        let mut table = Table::new();
+        insert(&mut table, &["listen", "port"], Value::Integer(1)).unwrap();
+        insert(&mut table, &["listen", "address"], Value::Integer(2)).unwrap();
+        insert(&mut table, &["tls", "client", "ca"], Value::Integer(3)).unwrap();
+
+        let expected: Table = toml::from_str(
+            "
+            [listen]
+            port = 1
+            address = 2
+
+            [tls.client]
+            ca = 3
+            ",
+        )
+        .unwrap();
+        assert_eq!(table, expected);
+
+        let err = insert(&mut table, &["listen", "port", "x"], Value::Integer(4));
+        assert_eq!(err, Err(String::from("'port' is not a section")));
+        assert!(insert(&mut table, &[], Value::Integer(5)).is_err());
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (String::from(*name), String::from(*value)))
+            .collect()
+    }
+
+    #[test]
+    fn variables_with_the_prefix_are_applied() {
+        let mut table: Table = toml::from_str("[listen]\nport = 1\naddress = \"::1\"").unwrap();
+        let applied = apply_vars(
+            &mut table,
+            "TEST",
+            vars(&[
+                ("TEST__LISTEN__PORT", "50002"),
+                ("TEST_LISTEN__ADDRESS", "ignored"),
+                ("OTHER__LISTEN__ADDRESS", "ignored"),
+            ]),
+        );
+
+        assert!(applied.is_ok());
+        let expected: Table = toml::from_str("[listen]\nport = 50002\naddress = \"::1\"").unwrap();
+        assert_eq!(table, expected);
+
+        let empty_key = apply_vars(
+            &mut Table::new(),
+            "TEST",
+            vars(&[("TEST__LISTEN____PORT", "50003")]),
+        );
+        assert!(matches!(empty_key, Err(ConfigError::Env(..))));
+    }
+}
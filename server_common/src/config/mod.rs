@@ -0,0 +1,188 @@
+mod env;
+mod sections;
+
+pub use sections::{CommonConfig, ListenConfig, LoggingConfig, TlsConfig};
+
+use crate::logging::LogFormat;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use toml::value::{Table, Value};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read.
+    Read(PathBuf, io::Error),
+    /// The configuration file is not valid TOML.
+    Parse(PathBuf, toml::de::Error),
+    /// An environment variable could not be applied.
+    Env(String, String),
+    /// The merged configuration does not match the expected structure.
+    Invalid(toml::de::Error),
+    /// The command line arguments are inconsistent.
+    Args(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => {
+                write!(f, "could not read {}: {}", path.display(), err)
+            }
+            ConfigError::Parse(path, err) => {
+                write!(f, "could not parse {}: {}", path.display(), err)
+            }
+            ConfigError::Env(name, reason) => write!(f, "invalid variable {}: {}", name, reason),
+            ConfigError::Invalid(err) => write!(f, "invalid configuration: {}", err),
+            ConfigError::Args(reason) => write!(f, "invalid arguments: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Loads a configuration from the optional TOML `file` and applies the environment variables
+/// starting with `env_prefix` on top of it. Values missing in both fall back to the defaults of `T`.
+pub fn load<T: DeserializeOwned>(file: Option<&Path>, env_prefix: &str) -> Result<T, ConfigError> {
+    let mut config = match file {
+        Some(path) => read_file(path)?,
+        None => Table::new(),
+    };
+
+    env::apply_overrides(&mut config, env_prefix)?;
+
+    match Value::Table(config).try_into() {
+        Ok(config) => Ok(config),
+        Err(err) => Err(ConfigError::Invalid(err)),
+    }
+}
+
+fn read_file(path: &Path) -> Result<Table, ConfigError> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => return Err(ConfigError::Read(path.to_path_buf(), err)),
+    };
+
+    match toml::from_str(&content) {
+        Ok(table) => Ok(table),
+        Err(err) => Err(ConfigError::Parse(path.to_path_buf(), err)),
+    }
+}
+
+// Command line arguments shared by all servers, they take precedence over the configuration file
+// and environment variables. Not a doc comment, structopt would use it as the about text of the
+// servers flattening these arguments.
+#[derive(StructOpt)]
+pub struct CommonArgs {
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        help = "TOML configuration file, values may be overridden by environment variables and arguments"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "The IPv4 or IPv6 address on which the gRPC server will be opened"
+    )]
+    pub address: Option<IpAddr>,
+
+    #[structopt(short, help = "The port on which the gRPC server will be opened")]
+    pub port: Option<u16>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Unix domain socket on which the gRPC server will be opened instead of a TCP port"
+    )]
+    pub unix_socket: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), help = "PEM encoded TLS certificate")]
+    pub tls_cert: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), help = "PEM encoded TLS private key")]
+    pub tls_key: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "PEM encoded CA certificate used to verify client certificates"
+    )]
+    pub tls_client_ca: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Log filter, e.g. 'debug' or 'info,chat_server::user_list=trace' (overrides RUST_LOG)"
+    )]
+    pub log_level: Option<String>,
+
+    #[structopt(long, help = "Log output format, either text or json")]
+    pub log_format: Option<LogFormat>,
+}
+
+impl CommonArgs {
+    /// Loads the configuration file given on the command line and applies the environment
+    /// variables starting with `env_prefix`.
+    pub fn load<T: DeserializeOwned>(&self, env_prefix: &str) -> Result<T, ConfigError> {
+        load(self.config.as_deref(), env_prefix)
+    }
+
+    /// Overrides the shared configuration sections with the given arguments.
+    pub fn apply(&self, config: &mut CommonConfig) -> Result<(), ConfigError> {
+        if let Some(address) = self.address {
+            config.listen.address = address;
+        }
+
+        if let Some(port) = self.port {
+            config.listen.port = port;
+        }
+
+        if let Some(unix_socket) = &self.unix_socket {
+            config.listen.unix_socket = Some(unix_socket.clone());
+        }
+
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => {
+                let client_ca = config.tls.take().and_then(|tls| tls.client_ca);
+
+                config.tls = Some(TlsConfig {
+                    cert: cert.clone(),
+                    key: key.clone(),
+                    client_ca,
+                })
+            }
+            (None, None) => {}
+            _ => {
+                return Err(ConfigError::Args(
+                    "--tls-cert and --tls-key must be given together",
+                ))
+            }
+        }
+
+        if let Some(client_ca) = &self.tls_client_ca {
+            match &mut config.tls {
+                Some(tls) => tls.client_ca = Some(client_ca.clone()),
+                None => {
+                    return Err(ConfigError::Args(
+                        "--tls-client-ca requires TLS to be enabled",
+                    ))
+                }
+            }
+        }
+
+        if let Some(level) = &self.log_level {
+            config.logging.level = Some(level.clone());
+        }
+
+        if let Some(format) = self.log_format {
+            config.logging.format = format;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use crate::logging::{self, LogFormat};
+use serde::Deserialize;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+
+/// Configuration sections shared by all servers.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CommonConfig {
+    pub listen: ListenConfig,
+    pub tls: Option<TlsConfig>,
+    pub logging: LoggingConfig,
+}
+
+impl CommonConfig {
+    /// Creates a server builder, with TLS enabled if it is configured.
+    pub async fn server_builder(&self) -> Result<Server, Box<dyn std::error::Error>> {
+        let builder = Server::builder().tcp_keepalive(self.listen.tcp_keepalive());
+
+        match &self.tls {
+            Some(tls) => Ok(builder.tls_config(tls.server_tls_config().await?)?),
+            None => Ok(builder),
+        }
+    }
+}
+
+/// Address the gRPC server listens on, IPv4 and IPv6 addresses are supported.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    pub address: IpAddr,
+    pub port: u16,
+    /// If set, the server listens on this Unix domain socket instead of `address` and `port`.
+    pub unix_socket: Option<PathBuf>,
+    /// Seconds a TCP connection may be idle before keepalive probes detect a dead peer, 0
+    /// disables the probes.
+    pub tcp_keepalive: u64,
+}
+
+impl ListenConfig {
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+
+    pub fn tcp_keepalive(&self) -> Option<Duration> {
+        match self.tcp_keepalive {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+}
+
+impl Default for ListenConfig {
+    fn default() -> ListenConfig {
+        ListenConfig {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 50001,
+            unix_socket: None,
+            tcp_keepalive: 60,
+        }
+    }
+}
+
+/// PEM encoded server identity. If `client_ca` is set, clients have to present a certificate
+/// signed by it.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub async fn server_tls_config(&self) -> io::Result<ServerTlsConfig> {
+        let cert = fs::read(&self.cert).await?;
+        let key = fs::read(&self.key).await?;
+
+        let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+        if let Some(client_ca) = &self.client_ca {
+            let client_ca = fs::read(client_ca).await?;
+            tls_config = tls_config.client_ca_root(Certificate::from_pem(client_ca));
+        }
+
+        Ok(tls_config)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Filter in the `RUST_LOG` directive syntax, `RUST_LOG` is used if it is not set.
+    pub level: Option<String>,
+    pub format: LogFormat,
+}
+
+impl LoggingConfig {
+    /// Installs the global subscriber.
+    pub fn init(&self) -> Result<(), Box<dyn std::error::Error>> {
+        logging::init(self.format, self.level.as_deref())
+    }
+}
@@ -0,0 +1,77 @@
+use super::{ServingStatus, StatusMap};
+use crate::proto::health::health_server;
+use crate::proto::health::{HealthCheckRequest, HealthCheckResponse};
+use futures::future;
+use futures::stream::{self, Stream, StreamExt};
+use std::pin::Pin;
+use tonic::{Request, Response, Status};
+
+pub struct HealthService {
+    statuses: StatusMap,
+}
+
+impl HealthService {
+    pub(super) fn new(statuses: StatusMap) -> HealthService {
+        HealthService { statuses }
+    }
+}
+
+#[tonic::async_trait]
+impl health_server::Health for HealthService {
+    type WatchStream =
+        Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, Status>> + Send + Sync + 'static>>;
+
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service_name = request.into_inner().service;
+
+        let statuses = self.statuses.read().unwrap();
+        match statuses.get(&service_name) {
+            Some((_, receiver)) => Ok(Response::new(HealthCheckResponse {
+                status: *receiver.borrow() as i32,
+            })),
+            None => Err(Status::not_found(format!(
+                "service '{}' is unknown",
+                service_name
+            ))),
+        }
+    }
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service_name = request.into_inner().service;
+
+        let receiver = {
+            let statuses = self.statuses.read().unwrap();
+            statuses
+                .get(&service_name)
+                .map(|(_, receiver)| receiver.clone())
+        };
+
+        let receiver = match receiver {
+            Some(receiver) => receiver,
+            None => {
+                // services are registered before the server starts, so an unknown service stays
+                // unknown; nothing is stored for it, clients can't grow the map
+                let unknown = Ok(HealthCheckResponse {
+                    status: ServingStatus::ServiceUnknown as i32,
+                });
+                let stream = stream::once(future::ready(unknown)).chain(stream::pending());
+
+                return Ok(Response::new(Box::pin(stream)));
+            }
+        };
+
+        let stream = receiver.map(|status| {
+            Ok(HealthCheckResponse {
+                status: status as i32,
+            })
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
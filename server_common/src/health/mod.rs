@@ -0,0 +1,168 @@
+mod health_service;
+
+use crate::proto::health::health_server::HealthServer;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::watch;
+use tonic::transport::NamedService;
+
+pub use crate::proto::health::health_check_response::ServingStatus;
+pub use health_service::HealthService;
+
+type StatusMap =
+    Arc<RwLock<HashMap<String, (watch::Sender<ServingStatus>, watch::Receiver<ServingStatus>)>>>;
+
+/// Creates the `grpc.health.v1.Health` service together with a reporter to update the
+/// serving status reported by it. The overall server status (empty service name)
+/// starts out as `SERVING`.
+pub fn health_reporter() -> (HealthReporter, HealthServer<HealthService>) {
+    let reporter = HealthReporter {
+        statuses: Arc::new(RwLock::new(HashMap::new())),
+    };
+    reporter.set_service_status("", ServingStatus::Serving);
+
+    let service = HealthService::new(reporter.statuses.clone());
+
+    (reporter, HealthServer::new(service))
+}
+
+/// Updates the serving status of the services known to the health service.
+#[derive(Clone)]
+pub struct HealthReporter {
+    statuses: StatusMap,
+}
+
+impl HealthReporter {
+    pub fn set_serving<S: NamedService>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::Serving);
+    }
+
+    pub fn set_not_serving<S: NamedService>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::NotServing);
+    }
+
+    pub fn set_service_status(&self, service_name: &str, status: ServingStatus) {
+        let mut statuses = self.statuses.write().unwrap();
+
+        match statuses.get(service_name) {
+            Some((sender, _)) => {
+                // there is always a receiver stored next to the sender, so this can't fail
+                let _ = sender.broadcast(status);
+            }
+            None => {
+                statuses.insert(String::from(service_name), watch::channel(status));
+            }
+        }
+    }
+
+    /// Sets the status of the server and of all services registered so far, e.g. to
+    /// report `NOT_SERVING` everywhere while shutting down.
+    pub fn set_all(&self, status: ServingStatus) {
+        let statuses = self.statuses.read().unwrap();
+
+        for (sender, _) in statuses.values() {
+            let _ = sender.broadcast(status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::health::health_server::Health;
+    use crate::proto::health::{HealthCheckRequest, HealthCheckResponse};
+    use futures::stream::{Stream, StreamExt};
+    use std::time::Duration;
+    use tonic::{Code, Request, Status};
+
+    fn service() -> (HealthReporter, HealthService) {
+        let reporter = HealthReporter {
+            statuses: Arc::new(RwLock::new(HashMap::new())),
+        };
+        reporter.set_service_status("", ServingStatus::Serving);
+        let service = HealthService::new(reporter.statuses.clone());
+
+        (reporter, service)
+    }
+
+    fn request(service_name: &str) -> Request<HealthCheckRequest> {
+        Request::new(HealthCheckRequest {
+            service: String::from(service_name),
+        })
+    }
+
+    async fn check(service: &HealthService, service_name: &str) -> Result<ServingStatus, Code> {
+        match service.check(request(service_name)).await {
+            Ok(response) => Ok(status(response.into_inner())),
+            Err(status) => Err(status.code()),
+        }
+    }
+
+    async fn next<S>(stream: &mut S) -> ServingStatus
+    where
+        S: Stream<Item = Result<HealthCheckResponse, Status>> + Unpin,
+    {
+        let response = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .expect("no status received")
+            .expect("stream ended")
+            .expect("stream failed");
+
+        status(response)
+    }
+
+    fn status(response: HealthCheckResponse) -> ServingStatus {
+        ServingStatus::from_i32(response.status).expect("invalid status")
+    }
+
+    #[tokio::test]
+    async fn check_reports_the_current_status() {
+        let (reporter, service) = service();
+
+        assert_eq!(check(&service, "").await, Ok(ServingStatus::Serving));
+        assert_eq!(check(&service, "chat.Chat").await, Err(Code::NotFound));
+
+        reporter.set_service_status("chat.Chat", ServingStatus::Serving);
+        assert_eq!(
+            check(&service, "chat.Chat").await,
+            Ok(ServingStatus::Serving)
+        );
+
+        reporter.set_all(ServingStatus::NotServing);
+        assert_eq!(check(&service, "").await, Ok(ServingStatus::NotServing));
+        assert_eq!(
+            check(&service, "chat.Chat").await,
+            Ok(ServingStatus::NotServing)
+        );
+    }
+
+    #[tokio::test]
+    async fn watch_streams_status_changes() {
+        let (reporter, service) = service();
+        reporter.set_service_status("chat.Chat", ServingStatus::Serving);
+
+        let mut statuses = service
+            .watch(request("chat.Chat"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(next(&mut statuses).await, ServingStatus::Serving);
+
+        reporter.set_service_status("chat.Chat", ServingStatus::NotServing);
+        assert_eq!(next(&mut statuses).await, ServingStatus::NotServing);
+    }
+
+    #[tokio::test]
+    async fn unknown_services_are_watched_without_being_stored() {
+        let (reporter, service) = service();
+
+        let mut statuses = service
+            .watch(request("unknown"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(next(&mut statuses).await, ServingStatus::ServiceUnknown);
+
+        assert_eq!(reporter.statuses.read().unwrap().len(), 1);
+    }
+}
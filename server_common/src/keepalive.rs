@@ -0,0 +1,16 @@
+//! HTTP/2 keepalive of client connections. The servers answer the keepalive pings, a connection
+//! whose server stopped answering is closed after `INTERVAL` + `TIMEOUT`.
+
+use std::time::Duration;
+use tonic::transport::Endpoint;
+
+pub const INTERVAL: Duration = Duration::from_secs(20);
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Enables keepalive pings on the endpoint, also while no request is in flight.
+pub fn configure(endpoint: Endpoint) -> Endpoint {
+    endpoint
+        .http2_keep_alive_interval(INTERVAL)
+        .keep_alive_timeout(TIMEOUT)
+        .keep_alive_while_idle(true)
+}
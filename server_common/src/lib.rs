@@ -0,0 +1,23 @@
+// tonic::Status is large by design and dictated by the generated service traits
+#![allow(clippy::result_large_err)]
+
+pub mod config;
+pub mod health;
+pub mod keepalive;
+pub mod logging;
+pub mod reflection;
+pub mod uds;
+
+pub mod proto {
+    pub mod health {
+        tonic::include_proto!("grpc.health.v1");
+    }
+
+    pub mod reflection {
+        tonic::include_proto!("grpc.reflection.v1alpha");
+    }
+
+    /// Encoded `FileDescriptorSet` of the health and reflection protos.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/server_common_descriptor.bin"));
+}
@@ -0,0 +1,46 @@
+use serde::Deserialize;
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+/// Filter used if neither a configured filter nor `RUST_LOG` are given.
+const DEFAULT_FILTER: &str = "info";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}', use text or json", s)),
+        }
+    }
+}
+
+/// Installs the global subscriber. `filter` uses the `RUST_LOG` directive syntax
+/// (e.g. `info,chat_server::user_list=debug`) and takes precedence over `RUST_LOG`.
+pub fn init(format: LogFormat, filter: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = match filter {
+        Some(filter) => EnvFilter::try_new(filter)?,
+        None => {
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER))
+        }
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
+
+    result.map_err(|err| err as Box<dyn std::error::Error>)
+}
@@ -0,0 +1,117 @@
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use std::collections::HashMap;
+
+/// Lookup tables from file names and fully qualified symbols to file descriptors.
+#[derive(Default)]
+pub struct DescriptorIndex {
+    files: HashMap<String, FileDescriptorProto>,
+    symbols: HashMap<String, String>,
+    services: Vec<String>,
+}
+
+impl DescriptorIndex {
+    pub fn add_file_descriptor_set(&mut self, encoded: &[u8]) -> Result<(), prost::DecodeError> {
+        let file_descriptor_set = FileDescriptorSet::decode(encoded)?;
+
+        for file in file_descriptor_set.file {
+            self.add_file(file);
+        }
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, file: FileDescriptorProto) {
+        let file_name = file.name().to_string();
+        if self.files.contains_key(&file_name) {
+            return;
+        }
+
+        let prefix = match file.package() {
+            "" => String::new(),
+            package => format!("{}.", package),
+        };
+
+        for message in &file.message_type {
+            self.add_message(&prefix, message, &file_name);
+        }
+
+        for enumeration in &file.enum_type {
+            self.add_symbol(format!("{}{}", prefix, enumeration.name()), &file_name);
+        }
+
+        for service in &file.service {
+            let service_name = format!("{}{}", prefix, service.name());
+
+            for method in &service.method {
+                self.add_symbol(format!("{}.{}", service_name, method.name()), &file_name);
+            }
+
+            self.add_symbol(service_name.clone(), &file_name);
+            self.services.push(service_name);
+        }
+
+        self.files.insert(file_name, file);
+    }
+
+    fn add_message(&mut self, prefix: &str, message: &DescriptorProto, file_name: &str) {
+        let message_name = format!("{}{}", prefix, message.name());
+        let nested_prefix = format!("{}.", message_name);
+
+        for nested in &message.nested_type {
+            self.add_message(&nested_prefix, nested, file_name);
+        }
+
+        for enumeration in &message.enum_type {
+            self.add_symbol(
+                format!("{}{}", nested_prefix, enumeration.name()),
+                file_name,
+            );
+        }
+
+        self.add_symbol(message_name, file_name);
+    }
+
+    fn add_symbol(&mut self, symbol: String, file_name: &str) {
+        self.symbols.insert(symbol, String::from(file_name));
+    }
+
+    pub fn services(&self) -> &[String] {
+        &self.services
+    }
+
+    pub fn file_containing_symbol(&self, symbol: &str) -> Option<&str> {
+        self.symbols.get(symbol).map(String::as_str)
+    }
+
+    /// Returns the encoded descriptor of the file followed by those of all its
+    /// transitive dependencies.
+    pub fn encoded_file_with_dependencies(&self, file_name: &str) -> Option<Vec<Vec<u8>>> {
+        if !self.files.contains_key(file_name) {
+            return None;
+        }
+
+        let mut visited = vec![String::from(file_name)];
+        let mut encoded = vec![];
+        let mut index = 0;
+
+        while index < visited.len() {
+            if let Some(file) = self.files.get(&visited[index]) {
+                let mut buffer = Vec::with_capacity(file.encoded_len());
+                file.encode(&mut buffer)
+                    .expect("Vec<u8> provides sufficient capacity");
+                encoded.push(buffer);
+
+                for dependency in &file.dependency {
+                    if !visited.contains(dependency) {
+                        visited.push(dependency.clone());
+                    }
+                }
+            }
+
+            index += 1;
+        }
+
+        Some(encoded)
+    }
+}
@@ -0,0 +1,46 @@
+mod descriptor_index;
+mod reflection_service;
+
+use crate::proto::reflection::server_reflection_server::ServerReflectionServer;
+use descriptor_index::DescriptorIndex;
+use std::sync::Arc;
+
+pub use reflection_service::ReflectionService;
+
+/// Collects the encoded `FileDescriptorSet`s of the services a server exposes and
+/// creates a `grpc.reflection.v1alpha.ServerReflection` service describing them.
+///
+/// The descriptors of the health and reflection services are always included.
+pub struct Builder {
+    file_descriptor_sets: Vec<&'static [u8]>,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            file_descriptor_sets: vec![crate::proto::FILE_DESCRIPTOR_SET],
+        }
+    }
+
+    pub fn register_file_descriptor_set(mut self, file_descriptor_set: &'static [u8]) -> Builder {
+        self.file_descriptor_sets.push(file_descriptor_set);
+        self
+    }
+
+    pub fn build(self) -> Result<ServerReflectionServer<ReflectionService>, prost::DecodeError> {
+        let mut index = DescriptorIndex::default();
+        for file_descriptor_set in self.file_descriptor_sets {
+            index.add_file_descriptor_set(file_descriptor_set)?;
+        }
+
+        Ok(ServerReflectionServer::new(ReflectionService::new(
+            Arc::new(index),
+        )))
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
@@ -0,0 +1,202 @@
+use super::DescriptorIndex;
+use crate::proto::reflection::server_reflection_request::MessageRequest;
+use crate::proto::reflection::server_reflection_response::MessageResponse;
+use crate::proto::reflection::server_reflection_server;
+use crate::proto::reflection::*;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tonic::{Code, Request, Response, Status, Streaming};
+
+pub struct ReflectionService {
+    index: Arc<DescriptorIndex>,
+}
+
+impl ReflectionService {
+    pub(super) fn new(index: Arc<DescriptorIndex>) -> ReflectionService {
+        ReflectionService { index }
+    }
+}
+
+fn respond(index: &DescriptorIndex, request: &ServerReflectionRequest) -> MessageResponse {
+    let message_request = match &request.message_request {
+        Some(message_request) => message_request,
+        None => return error(Code::InvalidArgument, "message_request is missing"),
+    };
+
+    match message_request {
+        MessageRequest::ListServices(_) => {
+            MessageResponse::ListServicesResponse(ListServiceResponse {
+                service: index
+                    .services()
+                    .iter()
+                    .map(|name| ServiceResponse { name: name.clone() })
+                    .collect(),
+            })
+        }
+        MessageRequest::FileByFilename(file_name) => file_response(index, file_name),
+        MessageRequest::FileContainingSymbol(symbol) => {
+            match index.file_containing_symbol(symbol) {
+                Some(file_name) => file_response(index, file_name),
+                None => error(Code::NotFound, &format!("symbol '{}' not found", symbol)),
+            }
+        }
+        MessageRequest::FileContainingExtension(_)
+        | MessageRequest::AllExtensionNumbersOfType(_) => {
+            error(Code::Unimplemented, "extensions are not supported")
+        }
+    }
+}
+
+fn file_response(index: &DescriptorIndex, file_name: &str) -> MessageResponse {
+    match index.encoded_file_with_dependencies(file_name) {
+        Some(file_descriptor_proto) => {
+            MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+                file_descriptor_proto,
+            })
+        }
+        None => error(Code::NotFound, &format!("file '{}' not found", file_name)),
+    }
+}
+
+fn error(code: Code, message: &str) -> MessageResponse {
+    MessageResponse::ErrorResponse(ErrorResponse {
+        error_code: code as i32,
+        error_message: String::from(message),
+    })
+}
+
+#[tonic::async_trait]
+impl server_reflection_server::ServerReflection for ReflectionService {
+    type ServerReflectionInfoStream = mpsc::Receiver<Result<ServerReflectionResponse, Status>>;
+
+    async fn server_reflection_info(
+        &self,
+        request: Request<Streaming<ServerReflectionRequest>>,
+    ) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+        let mut stream = request.into_inner();
+        let (mut tx, rx) = mpsc::channel(4);
+        let index = self.index.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let request = match stream.message().await {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+
+                let response = ServerReflectionResponse {
+                    valid_host: request.host.clone(),
+                    message_response: Some(respond(&index, &request)),
+                    original_request: Some(request),
+                };
+
+                if tx.send(Ok(response)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(rx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+    use prost_types::FileDescriptorProto;
+
+    fn index() -> DescriptorIndex {
+        let mut index = DescriptorIndex::default();
+        index
+            .add_file_descriptor_set(crate::proto::FILE_DESCRIPTOR_SET)
+            .unwrap();
+        index
+    }
+
+    fn request(message_request: MessageRequest) -> ServerReflectionRequest {
+        ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(message_request),
+        }
+    }
+
+    /// Returns the names of the files in a response, the requested file first.
+    fn file_names(response: MessageResponse) -> Vec<String> {
+        match response {
+            MessageResponse::FileDescriptorResponse(response) => response
+                .file_descriptor_proto
+                .iter()
+                .map(|file| {
+                    let file = FileDescriptorProto::decode(file.as_slice()).unwrap();
+                    String::from(file.name())
+                })
+                .collect(),
+            response => panic!("expected files, got {:?}", response),
+        }
+    }
+
+    fn error_code(response: MessageResponse) -> Code {
+        match response {
+            MessageResponse::ErrorResponse(error) => Code::from_i32(error.error_code),
+            response => panic!("expected an error, got {:?}", response),
+        }
+    }
+
+    #[test]
+    fn files_are_found_by_name_and_symbol() {
+        let index = index();
+        let health = "grpc/health/v1/health.proto";
+
+        let by_name = respond(
+            &index,
+            &request(MessageRequest::FileByFilename(health.into())),
+        );
+        assert_eq!(file_names(by_name), vec![health]);
+
+        for symbol in &[
+            "grpc.health.v1.Health",
+            "grpc.health.v1.Health.Watch",
+            "grpc.health.v1.HealthCheckResponse.ServingStatus",
+        ] {
+            let response = respond(
+                &index,
+                &request(MessageRequest::FileContainingSymbol(symbol.to_string())),
+            );
+            assert_eq!(file_names(response), vec![health], "{}", symbol);
+        }
+
+        let unknown = respond(
+            &index,
+            &request(MessageRequest::FileByFilename("unknown.proto".into())),
+        );
+        assert_eq!(error_code(unknown), Code::NotFound);
+
+        let unknown = respond(
+            &index,
+            &request(MessageRequest::FileContainingSymbol("grpc.Unknown".into())),
+        );
+        assert_eq!(error_code(unknown), Code::NotFound);
+    }
+
+    #[test]
+    fn services_are_listed() {
+        let response = respond(
+            &index(),
+            &request(MessageRequest::ListServices(String::new())),
+        );
+
+        match response {
+            MessageResponse::ListServicesResponse(response) => {
+                let names: Vec<&str> = response.service.iter().map(|v| v.name.as_str()).collect();
+                assert!(names.contains(&"grpc.health.v1.Health"));
+                assert!(names.contains(&"grpc.reflection.v1alpha.ServerReflection"));
+            }
+            response => panic!("expected services, got {:?}", response),
+        }
+    }
+}